@@ -0,0 +1,5 @@
+fn main() {
+    // `--cfg=ci` is set by `run_ci_tests.sh` and the CI to run the integration tests.
+    println!("cargo:rustc-check-cfg=cfg(ci)");
+    println!("cargo:rerun-if-changed=build.rs");
+}
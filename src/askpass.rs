@@ -0,0 +1,326 @@
+//! Drive password, passphrase and keyboard-interactive prompts of the
+//! ssh master through `SSH_ASKPASS`.
+//!
+//! When an askpass callback is registered on the [`SessionBuilder`], the
+//! master is started with `SSH_ASKPASS` pointing to a small helper script
+//! inside of the control directory, and `SSH_ASKPASS_REQUIRE=force`.
+//!
+//! Every time ssh needs user input, it runs the helper, which forwards the
+//! prompt to this process through a private fifo and then waits for the
+//! answer on a second fifo.
+//!
+//! [`SessionBuilder`]: crate::SessionBuilder
+
+use super::Error;
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::pipe::{Receiver as PipeReader, Sender as PipeWriter};
+
+use crate::escape::escape;
+
+/// The kind of input ssh asks for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum PromptKind {
+    /// ssh needs a secret, e.g. a password, a one-time password or the
+    /// passphrase of a private key.
+    Secret,
+
+    /// ssh asks for a confirmation, e.g. whether an unknown host key should
+    /// be accepted.
+    ///
+    /// Answering with `Some(_)` that is empty or `yes` confirms, while
+    /// answering `None` rejects.
+    Confirm,
+
+    /// ssh only displays a notice and does not use the answer.
+    Notice,
+}
+
+impl PromptKind {
+    fn from_env(value: &str) -> Self {
+        // This mirrors the values of `SSH_ASKPASS_PROMPT` set by ssh.
+        match value {
+            "confirm" => PromptKind::Confirm,
+            "none" => PromptKind::Notice,
+            _ => PromptKind::Secret,
+        }
+    }
+}
+
+/// A prompt issued by ssh while establishing the master connection.
+#[derive(Clone, Debug)]
+pub struct Prompt {
+    message: String,
+    kind: PromptKind,
+}
+
+impl Prompt {
+    /// The message that ssh displays to the user, e.g.
+    /// `user@host's password: ` or `Verification code: `.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// What kind of answer ssh expects.
+    pub fn kind(&self) -> PromptKind {
+        self.kind
+    }
+}
+
+/// A string holding a secret, e.g. a password.
+///
+/// Its `Debug` implementation does not reveal the content and the
+/// memory is zeroed on drop.
+#[derive(Clone, Default)]
+pub struct SecretString(String);
+
+impl SecretString {
+    /// Create a new `SecretString`.
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    /// Access the secret.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self::new(secret.to_owned())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString([REDACTED])")
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        // Safety: zero bytes are valid utf-8.
+        for byte in unsafe { self.0.as_bytes_mut() } {
+            // Use a volatile write so that it does not get optimized out.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+pub(crate) type AskPassFuture = Pin<Box<dyn Future<Output = Option<SecretString>> + Send>>;
+
+/// Type-erased askpass callback stored in [`SessionBuilder`](crate::SessionBuilder).
+#[derive(Clone)]
+pub(crate) struct AskPass(Arc<dyn Fn(Prompt) -> AskPassFuture + Send + Sync>);
+
+impl AskPass {
+    pub(crate) fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn(Prompt) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<SecretString>> + Send + 'static,
+    {
+        Self(Arc::new(move |prompt| Box::pin(f(prompt))))
+    }
+}
+
+impl fmt::Debug for AskPass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AskPass")
+    }
+}
+
+fn mkfifo(path: &Path) -> io::Result<()> {
+    let mut bytes = path.as_os_str().as_bytes().to_vec();
+    bytes.push(0);
+
+    let ret = unsafe { libc::mkfifo(bytes.as_ptr().cast(), 0o600) };
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Open the fifo in read-write mode, so that opening never blocks and
+/// the fifo never reports EOF to us while the helper is not running.
+fn open_fifo(path: &Path) -> io::Result<fs::File> {
+    OpenOptions::new().read(true).write(true).open(path)
+}
+
+/// Helper serving the prompts of one `ssh` invocation.
+#[derive(Debug)]
+pub(crate) struct Helper {
+    askpass: AskPass,
+    script: PathBuf,
+    prompts: BufReader<PipeReader>,
+    replies: PipeWriter,
+}
+
+impl Helper {
+    /// Create the fifos and the helper script in `dir`.
+    pub(crate) fn new(dir: &Path, askpass: AskPass) -> io::Result<Self> {
+        let prompt_fifo = dir.join("askpass-prompt");
+        let reply_fifo = dir.join("askpass-reply");
+        let script = dir.join("askpass");
+
+        mkfifo(&prompt_fifo)?;
+        mkfifo(&reply_fifo)?;
+
+        let prompts = PipeReader::from_file(open_fifo(&prompt_fifo)?)?;
+        let replies = PipeWriter::from_file(open_fifo(&reply_fifo)?)?;
+
+        // The prompt is terminated with a nul byte since it can contain
+        // newlines, while the reply consists of a status line followed by
+        // the secret on a single line.
+        let mut content = b"#!/bin/sh\n".to_vec();
+        content.extend_from_slice(br#"printf '%s\n%s\000' "${SSH_ASKPASS_PROMPT:-}" "$1" > "#);
+        content.extend_from_slice(escape(prompt_fifo.as_os_str()).as_bytes());
+        content.extend_from_slice(b" || exit 1\n");
+        content.extend_from_slice(b"{ IFS= read -r status && IFS= read -r reply; } < ");
+        content.extend_from_slice(escape(reply_fifo.as_os_str()).as_bytes());
+        content.extend_from_slice(b" || exit 1\n");
+        content.extend_from_slice(b"[ \"$status\" = ok ] || exit 1\n");
+        content.extend_from_slice(b"printf '%s\\n' \"$reply\"\n");
+
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o700)
+            .open(&script)
+            .and_then(|mut file| io::Write::write_all(&mut file, &content))?;
+
+        Ok(Self {
+            askpass,
+            script,
+            prompts: BufReader::new(prompts),
+            replies,
+        })
+    }
+
+    /// Path to the script that should be used as `SSH_ASKPASS`.
+    pub(crate) fn script(&self) -> &OsStr {
+        self.script.as_os_str()
+    }
+
+    /// Answer prompts until `until` resolves.
+    // `select!` works on our MSRV, but clippy attributes its expansion to Rust 1.64.
+    #[allow(clippy::incompatible_msrv)]
+    pub(crate) async fn serve<F: Future>(&mut self, until: F) -> Result<F::Output, Error> {
+        tokio::pin!(until);
+
+        let mut buffer = Vec::new();
+
+        loop {
+            tokio::select! {
+                output = &mut until => break Ok(output),
+                res = self.prompts.read_until(b'\0', &mut buffer) => {
+                    res.map_err(Error::Connect)?;
+
+                    let prompt = parse_prompt(&buffer);
+                    buffer.clear();
+
+                    let reply = (self.askpass.0)(prompt).await;
+                    self.reply(reply).await.map_err(Error::Connect)?;
+                }
+            }
+        }
+    }
+
+    async fn reply(&mut self, reply: Option<SecretString>) -> io::Result<()> {
+        let mut message = Vec::new();
+
+        match reply {
+            // ssh reads the output of the helper line by line, so the secret
+            // cannot contain a newline.
+            Some(secret) if !secret.expose_secret().contains('\n') => {
+                message.extend_from_slice(b"ok\n");
+                message.extend_from_slice(secret.expose_secret().as_bytes());
+                message.push(b'\n');
+            }
+            _ => message.extend_from_slice(b"cancel\n\n"),
+        }
+
+        let res = self.replies.write_all(&message).await;
+        message.iter_mut().for_each(|byte| *byte = 0);
+        res
+    }
+}
+
+fn parse_prompt(buffer: &[u8]) -> Prompt {
+    let buffer = buffer.strip_suffix(b"\0").unwrap_or(buffer);
+    let buffer = String::from_utf8_lossy(buffer);
+
+    let (kind, message) = buffer.split_once('\n').unwrap_or(("", &buffer));
+
+    Prompt {
+        message: message.to_owned(),
+        kind: PromptKind::from_env(kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_parsing() {
+        let prompt = parse_prompt(b"\nuser@host's password: \0");
+        assert_eq!(prompt.kind(), PromptKind::Secret);
+        assert_eq!(prompt.message(), "user@host's password: ");
+
+        let prompt = parse_prompt(b"confirm\nAre you sure?\nyes/no\0");
+        assert_eq!(prompt.kind(), PromptKind::Confirm);
+        assert_eq!(prompt.message(), "Are you sure?\nyes/no");
+
+        let prompt = parse_prompt(b"none\nNotice");
+        assert_eq!(prompt.kind(), PromptKind::Notice);
+        assert_eq!(prompt.message(), "Notice");
+    }
+
+    #[test]
+    fn secret_string_is_redacted() {
+        let secret = SecretString::from("hunter2");
+        assert_eq!(secret.expose_secret(), "hunter2");
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn helper_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let askpass = AskPass::new(|prompt: Prompt| async move {
+            assert_eq!(prompt.kind(), PromptKind::Secret);
+            Some(SecretString::from(format!(
+                "answer to {}",
+                prompt.message()
+            )))
+        });
+        let mut helper = Helper::new(dir.path(), askpass).unwrap();
+
+        let child = tokio::process::Command::new(helper.script())
+            .arg("Password: ")
+            .output();
+        let output = helper.serve(child).await.unwrap().unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"answer to Password: \n");
+    }
+}
@@ -1,12 +1,18 @@
-use super::{Error, Session};
+use super::askpass::{self, AskPass, Prompt, SecretString};
+use super::host_key::{self, HostKey};
+use super::session::ChannelLimit;
+use super::ssh_config::{self, ConfigFile};
+use super::{Error, Session, SshConfig};
 
 use std::borrow::Cow;
 use std::ffi::OsString;
+use std::future::Future;
 use std::iter::IntoIterator;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::str;
+use std::time::Duration;
 use std::{fs, io};
 
 use once_cell::sync::OnceCell;
@@ -93,6 +99,11 @@ pub struct SessionBuilder {
     jump_hosts: Vec<Box<str>>,
     user_known_hosts_file: Option<Box<Path>>,
     ssh_auth_sock: Option<Box<Path>>,
+    askpass: Option<AskPass>,
+    max_sessions: Option<usize>,
+    max_sessions_timeout: Option<Duration>,
+    algorithms: Vec<(AlgorithmOption, AlgorithmList)>,
+    options: Vec<(String, String)>,
 }
 
 impl Default for SessionBuilder {
@@ -112,6 +123,11 @@ impl Default for SessionBuilder {
             jump_hosts: Vec::new(),
             user_known_hosts_file: None,
             ssh_auth_sock: None,
+            askpass: None,
+            max_sessions: None,
+            max_sessions_timeout: None,
+            algorithms: Vec::new(),
+            options: Vec::new(),
         }
     }
 }
@@ -168,6 +184,16 @@ impl SessionBuilder {
         self
     }
 
+    /// Lower the connection timeout to `d`, rounded up to whole seconds, unless it is lower
+    /// already.
+    pub(crate) fn limit_connect_timeout(&mut self, d: std::time::Duration) {
+        let secs = d.as_secs() + u64::from(d.subsec_nanos() > 0);
+        let current = self.connect_timeout.as_ref().and_then(|t| t.parse().ok());
+        if current.map_or(true, |current: u64| secs < current) {
+            self.connect_timeout = Some(secs.to_string());
+        }
+    }
+
     /// Set the timeout interval after which if no data has been received from the server, ssh
     /// will request a response from the server (`ssh -o ServerAliveInterval`).
     ///
@@ -284,6 +310,229 @@ impl SessionBuilder {
         self
     }
 
+    /// Answer the prompts of ssh while connecting, such as passwords, one-time
+    /// passwords of keyboard-interactive authentication or passphrases of
+    /// private keys.
+    ///
+    /// `askpass` is called for every prompt. Returning `None` cancels
+    /// the prompt, which usually makes the authentication method fail.
+    ///
+    /// Internally, this sets `SSH_ASKPASS` to a helper script in the control
+    /// directory together with `SSH_ASKPASS_REQUIRE=force`, and no longer
+    /// passes `BatchMode=yes` to the ssh master.
+    ///
+    /// Note that the secret returned must not contain a newline.
+    ///
+    /// Defaults to `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "process-mux")]
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::{PromptKind, SecretString, SessionBuilder};
+    ///
+    /// let session = SessionBuilder::default()
+    ///     .askpass(|prompt| async move {
+    ///         match prompt.kind() {
+    ///             PromptKind::Secret => Some(SecretString::from("hunter2")),
+    ///             _ => None,
+    ///         }
+    ///     })
+    ///     .connect("me@ssh.example.com")
+    ///     .await?;
+    /// # Ok(()) }
+    /// ```
+    pub fn askpass<F, Fut>(&mut self, askpass: F) -> &mut Self
+    where
+        F: Fn(Prompt) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<SecretString>> + Send + 'static,
+    {
+        self.askpass = Some(AskPass::new(askpass));
+        self
+    }
+
+    /// Limit the number of remote commands that can run over the connection at the same time.
+    ///
+    /// Every remote command, subsystem and sftp session occupies one channel of the connection
+    /// until its [`Child`](crate::Child) is dropped or waited for, and sshd refuses to open more
+    /// channels than its `MaxSessions` setting allows, which is 10 by default. With a limit in
+    /// place, spawning a remote command waits for a free channel instead, see
+    /// [`max_sessions_timeout`](SessionBuilder::max_sessions_timeout). Delivering a signal with
+    /// [`Child::signal`](crate::Child::signal) briefly opens one more channel, which is not
+    /// counted against the limit, so that the process can be signalled while it holds the
+    /// last free channel.
+    ///
+    /// The limit only applies to sessions created with `connect` and `connect_mux`, not to
+    /// sessions created from [`launch_master`](SessionBuilder::launch_master) with
+    /// `Session::new_process_mux` or `Session::new_native_mux`.
+    ///
+    /// Defaults to `None`, i.e. no limit.
+    ///
+    /// # Panics
+    ///
+    /// If `max` is `0`.
+    pub fn max_sessions(&mut self, max: usize) -> &mut Self {
+        assert_ne!(max, 0, "max_sessions must not be 0");
+        self.max_sessions = Some(max);
+        self
+    }
+
+    /// Set how long spawning a remote command waits for a free channel once the
+    /// [`max_sessions`](SessionBuilder::max_sessions) limit is reached, before failing with
+    /// [`Error::TooManySessions`].
+    ///
+    /// Defaults to `None`, i.e. waiting forever.
+    pub fn max_sessions_timeout(&mut self, d: Duration) -> &mut Self {
+        self.max_sessions_timeout = Some(d);
+        self
+    }
+
+    fn set_algorithms(&mut self, option: AlgorithmOption, list: AlgorithmList) -> &mut Self {
+        self.algorithms.retain(|(o, _)| *o != option);
+        self.algorithms.push((option, list));
+        self
+    }
+
+    /// Set the ciphers allowed for the connection (`ssh -o Ciphers`).
+    ///
+    /// The algorithms are checked against `ssh -Q cipher` before connecting, and
+    /// [`Error::UnsupportedAlgorithm`] is returned for an unknown one.
+    ///
+    /// By default, ssh uses the value set in `~/.ssh/config` or its built-in default.
+    pub fn ciphers(&mut self, list: AlgorithmList) -> &mut Self {
+        self.set_algorithms(AlgorithmOption::Ciphers, list)
+    }
+
+    /// Set the key exchange algorithms allowed for the connection (`ssh -o KexAlgorithms`).
+    ///
+    /// The algorithms are checked against `ssh -Q kex`, see [`ciphers`](SessionBuilder::ciphers).
+    pub fn kex_algorithms(&mut self, list: AlgorithmList) -> &mut Self {
+        self.set_algorithms(AlgorithmOption::KexAlgorithms, list)
+    }
+
+    /// Set the message authentication codes allowed for the connection (`ssh -o MACs`).
+    ///
+    /// The algorithms are checked against `ssh -Q mac`, see [`ciphers`](SessionBuilder::ciphers).
+    pub fn macs(&mut self, list: AlgorithmList) -> &mut Self {
+        self.set_algorithms(AlgorithmOption::Macs, list)
+    }
+
+    /// Set the host key signature algorithms the client accepts from the server
+    /// (`ssh -o HostKeyAlgorithms`).
+    ///
+    /// The algorithms are checked against `ssh -Q key-sig`, see
+    /// [`ciphers`](SessionBuilder::ciphers).
+    pub fn host_key_algorithms(&mut self, list: AlgorithmList) -> &mut Self {
+        self.set_algorithms(AlgorithmOption::HostKeyAlgorithms, list)
+    }
+
+    /// Set the signature algorithms used for public key authentication
+    /// (`ssh -o PubkeyAcceptedAlgorithms`).
+    ///
+    /// The algorithms are checked against `ssh -Q key-sig`, see
+    /// [`ciphers`](SessionBuilder::ciphers).
+    pub fn pubkey_accepted_algorithms(&mut self, list: AlgorithmList) -> &mut Self {
+        self.set_algorithms(AlgorithmOption::PubkeyAcceptedAlgorithms, list)
+    }
+
+    /// Check the algorithms set with [`ciphers`](SessionBuilder::ciphers) and friends against
+    /// the ones the local `ssh` supports.
+    async fn check_algorithms(&self) -> Result<(), Error> {
+        for (option, list) in &self.algorithms {
+            let supported = match option.supported().await? {
+                Some(supported) => supported,
+                // Too old to be queried, so leave it to ssh to complain.
+                None => continue,
+            };
+
+            if let Some(algorithm) = list
+                .algorithms()
+                // Only algorithms to remove may contain wildcards.
+                .filter(|algorithm| !algorithm.contains(['*', '?']))
+                .find(|algorithm| !supported.iter().any(|s| s == algorithm))
+            {
+                return Err(Error::UnsupportedAlgorithm {
+                    option: option.name(),
+                    algorithm: algorithm.to_owned(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass an arbitrary option to the ssh master (`ssh -o keyword=value`), for settings that
+    /// have no dedicated method.
+    ///
+    /// Options are passed in the order they were added. Since ssh uses the first value it
+    /// obtains for an option, the options set with dedicated methods of [`SessionBuilder`] take
+    /// precedence, while these take precedence over the `config_file`.
+    ///
+    /// Before connecting, [`Error::InvalidOption`] is returned if `keyword` is one of the
+    /// options this crate relies on (`ControlPath`, `ControlMaster`, `ControlPersist`,
+    /// `BatchMode` and `StrictHostKeyChecking`), or if `ssh -G` rejects the option.
+    pub fn option(&mut self, keyword: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.options.push((keyword.into(), value.into()));
+        self
+    }
+
+    /// Pass multiple options to the ssh master, see [`option`](SessionBuilder::option).
+    pub fn options<K, V>(&mut self, options: impl IntoIterator<Item = (K, V)>) -> &mut Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        for (keyword, value) in options {
+            self.option(keyword, value);
+        }
+        self
+    }
+
+    /// Check the options set with [`option`](SessionBuilder::option).
+    async fn check_options(&self) -> Result<(), Error> {
+        /// Options controlled by this crate, see `launch_master`.
+        const RESERVED: &[&str] = &[
+            "ControlPath",
+            "ControlMaster",
+            "ControlPersist",
+            "BatchMode",
+            "StrictHostKeyChecking",
+        ];
+
+        if let Some((keyword, _)) = self.options.iter().find(|(keyword, _)| {
+            RESERVED
+                .iter()
+                .any(|reserved| reserved.eq_ignore_ascii_case(keyword))
+        }) {
+            return Err(Error::InvalidOption {
+                keyword: keyword.clone(),
+                reason: "the option is controlled by openssh".to_owned(),
+            });
+        }
+
+        if self.options.is_empty() || check_options(&self.options).await?.is_none() {
+            return Ok(());
+        }
+
+        // Find the option ssh complained about.
+        for option in &self.options {
+            if let Some(reason) = check_options(std::slice::from_ref(option)).await? {
+                return Err(Error::InvalidOption {
+                    keyword: option.0.clone(),
+                    reason,
+                });
+            }
+        }
+
+        // Only the combination of options is rejected.
+        let (keyword, _) = &self.options[0];
+        Err(Error::InvalidOption {
+            keyword: keyword.clone(),
+            reason: check_options(&self.options).await?.unwrap_or_default(),
+        })
+    }
+
     /// Connect to the host at the given `host` over SSH using process impl, which will
     /// spawn a new ssh process for each `Child` created.
     ///
@@ -293,8 +542,8 @@ impl SessionBuilder {
     /// builder (but does not change the builder).
     ///
     /// If connecting requires interactive authentication based on `STDIN` (such as reading a
-    /// password), the connection will fail. Consider setting up keypair-based authentication
-    /// instead.
+    /// password), the connection will fail unless [`SessionBuilder::askpass`] is set.
+    /// Consider setting up keypair-based authentication instead.
     #[cfg(feature = "process-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
     pub async fn connect<S: AsRef<str>>(&self, destination: S) -> Result<Session, Error> {
@@ -313,8 +562,8 @@ impl SessionBuilder {
     /// builder (but does not change the builder).
     ///
     /// If connecting requires interactive authentication based on `STDIN` (such as reading a
-    /// password), the connection will fail. Consider setting up keypair-based authentication
-    /// instead.
+    /// password), the connection will fail unless [`SessionBuilder::askpass`] is set.
+    /// Consider setting up keypair-based authentication instead.
     #[cfg(feature = "native-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
     pub async fn connect_mux<S: AsRef<str>>(&self, destination: S) -> Result<Session, Error> {
@@ -322,14 +571,20 @@ impl SessionBuilder {
             .await
     }
 
-    async fn connect_impl(
+    pub(crate) async fn connect_impl(
         &self,
         destination: &str,
         f: fn(TempDir) -> Session,
     ) -> Result<Session, Error> {
         let (builder, destination) = self.resolve(destination);
         let tempdir = builder.launch_master(destination).await?;
-        Ok(f(tempdir))
+
+        let mut session = f(tempdir);
+        session.set_channel_limit(
+            self.max_sessions
+                .map(|max| ChannelLimit::new(max, self.max_sessions_timeout)),
+        );
+        Ok(session)
     }
 
     /// [`SessionBuilder`] support for `destination` parsing.
@@ -383,9 +638,152 @@ impl SessionBuilder {
         (Cow::Owned(with_overrides), destination)
     }
 
+    /// Resolve the configuration ssh would use to connect to `destination`, after applying
+    /// `~/.ssh/config`, the [`config_file`](SessionBuilder::config_file) and the options of
+    /// the builder, by running `ssh -G` with the arguments
+    /// [`launch_master`](SessionBuilder::launch_master) would pass.
+    ///
+    /// `destination` is resolved like in [`connect`](SessionBuilder::connect), and the
+    /// algorithms and [options](SessionBuilder::option) are checked the same way, but no
+    /// connection is made.
+    ///
+    /// ```rust,no_run
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::SessionBuilder;
+    ///
+    /// let config = SessionBuilder::default()
+    ///     .effective_config("me@ssh.example.com")
+    ///     .await?;
+    /// println!("connecting to {:?} port {:?}", config.host_name, config.port);
+    /// # Ok(()) }
+    /// ```
+    pub async fn effective_config(&self, destination: &str) -> Result<SshConfig, Error> {
+        let (builder, destination) = self.resolve(destination);
+        builder.check_algorithms().await?;
+        builder.check_options().await?;
+
+        let mut cmd = process::Command::new("ssh");
+        cmd.stdin(Stdio::null())
+            .arg("-G")
+            .arg("-o")
+            .arg(builder.control_persist.as_option().deref());
+        if builder.askpass.is_none() {
+            cmd.arg("-o").arg("BatchMode=yes");
+        }
+        builder.apply_options(&mut cmd);
+
+        let output = cmd.arg(destination).output().await.map_err(Error::Ssh)?;
+
+        if output.status.success() {
+            Ok(SshConfig::from_effective(&String::from_utf8_lossy(
+                &output.stdout,
+            )))
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(Error::Ssh(io::Error::new(
+                io::ErrorKind::Other,
+                stderr.trim(),
+            )))
+        }
+    }
+
+    /// Generate an OpenSSH client configuration file with a `Host` block for `destination`
+    /// that holds the options of this builder, for use with `ssh` or other tools.
+    ///
+    /// `destination` is resolved like in [`connect`](SessionBuilder::connect). The
+    /// [`config_file`](SessionBuilder::config_file) is included from the block, but unlike
+    /// with this builder, ssh still reads `~/.ssh/config` as well. The options this crate
+    /// uses to control the master, such as `ControlPath` and `BatchMode`, are left out, as
+    /// are the keys of [`KnownHosts::Pinned`].
+    ///
+    /// ```rust
+    /// use openssh::{KnownHosts, SessionBuilder};
+    ///
+    /// let mut builder = SessionBuilder::default();
+    /// builder.known_hosts_check(KnownHosts::Strict).port(2222);
+    ///
+    /// let config = builder.to_config_file("me@ssh.example.com").to_string();
+    /// assert_eq!(
+    ///     config,
+    ///     "Host ssh.example.com\n    StrictHostKeyChecking yes\n    Port 2222\n    User me\n",
+    /// );
+    /// ```
+    pub fn to_config_file(&self, destination: &str) -> ConfigFile {
+        let (builder, destination) = self.resolve(destination);
+        // Like `ssh -l`, the user set on the builder takes precedence over `user@host`.
+        let (user, host) = match destination.rsplit_once('@') {
+            Some((user, host)) => (builder.user.as_deref().or(Some(user)), host),
+            None => (builder.user.as_deref(), destination),
+        };
+        let quote = |path: &Path| ssh_config::quote(&path.to_string_lossy()).into_owned();
+
+        let mut options = Vec::new();
+        let (keyword, value) = builder
+            .known_hosts_check
+            .as_option()
+            .split_once('=')
+            .expect("the option has a value");
+        options.push((keyword, value.to_owned()));
+
+        if let Some(ref timeout) = builder.connect_timeout {
+            options.push(("ConnectTimeout", timeout.clone()));
+        }
+
+        if let Some(interval) = builder.server_alive_interval {
+            options.push(("ServerAliveInterval", interval.to_string()));
+        }
+
+        if let Some(ref port) = builder.port {
+            options.push(("Port", port.clone()));
+        }
+
+        if let Some(user) = user {
+            options.push(("User", ssh_config::quote(user).into_owned()));
+        }
+
+        if let Some(ref k) = builder.keyfile {
+            options.push(("IdentitiesOnly", "yes".to_owned()));
+            options.push(("IdentityFile", quote(k)));
+        }
+
+        if let Some(compression) = builder.compression {
+            let value = if compression { "yes" } else { "no" };
+            options.push(("Compression", value.to_owned()));
+        }
+
+        for (option, list) in &builder.algorithms {
+            options.push((option.name(), list.as_value()));
+        }
+
+        if let Some(ssh_auth_sock) = builder.ssh_auth_sock.as_deref() {
+            options.push(("IdentityAgent", quote(ssh_auth_sock)));
+        }
+
+        if !builder.jump_hosts.is_empty() {
+            options.push(("ProxyJump", builder.jump_hosts.join(",")));
+        }
+
+        if let Some(user_known_hosts_file) = &builder.user_known_hosts_file {
+            options.push(("UserKnownHostsFile", quote(user_known_hosts_file)));
+        }
+
+        let mut file = ConfigFile::from_host(host, options);
+        for (keyword, value) in &builder.options {
+            file.push_option(keyword, value);
+        }
+        if let Some(ref config_file) = builder.config_file {
+            file.push_include(&quote(config_file));
+        }
+        file
+    }
+
     /// Create ssh master session and return [`TempDir`] which
     /// contains the ssh control socket.
     pub async fn launch_master(&self, destination: &str) -> Result<TempDir, Error> {
+        self.check_algorithms().await?;
+        self.check_options().await?;
+
         let socketdir = if let Some(socketdir) = self.control_dir.as_ref() {
             socketdir
         } else {
@@ -407,9 +805,20 @@ impl SessionBuilder {
 
         let mut init = process::Command::new("ssh");
 
-        init.stdin(Stdio::null())
+        // Errors of `ProxyCommand` and `ProxyJump` are printed to stderr instead
+        // of the log file specified by `-E`, so collect them in the log as well.
+        let stderr = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log)
+            .map_err(Error::Connect)?;
+
+        // If the future is dropped, e.g. by a timeout, ssh must not keep connecting
+        // in the background, since the control directory is removed underneath it.
+        init.kill_on_drop(true)
+            .stdin(Stdio::null())
             .stdout(Stdio::null())
-            .stderr(Stdio::null())
+            .stderr(stderr)
             .arg("-E")
             .arg(&log)
             .arg("-S")
@@ -418,47 +827,104 @@ impl SessionBuilder {
             .arg("-f")
             .arg("-N")
             .arg("-o")
-            .arg(self.control_persist.as_option().deref())
-            .arg("-o")
-            .arg("BatchMode=yes")
-            .arg("-o")
-            .arg(self.known_hosts_check.as_option());
+            .arg(self.control_persist.as_option().deref());
+
+        let mut askpass_helper = if let Some(askpass) = self.askpass.clone() {
+            let helper = askpass::Helper::new(dir.path(), askpass).map_err(Error::Connect)?;
+
+            init.env("SSH_ASKPASS", helper.script())
+                .env("SSH_ASKPASS_REQUIRE", "force");
+
+            Some(helper)
+        } else {
+            init.arg("-o").arg("BatchMode=yes");
+
+            None
+        };
+
+        // Pass the pinned keys first, since ssh uses the first value it obtains.
+        if let KnownHosts::Pinned(keys) = &self.known_hosts_check {
+            let known_hosts = dir.path().join("known_hosts");
+            host_key::write_known_hosts(&known_hosts, keys, self, destination).await?;
+
+            let mut option: OsString = "UserKnownHostsFile=".into();
+            option.push(&known_hosts);
+            init.arg("-o")
+                .arg(option)
+                .arg("-o")
+                .arg("GlobalKnownHostsFile=none");
+        }
+
+        self.apply_options(&mut init);
+
+        init.arg(destination);
+
+        // we spawn and immediately wait, because the process is supposed to fork.
+        let status = if let Some(helper) = askpass_helper.as_mut() {
+            // ssh only forks after authentication, so the prompts have to be
+            // answered while waiting.
+            let mut child = init.spawn().map_err(Error::Connect)?;
+            helper.serve(child.wait()).await?
+        } else {
+            init.status().await
+        }
+        .map_err(Error::Connect)?;
+
+        if !status.success() {
+            let output = fs::read_to_string(log).map_err(Error::Connect)?;
+
+            Err(Error::interpret_ssh_error(&output, &self.jump_hosts))
+        } else {
+            Ok(dir)
+        }
+    }
+
+    /// Pass the options of the builder to the `ssh` command `cmd`, see
+    /// [`launch_master`](SessionBuilder::launch_master) and
+    /// [`effective_config`](SessionBuilder::effective_config).
+    fn apply_options(&self, cmd: &mut process::Command) {
+        cmd.arg("-o").arg(self.known_hosts_check.as_option());
 
         if let Some(ref timeout) = self.connect_timeout {
-            init.arg("-o").arg(format!("ConnectTimeout={}", timeout));
+            cmd.arg("-o").arg(format!("ConnectTimeout={}", timeout));
         }
 
         if let Some(ref interval) = self.server_alive_interval {
-            init.arg("-o")
+            cmd.arg("-o")
                 .arg(format!("ServerAliveInterval={}", interval));
         }
 
         if let Some(ref port) = self.port {
-            init.arg("-p").arg(port);
+            cmd.arg("-p").arg(port);
         }
 
         if let Some(ref user) = self.user {
-            init.arg("-l").arg(user);
+            cmd.arg("-l").arg(user);
         }
 
         if let Some(ref k) = self.keyfile {
             // if the user gives a keyfile, _only_ use that keyfile
-            init.arg("-o").arg("IdentitiesOnly=yes");
-            init.arg("-i").arg(k);
+            cmd.arg("-o").arg("IdentitiesOnly=yes");
+            cmd.arg("-i").arg(k);
         }
 
         if let Some(ref config_file) = self.config_file {
-            init.arg("-F").arg(config_file);
+            cmd.arg("-F").arg(config_file);
         }
 
         if let Some(compression) = self.compression {
             let arg = if compression { "yes" } else { "no" };
 
-            init.arg("-o").arg(format!("Compression={}", arg));
+            cmd.arg("-o").arg(format!("Compression={}", arg));
+        }
+
+        for (option, list) in &self.algorithms {
+            cmd.arg("-o")
+                .arg(format!("{}={}", option.name(), list.as_value()));
         }
 
         if let Some(ssh_auth_sock) = self.ssh_auth_sock.as_deref() {
-            init.env("SSH_AUTH_SOCK", ssh_auth_sock);
+            cmd.env("SSH_AUTH_SOCK", ssh_auth_sock);
         }
 
         let mut it = self.jump_hosts.iter();
@@ -472,26 +938,19 @@ impl SessionBuilder {
                 s
             });
 
-            init.arg("-J").arg(&dest);
+            cmd.arg("-J").arg(&dest);
         }
 
         if let Some(user_known_hosts_file) = &self.user_known_hosts_file {
             let mut option: OsString = "UserKnownHostsFile=".into();
             option.push(&**user_known_hosts_file);
-            init.arg("-o").arg(option);
+            cmd.arg("-o").arg(option);
         }
 
-        init.arg(destination);
-
-        // we spawn and immediately wait, because the process is supposed to fork.
-        let status = init.status().await.map_err(Error::Connect)?;
-
-        if !status.success() {
-            let output = fs::read_to_string(log).map_err(Error::Connect)?;
-
-            Err(Error::interpret_ssh_error(&output))
-        } else {
-            Ok(dir)
+        // Options set with dedicated methods take precedence, since ssh uses the
+        // first value it obtains.
+        for (keyword, value) in &self.options {
+            cmd.arg("-o").arg(format!("{}={}", keyword, value));
         }
     }
 }
@@ -520,6 +979,126 @@ impl ControlPersist {
     }
 }
 
+/// Let `ssh -G` evaluate `options`, returning its error if it rejects them.
+///
+/// No config file is read, so that only the options themselves are checked.
+async fn check_options(options: &[(String, String)]) -> Result<Option<String>, Error> {
+    let mut cmd = process::Command::new("ssh");
+    cmd.stdin(Stdio::null()).arg("-G").arg("-F").arg("none");
+    for (keyword, value) in options {
+        cmd.arg("-o").arg(format!("{}={}", keyword, value));
+    }
+
+    let output = cmd.arg("none").output().await.map_err(Error::Connect)?;
+
+    Ok((!output.status.success())
+        .then(|| String::from_utf8_lossy(&output.stderr).trim().to_owned()))
+}
+
+/// A list of algorithms for [`SessionBuilder::ciphers`] and similar options, along with how it
+/// modifies the default list of ssh.
+///
+/// The algorithms are sent to ssh as a comma-separated list, prefixed with the
+/// modifier of the variant.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum AlgorithmList {
+    /// Use exactly these algorithms, in order of preference.
+    Set(Vec<String>),
+    /// Append the algorithms to the default list (`+`).
+    Append(Vec<String>),
+    /// Remove the algorithms from the default list (`-`).
+    ///
+    /// The algorithms may contain the wildcards `*` and `?`.
+    Remove(Vec<String>),
+    /// Move the algorithms to the front of the default list (`^`).
+    Prepend(Vec<String>),
+}
+
+impl AlgorithmList {
+    fn algorithms(&self) -> impl Iterator<Item = &str> {
+        match self {
+            AlgorithmList::Set(list)
+            | AlgorithmList::Append(list)
+            | AlgorithmList::Remove(list)
+            | AlgorithmList::Prepend(list) => list.iter().map(String::as_str),
+        }
+    }
+
+    fn as_value(&self) -> String {
+        let modifier = match self {
+            AlgorithmList::Set(_) => "",
+            AlgorithmList::Append(_) => "+",
+            AlgorithmList::Remove(_) => "-",
+            AlgorithmList::Prepend(_) => "^",
+        };
+
+        let mut value = modifier.to_owned();
+        for (i, algorithm) in self.algorithms().enumerate() {
+            if i != 0 {
+                value.push(',');
+            }
+            value.push_str(algorithm);
+        }
+        value
+    }
+}
+
+/// The ssh options taking an [`AlgorithmList`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AlgorithmOption {
+    Ciphers,
+    KexAlgorithms,
+    Macs,
+    HostKeyAlgorithms,
+    PubkeyAcceptedAlgorithms,
+}
+
+impl AlgorithmOption {
+    fn name(self) -> &'static str {
+        match self {
+            AlgorithmOption::Ciphers => "Ciphers",
+            AlgorithmOption::KexAlgorithms => "KexAlgorithms",
+            AlgorithmOption::Macs => "MACs",
+            AlgorithmOption::HostKeyAlgorithms => "HostKeyAlgorithms",
+            AlgorithmOption::PubkeyAcceptedAlgorithms => "PubkeyAcceptedAlgorithms",
+        }
+    }
+
+    /// Queries of `ssh -Q` listing the supported algorithms, in order of preference.
+    fn queries(self) -> &'static [&'static str] {
+        match self {
+            AlgorithmOption::Ciphers => &["cipher"],
+            AlgorithmOption::KexAlgorithms => &["kex"],
+            AlgorithmOption::Macs => &["mac"],
+            // `key-sig` also lists signature algorithms such as `rsa-sha2-256`,
+            // but is only supported since OpenSSH 8.3.
+            AlgorithmOption::HostKeyAlgorithms | AlgorithmOption::PubkeyAcceptedAlgorithms => {
+                &["key-sig", "key"]
+            }
+        }
+    }
+
+    /// The algorithms supported by the local `ssh`, or `None` if it cannot be queried.
+    async fn supported(self) -> Result<Option<Vec<String>>, Error> {
+        for query in self.queries() {
+            let output = process::Command::new("ssh")
+                .arg("-Q")
+                .arg(query)
+                .stdin(Stdio::null())
+                .output()
+                .await
+                .map_err(Error::Connect)?;
+
+            if output.status.success() {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                return Ok(Some(stdout.lines().map(str::to_owned).collect()));
+            }
+        }
+        Ok(None)
+    }
+}
+
 /// Specifies how the host's key fingerprint should be handled.
 #[derive(Debug, Clone)]
 pub enum KnownHosts {
@@ -537,6 +1116,17 @@ pub enum KnownHosts {
     ///
     /// This corresponds to `ssh -o StrictHostKeyChecking=no`.
     Accept,
+    /// Trust exactly the given keys, regardless of the known hosts files.
+    ///
+    /// The keys are written to a private known hosts file in the control directory of the
+    /// session, which ssh uses instead of `~/.ssh/known_hosts`, the global known hosts file
+    /// and [`SessionBuilder::user_known_hosts_file`]. Hosts presenting any other key are
+    /// rejected with [`ConnectError::HostKeyMismatch`](crate::ConnectError::HostKeyMismatch)
+    /// and nothing is ever added to a known hosts file.
+    ///
+    /// This corresponds to `ssh -o StrictHostKeyChecking=yes` with a `UserKnownHostsFile`
+    /// containing the keys and `GlobalKnownHostsFile=none`.
+    Pinned(Vec<HostKey>),
 }
 
 impl KnownHosts {
@@ -545,13 +1135,27 @@ impl KnownHosts {
             KnownHosts::Strict => "StrictHostKeyChecking=yes",
             KnownHosts::Add => "StrictHostKeyChecking=accept-new",
             KnownHosts::Accept => "StrictHostKeyChecking=no",
+            KnownHosts::Pinned(_) => "StrictHostKeyChecking=yes",
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SessionBuilder;
+    use super::{AlgorithmList, SessionBuilder};
+
+    #[test]
+    fn algorithm_list() {
+        let list = |algorithms: &[&str]| algorithms.iter().map(|s| s.to_string()).collect();
+
+        assert_eq!(AlgorithmList::Set(list(&["a", "b"])).as_value(), "a,b");
+        assert_eq!(AlgorithmList::Append(list(&["a"])).as_value(), "+a");
+        assert_eq!(
+            AlgorithmList::Remove(list(&["a*", "b"])).as_value(),
+            "-a*,b"
+        );
+        assert_eq!(AlgorithmList::Prepend(list(&["a"])).as_value(), "^a");
+    }
 
     #[test]
     fn resolve() {
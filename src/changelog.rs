@@ -2,6 +2,17 @@
 use crate::*;
 
 /// TODO: RENAME THIS INTO THE NEXT VERSION BEFORE RELEASE
+///
+/// ## Changed
+/// - Failures to establish the master connection, such as DNS failures, timeouts, refused
+///   connections and authentication failures, are now reported as [`Error::ConnectFailed`]
+///   carrying a [`ConnectError`] instead of as [`Error::Connect`].
+/// - Add [`ForwardType::Dynamic`] for SOCKS proxies (`ssh -D`). Since [`ForwardType`] is not
+///   `#[non_exhaustive]`, exhaustive matches on it need a new arm.
+/// - [`Session::request_port_forward`] now returns the [`Socket`] the forwarding listens on
+///   instead of `()`, which carries the allocated port for a `listen_socket` with port 0.
+/// - Add [`KnownHosts::Pinned`] to trust exactly the given [`HostKey`]s. Since
+///   [`KnownHosts`] is not `#[non_exhaustive]`, exhaustive matches on it need a new arm.
 #[doc(hidden)]
 pub mod unreleased {}
 
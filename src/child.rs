@@ -1,9 +1,19 @@
+use super::command::Signaller;
+use super::error::SESSION_REFUSED;
+use super::pty::PtyControl;
+use super::registry::ChannelGuard;
+use super::wrapper::{CWD_NOT_FOUND, PID_FILE_NOT_WRITTEN};
 use super::{ChildStderr, ChildStdin, ChildStdout, Error};
 
+use std::future::Future;
 use std::io;
+use std::path::PathBuf;
 use std::process::{ExitStatus, Output};
+use std::time::Duration;
 
 use tokio::io::AsyncReadExt;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::time::{timeout, timeout_at, Instant};
 use tokio::try_join;
 
 #[derive(Debug)]
@@ -28,6 +38,7 @@ impl From<super::native_mux_impl::RemoteChild> for RemoteChildImp {
     }
 }
 
+#[cfg(any(feature = "process-mux", feature = "native-mux"))]
 macro_rules! delegate {
     ($impl:expr, $var:ident, $then:block) => {{
         match $impl {
@@ -40,6 +51,54 @@ macro_rules! delegate {
     }};
 }
 
+#[cfg(not(any(feature = "process-mux", feature = "native-mux")))]
+macro_rules! delegate {
+    ($impl:expr, $var:ident, $then:block) => {{
+        unreachable!("Neither feature process-mux nor native-mux is enabled")
+    }};
+}
+
+/// Signals that can be delivered to a remote process with [`Child::signal`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Signal {
+    /// `SIGHUP`
+    Hangup,
+    /// `SIGINT`
+    Interrupt,
+    /// `SIGQUIT`
+    Quit,
+    /// `SIGKILL`
+    Kill,
+    /// `SIGTERM`
+    Terminate,
+    /// `SIGUSR1`
+    User1,
+    /// `SIGUSR2`
+    User2,
+    /// `SIGSTOP`
+    Stop,
+    /// `SIGCONT`
+    Continue,
+}
+
+impl Signal {
+    /// Name of the signal as understood by `kill -s`.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Signal::Hangup => "HUP",
+            Signal::Interrupt => "INT",
+            Signal::Quit => "QUIT",
+            Signal::Kill => "KILL",
+            Signal::Terminate => "TERM",
+            Signal::User1 => "USR1",
+            Signal::User2 => "USR2",
+            Signal::Stop => "STOP",
+            Signal::Continue => "CONT",
+        }
+    }
+}
+
 /// Representation of a running or exited remote child process.
 ///
 /// This structure is used to represent and manage remote child
@@ -54,8 +113,9 @@ macro_rules! delegate {
 ///
 /// Unlike [`std::process::Child`], `Child` *does* implement [`Drop`], and will terminate the
 /// local `ssh` process corresponding to the remote process when it goes out of scope. Note that
-/// this does _not_ terminate the remote process. If you want to do that, you will need to kill it
-/// yourself by executing a remote command like `pkill` to kill it on the remote side.
+/// this does _not_ terminate the remote process. If you want to do that, spawn it with
+/// [`OwningCommand::track_pid`](crate::OwningCommand::track_pid) and use
+/// [`kill`](Child::kill) or [`signal`](Child::signal).
 ///
 /// As a result, `Child` cannot expose `stdin`, `stdout`, and `stderr` as fields for
 /// split-borrows like [`std::process::Child`] does. Instead, it exposes
@@ -80,6 +140,11 @@ macro_rules! delegate {
 pub struct Child<S> {
     session: S,
     imp: RemoteChildImp,
+    signaller: Option<Signaller>,
+    pty: Option<PtyControl>,
+    cwd: Option<PathBuf>,
+    permit: Option<OwnedSemaphorePermit>,
+    channel: Option<ChannelGuard>,
 
     stdin: Option<ChildStdin>,
     stdout: Option<ChildStdout>,
@@ -102,9 +167,76 @@ impl<S> Child<S> {
             stdout,
             stderr,
             imp,
+            signaller: None,
+            pty: None,
+            cwd: None,
+            permit: None,
+            channel: None,
+        }
+    }
+
+    pub(crate) fn set_pty(&mut self, pty: Option<PtyControl>) {
+        self.pty = pty;
+    }
+
+    pub(crate) fn set_cwd(&mut self, cwd: Option<PathBuf>) {
+        self.cwd = cwd;
+    }
+
+    pub(crate) fn set_permit(&mut self, permit: Option<OwnedSemaphorePermit>) {
+        self.permit = permit;
+    }
+
+    pub(crate) fn set_channel(&mut self, channel: Option<ChannelGuard>) {
+        self.channel = channel;
+    }
+
+    pub(crate) fn set_signaller(&mut self, signaller: Option<Signaller>) {
+        self.signaller = signaller;
+    }
+
+    /// Send `signal` to the remote process.
+    ///
+    /// If the process was spawned with a [`pty`](crate::OwningCommand::pty),
+    /// [`Signal::Interrupt`] and [`Signal::Quit`] are delivered to its foreground process group
+    /// by writing the corresponding control character to the terminal.
+    ///
+    /// Otherwise this is only supported if the process was spawned with
+    /// [`OwningCommand::track_pid`](crate::OwningCommand::track_pid), and
+    /// [`Error::SignalUnsupported`] is returned if it was not. The signal is then delivered to
+    /// the whole process group of the remote command, which includes every process it spawned,
+    /// unless they moved into a new process group.
+    ///
+    /// Note that this does not wait for the remote process to exit.
+    pub async fn signal(&self, signal: Signal) -> Result<(), Error> {
+        if let Some(res) = self.pty.as_ref().and_then(|pty| pty.signal(signal)) {
+            return res.map_err(Error::ChildIo);
+        }
+
+        match &self.signaller {
+            Some(signaller) => signaller.signal(signal).await,
+            None => Err(Error::SignalUnsupported),
         }
     }
 
+    /// Forces the remote process to exit by sending it `SIGKILL`.
+    ///
+    /// This is equivalent to `self.signal(Signal::Kill)`, see [`signal`](Child::signal).
+    pub async fn kill(&self) -> Result<(), Error> {
+        self.signal(Signal::Kill).await
+    }
+
+    /// Change the size of the pseudo-terminal of the remote process to `cols` x `rows`.
+    ///
+    /// Returns [`Error::NoPty`] if the process was not spawned with a
+    /// [`pty`](crate::OwningCommand::pty).
+    pub async fn resize(&self, cols: u16, rows: u16) -> Result<(), Error> {
+        let pty = self.pty.as_ref().ok_or(Error::NoPty)?;
+        pty.resize(cols, rows).map_err(Error::ChildIo)?;
+
+        delegate!(&self.imp, imp, { imp.window_changed().await })
+    }
+
     /// Disconnect from this given remote child process.
     ///
     /// Note that disconnecting does _not_ kill the remote process, it merely kills the local
@@ -121,12 +253,47 @@ impl<S> Child<S> {
     /// The stdin handle to the child process, if any, will be closed before waiting. This helps
     /// avoid deadlock: it ensures that the child does not block waiting for input from the parent,
     /// while the parent waits for the child to exit.
-    pub async fn wait(mut self) -> Result<ExitStatus, Error> {
+    pub async fn wait(self) -> Result<ExitStatus, Error> {
+        self.wait_impl(None).await
+    }
+
+    /// Same as [`wait`](Child::wait), except that it gives up after `timeout`.
+    ///
+    /// On expiry, the connection to the remote child is severed and [`Error::Timeout`] is
+    /// returned. Note that this does _not_ terminate the remote process, unless it was spawned
+    /// with [`OwningCommand::track_pid`](crate::OwningCommand::track_pid), in which case it is
+    /// killed. Killing it delays the error by at most a few seconds.
+    pub async fn wait_timeout(self, timeout: Duration) -> Result<ExitStatus, Error> {
+        self.wait_impl(Some(Instant::now() + timeout)).await
+    }
+
+    pub(crate) async fn wait_impl(
+        mut self,
+        deadline: Option<Instant>,
+    ) -> Result<ExitStatus, Error> {
         // Close stdin so that if the remote process is reading stdin,
         // it would return EOF and the remote process can exit.
         self.stdin().take();
 
-        delegate!(self.imp, imp, { imp.wait().await })
+        let signaller = self.signaller.take();
+        let cwd = self.cwd.take();
+
+        // Dropping the implementation on timeout disconnects from the remote child.
+        let wait = async move { delegate!(self.imp, imp, { imp.wait().await }) };
+
+        let res: Result<ExitStatus, Error> = match with_deadline(deadline, wait).await {
+            Some(res) => res,
+            None => return Err(timed_out(signaller.as_ref(), Vec::new(), Vec::new()).await),
+        };
+        let status = res?;
+
+        match cwd {
+            Some(cwd) if status.code() == Some(CWD_NOT_FOUND) => Err(Error::CwdNotFound(cwd)),
+            _ if signaller.is_some() && status.code() == Some(PID_FILE_NOT_WRITTEN) => {
+                Err(Error::PidFileNotWritten)
+            }
+            _ => Ok(status),
+        }
     }
 
     /// Simultaneously waits for the remote child to exit and collect all remaining output on the
@@ -139,13 +306,21 @@ impl<S> Child<S> {
     /// By default, stdin, stdout and stderr are inherited from the parent. In order to capture the
     /// output into this `Result<Output>` it is necessary to create new pipes between parent and
     /// child. Use `stdout(Stdio::piped())` or `stderr(Stdio::piped())`, respectively.
-    pub async fn wait_with_output(mut self) -> Result<Output, Error> {
+    pub async fn wait_with_output(self) -> Result<Output, Error> {
+        self.wait_with_output_impl(None).await
+    }
+
+    pub(crate) async fn wait_with_output_impl(
+        mut self,
+        deadline: Option<Instant>,
+    ) -> Result<Output, Error> {
         self.stdin().take();
 
-        let child_stdout = self.stdout.take();
-        let stdout_read = async move {
-            let mut stdout = Vec::new();
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
 
+        let child_stdout = self.stdout.take();
+        let stdout_read = async {
             if let Some(mut child_stdout) = child_stdout {
                 child_stdout
                     .read_to_end(&mut stdout)
@@ -153,13 +328,11 @@ impl<S> Child<S> {
                     .map_err(Error::ChildIo)?;
             }
 
-            Ok::<_, Error>(stdout)
+            Ok::<_, Error>(())
         };
 
         let child_stderr = self.stderr.take();
-        let stderr_read = async move {
-            let mut stderr = Vec::new();
-
+        let stderr_read = async {
             if let Some(mut child_stderr) = child_stderr {
                 child_stderr
                     .read_to_end(&mut stderr)
@@ -167,20 +340,49 @@ impl<S> Child<S> {
                     .map_err(Error::ChildIo)?;
             }
 
-            Ok::<_, Error>(stderr)
+            Ok::<_, Error>(())
         };
 
         // Execute them concurrently to avoid the pipe buffer being filled up
         // and cause the remote process to block forever.
-        let (stdout, stderr) = try_join!(stdout_read, stderr_read)?;
+        // tokio's `try_join!` supports our MSRV, clippy attributes its expansion to Rust 1.64.
+        #[allow(clippy::incompatible_msrv)]
+        let read = async { try_join!(stdout_read, stderr_read) };
+
+        // `read_to_end` keeps the data read so far in the buffers, so the partial
+        // output is still available when the deadline expires.
+        match with_deadline(deadline, read).await {
+            Some(res) => res?,
+            None => {
+                // Disconnect from the remote child and release its channel first, so that
+                // the channel limit cannot hold up killing it.
+                let signaller = self.signaller.take();
+                drop(self);
+                return Err(timed_out(signaller.as_ref(), stdout, stderr).await);
+            }
+        };
+
+        // The self.wait() future terminates the stdout and stderr futures
+        // when it resolves, even if there may still be more data arriving
+        // from the server.
+        //
+        // Therefore, we wait for them first, and only once they're complete
+        // do we wait for the process to have terminated.
+        let status = match self.wait_impl(deadline).await {
+            Err(Error::Timeout { .. }) => return Err(Error::Timeout { stdout, stderr }),
+            // The ssh multiplex client only reports a rejected channel on stderr.
+            Err(Error::RemoteProcessTerminated)
+                if stderr
+                    .windows(SESSION_REFUSED.len())
+                    .any(|window| window == SESSION_REFUSED) =>
+            {
+                return Err(Error::TooManySessions)
+            }
+            res => res?,
+        };
+
         Ok(Output {
-            // The self.wait() future terminates the stdout and stderr futures
-            // when it resolves, even if there may still be more data arriving
-            // from the server.
-            //
-            // Therefore, we wait for them first, and only once they're complete
-            // do we wait for the process to have terminated.
-            status: self.wait().await?,
+            status,
             stdout,
             stderr,
         })
@@ -209,3 +411,31 @@ impl<S: Clone> Child<S> {
         self.session.clone()
     }
 }
+
+/// Run `future` to completion, or return `None` if `deadline` expires first.
+pub(crate) async fn with_deadline<F: Future>(
+    deadline: Option<Instant>,
+    future: F,
+) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// How long killing the remote process may delay reporting [`Error::Timeout`].
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Kill the remote process if possible and return [`Error::Timeout`].
+async fn timed_out(signaller: Option<&Signaller>, stdout: Vec<u8>, stderr: Vec<u8>) -> Error {
+    if let Some(signaller) = signaller {
+        // This is best-effort, the timeout is what gets reported. Since sending the signal
+        // goes through the master, which may be as stuck as the remote process, it is
+        // given up on after a while.
+        //
+        // The future is boxed since sending a signal waits on a child itself.
+        let _ = timeout(KILL_TIMEOUT, Box::pin(signaller.signal(Signal::Kill))).await;
+    }
+
+    Error::Timeout { stdout, stderr }
+}
@@ -1,14 +1,24 @@
 use crate::escape::escape;
 
-use super::child::Child;
+use super::child::{with_deadline, Child, Signal};
+use super::pty::{Pty, PtyConfig, PtyControl, Tty};
+use super::registry::Registry;
+use super::session::ChannelLimit;
 use super::stdio::TryFromChildIo;
-use super::Stdio;
+use super::wrapper::{Env, PidFile, Wrapper};
+use super::{ChildStdin, ChildStdout, Stdio};
 use super::{Error, Session};
 
 use std::borrow::Cow;
 use std::ffi::OsStr;
+use std::future::Future;
+use std::io;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
+
+use tokio::time::Instant;
 
 #[derive(Debug)]
 pub(crate) enum CommandImp {
@@ -60,11 +70,12 @@ pub trait OverSsh {
     ///
     /// ### Notes
     ///
-    /// The command to be executed on the remote machine should not explicitly
-    /// set environment variables or the current working directory. It errors if the source command
-    /// has environment variables or a current working directory set, since `openssh` doesn't (yet) have
-    /// a method to set environment variables and `ssh` doesn't support setting a current working directory
-    /// outside of `bash/dash/zsh` (which is not always available).
+    /// The current working directory of the source command is carried over, see
+    /// [`OwningCommand::current_dir`].
+    ///
+    /// Environment variables of the source command are carried over and always set up
+    /// by the remote shell, see [`OwningCommand::env_fallback`], so that they are not
+    /// dropped by the `AcceptEnv` configuration of the remote host.
     ///
     /// ###  Examples
     ///
@@ -92,8 +103,7 @@ pub trait OverSsh {
     /// }
     ///
     /// ```
-    /// 2. Building a command with environment variables or a current working directory set will
-    ///    results in an error.
+    /// 2. Environment variables and the current working directory are set on the remote process.
     ///
     /// ```no_run
     /// # #[tokio::main(flavor = "current_thread")]
@@ -102,12 +112,22 @@ pub trait OverSsh {
     ///     use openssh::{Session, KnownHosts, OverSsh};
     ///
     ///     let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
-    ///     let echo =
-    ///         Command::new("echo")
+    ///     let printenv =
+    ///         Command::new("printenv")
     ///         .env("MY_ENV_VAR", "foo")
-    ///         .arg("$MY_ENV_VAR")
-    ///         .over_ssh(&session);
-    ///     assert!(matches!(echo, Err(openssh::Error::CommandHasEnv)));
+    ///         .arg("MY_ENV_VAR")
+    ///         .over_ssh(&session)?
+    ///         .output()
+    ///         .await?;
+    ///     assert_eq!(printenv.stdout, b"foo\n");
+    ///
+    ///     let pwd =
+    ///         Command::new("pwd")
+    ///         .current_dir("/tmp")
+    ///         .over_ssh(&session)?
+    ///         .output()
+    ///         .await?;
+    ///     assert_eq!(pwd.stdout, b"/tmp\n");
     ///
     /// #   Ok(())
     /// }
@@ -124,21 +144,27 @@ impl OverSsh for std::process::Command {
         &self,
         session: S,
     ) -> Result<OwningCommand<S>, crate::Error> {
-        // I'd really like `!self.get_envs().is_empty()` here, but that's
-        // behind a `exact_size_is_empty` feature flag.
-        if self.get_envs().len() > 0 {
-            return Err(crate::Error::CommandHasEnv);
-        }
-
-        if self.get_current_dir().is_some() {
-            return Err(crate::Error::CommandHasCwd);
-        }
-
         let program_escaped: Cow<'_, OsStr> = escape(self.get_program());
         let mut command = Session::to_raw_command(session, program_escaped);
 
         let args = self.get_args().map(escape);
         command.raw_args(args);
+
+        if let Some(dir) = self.get_current_dir() {
+            command.current_dir(dir);
+        }
+
+        // I'd really like `!self.get_envs().is_empty()` here, but that's
+        // behind a `exact_size_is_empty` feature flag.
+        if self.get_envs().len() > 0 {
+            for (key, val) in self.get_envs() {
+                match val {
+                    Some(val) => command.env(key, val),
+                    None => command.env_remove(key),
+                };
+            }
+            command.env_fallback(true);
+        }
         Ok(command)
     }
 }
@@ -189,14 +215,29 @@ where
 /// the command without needing to immediately spawn the process. Similarly, you can call builder
 /// methods after spawning a process and then spawn a new process with the modified settings.
 ///
-/// # Environment variables and current working directory.
+/// # Environment variables
+///
+/// Environment variables set with [`env`](Self::env) and [`envs`](Self::envs) are transmitted
+/// over the ssh protocol when using the `process-mux` implementation, by passing `-o SetEnv` to
+/// `ssh`. However, `sshd` silently drops any variable not listed in its `AcceptEnv`
+/// configuration, see the `ENVIRONMENT` section of [`ssh(1)`] and `AcceptEnv` in
+/// [`sshd_config(5)`]. If that is a problem, enable [`env_fallback`](Self::env_fallback), which
+/// instead sets up the environment in the remote shell before running the command, similar to
+/// [`env(1)`].
+///
+/// The `native-mux` implementation, as well as [`env_remove`](Self::env_remove) and
+/// [`env_clear`](Self::env_clear), always use the remote shell.
 ///
-/// You'll notice that unlike its `std` counterpart, `OwningCommand` does not have any methods for
-/// setting environment variables or the current working directory for the remote command. This is
-/// because the SSH protocol does not support this (at least not in its standard configuration).
-/// For more details on this, see the `ENVIRONMENT` section of [`ssh(1)`]. To work around this,
-/// give [`env(1)`] a try. If the remote shell supports it, you can also prefix your command with
-/// `["cd", "dir", "&&"]` to run the rest of the command in some directory `dir`.
+/// # Current working directory
+///
+/// The SSH protocol does not support setting the working directory of the remote command, so
+/// [`current_dir`](Self::current_dir) instead makes the remote shell `cd` into the directory
+/// before running the command. This requires the login shell of the remote user to be
+/// POSIX-compatible and is not supported for [subsystems](Session::subsystem).
+///
+/// If the directory cannot be entered, the remote shell exits with status 253, which is reported
+/// as [`Error::CwdNotFound`]. Just like with exit status 255, this means that a remote command
+/// exiting with status 253 is indistinguishable from this error.
 ///
 /// # Exit status
 ///
@@ -207,6 +248,7 @@ where
 /// error came from `ssh`, and acts accordingly.
 ///
 ///   [`ssh(1)`]: https://linux.die.net/man/1/ssh
+///   [`sshd_config(5)`]: https://linux.die.net/man/5/sshd_config
 ///   [`env(1)`]: https://linux.die.net/man/1/env
 #[derive(Debug)]
 pub struct OwningCommand<S> {
@@ -216,6 +258,15 @@ pub struct OwningCommand<S> {
     stdin_set: bool,
     stdout_set: bool,
     stderr_set: bool,
+
+    timeout: Option<Duration>,
+    track_pid: bool,
+    pty: Option<PtyConfig>,
+    env: Env,
+    cwd: Option<PathBuf>,
+    channel_limit: Option<ChannelLimit>,
+    /// `None` for internal commands, e.g. the ones delivering signals.
+    registry: Option<Registry>,
 }
 
 impl<S> OwningCommand<S> {
@@ -227,9 +278,25 @@ impl<S> OwningCommand<S> {
             stdin_set: false,
             stdout_set: false,
             stderr_set: false,
+
+            timeout: None,
+            track_pid: false,
+            pty: None,
+            env: Env::default(),
+            cwd: None,
+            channel_limit: None,
+            registry: None,
         }
     }
 
+    pub(crate) fn set_channel_limit(&mut self, limit: Option<ChannelLimit>) {
+        self.channel_limit = limit;
+    }
+
+    pub(crate) fn set_registry(&mut self, registry: Registry) {
+        self.registry = Some(registry);
+    }
+
     /// Adds an argument to pass to the remote program.
     ///
     /// Before it is passed to the remote host, `arg` is escaped so that special characters aren't
@@ -354,22 +421,217 @@ impl<S> OwningCommand<S> {
         self.stderr_set = true;
         self
     }
+
+    /// Inserts or updates an environment variable of the remote process.
+    ///
+    /// See the [section on environment variables](Self#environment-variables) for how they are
+    /// transmitted to the remote host.
+    pub fn env<K, V>(&mut self, key: K, val: V) -> &mut Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.env.set(key.as_ref(), val.as_ref());
+        self
+    }
+
+    /// Inserts or updates multiple environment variables of the remote process.
+    ///
+    /// To set a single variable see [`env`](Self::env).
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, val) in vars {
+            self.env(key, val);
+        }
+        self
+    }
+
+    /// Removes an environment variable from the remote process.
+    ///
+    /// This requires the remote environment to be set up by the remote shell, see
+    /// [`env_fallback`](Self::env_fallback).
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.env.remove(key.as_ref());
+        self
+    }
+
+    /// Clears the entire environment of the remote process, including the variables set up by
+    /// the login shell of the remote user such as `PATH`.
+    ///
+    /// This requires the remote environment to be set up by the remote shell, see
+    /// [`env_fallback`](Self::env_fallback).
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env.clear();
+        self
+    }
+
+    /// Always set up the environment of the remote process by prefixing the remote command with
+    /// shell code, instead of transmitting it over the ssh protocol.
+    ///
+    /// Use this if the `AcceptEnv` configuration of the remote `sshd` does not accept the
+    /// variables, in which case they are silently dropped. This requires the login shell of the
+    /// remote user to be POSIX-compatible and is not supported for
+    /// [subsystems](Session::subsystem).
+    ///
+    /// It is implied by [`env_remove`](Self::env_remove) and [`env_clear`](Self::env_clear).
+    ///
+    /// Defaults to `false`.
+    pub fn env_fallback(&mut self, fallback: bool) -> &mut Self {
+        self.env.set_fallback(fallback);
+        self
+    }
+
+    /// Sets the working directory of the remote process.
+    ///
+    /// Relative paths are resolved relative to the directory the remote shell starts in,
+    /// usually the home directory of the remote user. See the
+    /// [section on the working directory](Self#current-working-directory) for details.
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.cwd = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Set the maximum duration [`output`](Self::output) and [`status`](Self::status) wait
+    /// for the remote command to finish, including the time it takes to spawn it.
+    ///
+    /// If the remote command does not finish in time, the connection to it is severed and
+    /// [`Error::Timeout`] is returned, carrying any output collected so far.
+    ///
+    /// This does not apply to [`spawn`](Self::spawn), use [`Child::wait_timeout`] instead.
+    ///
+    /// Defaults to `None`.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Lower the [`timeout`](Self::timeout) to `timeout`, unless it is lower already.
+    pub(crate) fn limit_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(self.timeout.map_or(timeout, |current| current.min(timeout)));
+    }
+
+    /// Track the remote process, so that signals can be delivered to it with
+    /// [`Child::signal`] and [`Child::kill`].
+    ///
+    /// The ssh multiplex protocol does not support sending signals, so instead the remote
+    /// command is wrapped in a shell script that records its process group in a file in the
+    /// remote temporary directory. Signals are then delivered by running `kill` over the same
+    /// session.
+    ///
+    /// This requires the login shell of the remote user to be POSIX-compatible and is not
+    /// supported for [subsystems](Session::subsystem). A remote command that is terminated by a
+    /// signal exits with `128 + signal number`.
+    ///
+    /// If the pid cannot be recorded, the remote shell exits with status 252 without running the
+    /// command, which is reported as [`Error::PidFileNotWritten`].
+    ///
+    /// When combined with [`timeout`](Self::timeout), the remote process is killed on expiry.
+    ///
+    /// Defaults to `false`.
+    pub fn track_pid(&mut self, track: bool) -> &mut Self {
+        self.track_pid = track;
+        self
+    }
+
+    /// Run the remote command in a pseudo-terminal configured by `config`.
+    ///
+    /// This is required for interactive programs such as `sudo` or `top`. The terminal is
+    /// exposed through [`Child::stdin`] and [`Child::stdout`], so the stdin and stdout
+    /// configuration is ignored, while the remote stderr is merged into the terminal.
+    /// The local stderr of `ssh` is still controlled by [`stderr`](Self::stderr).
+    ///
+    /// The terminal can be resized with [`Child::resize`], and [`Child::signal`] supports
+    /// [`Signal::Interrupt`] and [`Signal::Quit`] by sending the corresponding control
+    /// characters. If the remote host fails to allocate a terminal, the command is run
+    /// without one, just like `ssh` does.
+    ///
+    /// Defaults to `None`.
+    pub fn pty(&mut self, config: PtyConfig) -> &mut Self {
+        self.pty = Some(config);
+        self
+    }
 }
 
 impl<S: Clone> OwningCommand<S> {
     async fn spawn_impl(&mut self) -> Result<Child<S>, Error> {
-        Ok(Child::new(
-            self.session.clone(),
-            delegate!(&mut self.imp, imp, {
-                let (imp, stdin, stdout, stderr) = imp.spawn().await?;
-                (
-                    imp.into(),
-                    stdin.map(TryFromChildIo::try_from).transpose()?,
-                    stdout.map(TryFromChildIo::try_from).transpose()?,
-                    stderr.map(TryFromChildIo::try_from).transpose()?,
-                )
-            }),
-        ))
+        // The channel is released once the child is dropped.
+        let permit = match &self.channel_limit {
+            Some(limit) => Some(limit.acquire().await?),
+            None => None,
+        };
+
+        let (command_line, subsystem): (String, bool) = delegate!(&self.imp, imp, {
+            (
+                String::from_utf8_lossy(imp.command_line()).into_owned(),
+                imp.is_subsystem(),
+            )
+        });
+
+        let pid_file = self.track_pid.then(PidFile::new);
+        let wrapper = Wrapper {
+            pid_file: pid_file.as_ref(),
+            env: Some(&self.env),
+            cwd: self.cwd.as_deref(),
+        };
+
+        // A new pty is allocated for every spawn.
+        let pty = self
+            .pty
+            .as_ref()
+            .map(Pty::open)
+            .transpose()
+            .map_err(Error::ChildIo)?;
+        let tty = self
+            .pty
+            .as_ref()
+            .zip(pty.as_ref())
+            .map(|(config, pty)| Tty {
+                term: &config.term,
+                pty,
+            });
+
+        let (imp, mut stdin, mut stdout, stderr) = delegate!(&mut self.imp, imp, {
+            let (imp, stdin, stdout, stderr) = imp.spawn(wrapper, tty).await?;
+            (
+                imp.into(),
+                stdin.map(TryFromChildIo::try_from).transpose()?,
+                stdout.map(TryFromChildIo::try_from).transpose()?,
+                stderr.map(TryFromChildIo::try_from).transpose()?,
+            )
+        });
+
+        let pty_control = match self.pty.as_ref().zip(pty) {
+            Some((config, pty)) => {
+                let master = pty.into_master();
+                let dup = || master.try_clone().map_err(Error::ChildIo);
+
+                stdin = Some(ChildStdin::from_pty(dup()?).map_err(Error::ChildIo)?);
+                stdout = Some(ChildStdout::from_pty(dup()?).map_err(Error::ChildIo)?);
+                Some(PtyControl::new(master, config))
+            }
+            None => None,
+        };
+
+        let mut child = Child::new(self.session.clone(), (imp, stdin, stdout, stderr));
+        child.set_pty(pty_control);
+        child.set_cwd(self.cwd.clone());
+        child.set_permit(permit);
+        child.set_channel(
+            self.registry
+                .as_ref()
+                .map(|registry| registry.open_channel(command_line, subsystem)),
+        );
+
+        child.set_signaller(pid_file.map(|pid_file| Signaller {
+            imp: delegate!(&self.imp, imp, { imp.sibling(Vec::new()).into() }),
+            pid_file,
+        }));
+
+        Ok(child)
     }
 
     /// Executes the remote command without waiting for it, returning a handle to it
@@ -395,6 +657,8 @@ impl<S: Clone> OwningCommand<S> {
     /// By default, stdout and stderr are captured (and used to provide the resulting
     /// output) and stdin is set to `Stdio::null()`.
     pub async fn output(&mut self) -> Result<process::Output, Error> {
+        let deadline = self.deadline();
+
         if !self.stdin_set {
             self.stdin(Stdio::null());
         }
@@ -405,13 +669,71 @@ impl<S: Clone> OwningCommand<S> {
             self.stderr(Stdio::piped());
         }
 
-        self.spawn_impl().await?.wait_with_output().await
+        Self::spawn_before(deadline, self.spawn_impl())
+            .await?
+            .wait_with_output_impl(deadline)
+            .await
     }
 
     /// Executes the remote command, waiting for it to finish and collecting its exit status.
     ///
     /// By default, stdin, stdout and stderr are inherited.
     pub async fn status(&mut self) -> Result<process::ExitStatus, Error> {
-        self.spawn().await?.wait().await
+        let deadline = self.deadline();
+
+        Self::spawn_before(deadline, self.spawn())
+            .await?
+            .wait_impl(deadline)
+            .await
+    }
+
+    /// The deadline is computed before spawning, so that the time spent on
+    /// opening the channel counts towards the timeout as well.
+    fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| Instant::now() + timeout)
+    }
+
+    /// Waiting for a channel slot or for the mux to open the session can take
+    /// arbitrarily long, so spawning is bounded by the deadline too.
+    async fn spawn_before(
+        deadline: Option<Instant>,
+        spawn: impl Future<Output = Result<Child<S>, Error>>,
+    ) -> Result<Child<S>, Error> {
+        match with_deadline(deadline, spawn).await {
+            Some(res) => res,
+            None => Err(Error::Timeout {
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Delivers signals to a remote process spawned with [`OwningCommand::track_pid`].
+#[derive(Debug)]
+pub(crate) struct Signaller {
+    /// Command for the master the remote process was spawned on.
+    imp: CommandImp,
+    pid_file: PidFile,
+}
+
+impl Signaller {
+    pub(crate) async fn signal(&self, signal: Signal) -> Result<(), Error> {
+        let imp = delegate!(&self.imp, imp, {
+            imp.sibling(self.pid_file.signal_script(signal)).into()
+        });
+
+        // The channel limit does not apply, since the process being signalled may hold
+        // the last free channel.
+        let output = OwningCommand::new((), imp).output().await?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(Error::Remote(io::Error::new(
+                io::ErrorKind::Other,
+                stderr.trim(),
+            )))
+        }
     }
 }
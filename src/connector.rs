@@ -0,0 +1,120 @@
+//! Connector tunnelling HTTP connections through a [`Session`].
+
+use super::{DirectStream, Error, Session};
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::Uri;
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use hyper_util::rt::TokioIo;
+use tower_service::Service;
+
+/// A [`Service<Uri>`](Service) connecting to the host of the requested URI from the remote host,
+/// for use with [`hyper_util`]'s `Client` and other clients built on [`tower_service`].
+///
+/// Every connection is carried by [`Session::connect_tcp`], so no port forwarding has to be
+/// set up, and the host of the URI is resolved on the remote host. The port defaults to 80 for
+/// `http` and 443 for `https` URIs.
+///
+/// The connector does not speak TLS itself. To request `https` URIs, wrap it in a TLS
+/// connector such as the one of `hyper-rustls`.
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "native-mux")]
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use http_body_util::Empty;
+/// use hyper::body::Bytes;
+/// use hyper_util::client::legacy::Client;
+/// use hyper_util::rt::TokioExecutor;
+/// use openssh::{HttpConnector, KnownHosts, Session};
+/// use std::sync::Arc;
+///
+/// let session = Session::connect_mux("me@bastion.example.com", KnownHosts::Strict).await?;
+///
+/// let client = Client::builder(TokioExecutor::new())
+///     .build::<_, Empty<Bytes>>(HttpConnector::new(Arc::new(session)));
+/// let response = client.get("http://dashboard.internal/".parse()?).await?;
+/// println!("{}", response.status());
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HttpConnector {
+    session: Arc<Session>,
+}
+
+impl HttpConnector {
+    /// Create a connector tunnelling its connections through `session`.
+    pub fn new(session: Arc<Session>) -> Self {
+        Self { session }
+    }
+
+    /// The session the connections are tunnelled through.
+    pub fn session(&self) -> &Arc<Session> {
+        &self.session
+    }
+}
+
+/// Host and port to connect to for `uri`.
+fn target(uri: &Uri) -> Result<(String, u16), Error> {
+    let host = uri
+        .host()
+        .ok_or_else(|| Error::UriWithoutHost(uri.to_string()))?;
+    // IPv6 addresses are enclosed in brackets in URIs.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let port = match (uri.port_u16(), uri.scheme_str()) {
+        (Some(port), _) => port,
+        (None, Some("https")) => 443,
+        (None, _) => 80,
+    };
+
+    Ok((host.to_owned(), port))
+}
+
+impl Service<Uri> for HttpConnector {
+    type Response = TokioIo<DirectStream>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let session = self.session.clone();
+
+        Box::pin(async move {
+            let (host, port) = target(&uri)?;
+            let stream = session.connect_tcp(&host, port).await?;
+            Ok(TokioIo::new(stream))
+        })
+    }
+}
+
+impl Connection for DirectStream {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        let target = |uri: &str| target(&uri.parse().unwrap()).unwrap();
+
+        assert_eq!(target("http://dashboard"), ("dashboard".to_owned(), 80));
+        assert_eq!(target("https://dashboard/x"), ("dashboard".to_owned(), 443));
+        assert_eq!(target("http://[::1]:8080"), ("::1".to_owned(), 8080));
+        assert!(matches!(
+            super::target(&"/relative".parse().unwrap()),
+            Err(Error::UriWithoutHost(uri)) if uri == "/relative"
+        ));
+    }
+}
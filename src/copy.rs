@@ -0,0 +1,368 @@
+//! Copy files over plain exec channels, for hosts without an sftp subsystem.
+//!
+//! Files are streamed through `cat` running in a POSIX shell on the remote
+//! host. The size and the sha256 checksum of the copy are verified, where the
+//! remote checksum is computed with whichever of `sha256sum`, `shasum` or
+//! `openssl` is available.
+
+use super::escape::escape;
+use super::{Error, OwningCommand, Session, Stdio};
+
+use std::ffi::OsStr;
+use std::fs::Permissions;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Output;
+
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+pub(crate) const CHUNK_SIZE: usize = 32 * 1024;
+
+/// Shell function printing the sha256 checksum of its stdin, or nothing if
+/// no suitable tool is installed.
+pub(crate) const SHA256: &[u8] =
+    b"sha256() { sha256sum 2>/dev/null || shasum -a 256 2>/dev/null || \
+openssl dgst -sha256 -r 2>/dev/null; }\n";
+
+pub(crate) fn sh(session: &Session, script: Vec<u8>) -> OwningCommand<&Session> {
+    let mut cmd = session.command("sh");
+    cmd.arg("-c").raw_arg(escape(OsStr::from_bytes(&script)));
+    cmd
+}
+
+pub(crate) fn remote_failure(output: &Output) -> Error {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Error::Remote(io::Error::new(io::ErrorKind::Other, stderr.trim()))
+}
+
+/// Script writing its stdin to a temporary file next to `remote`, printing
+/// the id of the temporary file, its size and its checksum on one line each.
+fn upload_script(remote: &Path) -> Vec<u8> {
+    let mut script = b"f=".to_vec();
+    script.extend_from_slice(escape(remote.as_os_str()).as_bytes());
+    script.extend_from_slice(b"\nt=\"$f.openssh-rs-$$\"\n");
+    script.extend_from_slice(SHA256);
+    script.extend_from_slice(
+        b"echo $$\n\
+          cat > \"$t\" || { rm -f \"$t\"; exit 1; }\n\
+          echo $(($(wc -c < \"$t\")))\n\
+          echo \"$(sha256 < \"$t\" | cut -d ' ' -f 1)\"\n",
+    );
+    script
+}
+
+/// Script replacing `remote` with the temporary file `id` written by
+/// [`upload_script`] if `mode` is given, or removing the temporary file otherwise.
+fn finish_script(remote: &Path, id: u32, mode: Option<u32>) -> Vec<u8> {
+    let mut script = b"f=".to_vec();
+    script.extend_from_slice(escape(remote.as_os_str()).as_bytes());
+    script.extend_from_slice(format!("\nt=\"$f.openssh-rs-{id}\"\n").as_bytes());
+    match mode {
+        Some(mode) => script.extend_from_slice(
+            format!(
+                "chmod {mode:o} \"$t\" && mv -f \"$t\" \"$f\" || {{ rm -f \"$t\"; exit 1; }}\n"
+            )
+            .as_bytes(),
+        ),
+        None => script.extend_from_slice(b"rm -f \"$t\"\n"),
+    }
+    script
+}
+
+/// Script printing the permissions, size and checksum of `remote` on one
+/// line each, followed by its content.
+fn download_script(remote: &Path) -> Vec<u8> {
+    let mut script = b"f=".to_vec();
+    script.extend_from_slice(escape(remote.as_os_str()).as_bytes());
+    script.push(b'\n');
+    script.extend_from_slice(SHA256);
+    script.extend_from_slice(
+        b"exec 3< \"$f\" || exit 1\n\
+          [ -f \"$f\" ] || { echo \"$f: not a regular file\" >&2; exit 1; }\n\
+          ls -ld -- \"$f\" | cut -c 1-10\n\
+          echo $(($(wc -c < \"$f\")))\n\
+          echo \"$(sha256 < \"$f\" | cut -d ' ' -f 1)\"\n\
+          exec cat <&3\n",
+    );
+    script
+}
+
+/// Parse the permissions printed by `ls -l`, e.g. `-rwsr-xr-t`.
+fn parse_mode(perms: &str) -> Option<u32> {
+    let perms = perms.as_bytes();
+    if perms.len() != 10 {
+        return None;
+    }
+
+    let mut mode = 0;
+    for (i, &c) in perms[1..].iter().enumerate() {
+        let bit = 1 << (8 - i);
+        let special = match i {
+            2 => 0o4000,
+            5 => 0o2000,
+            8 => 0o1000,
+            _ => 0,
+        };
+        mode |= match (i % 3, c) {
+            (_, b'-') => 0,
+            (0, b'r') | (1, b'w') | (2, b'x') => bit,
+            (2, b's') if special != 0o1000 => bit | special,
+            (2, b'S') if special != 0o1000 => special,
+            (2, b't') if special == 0o1000 => bit | special,
+            (2, b'T') if special == 0o1000 => special,
+            _ => return None,
+        };
+    }
+    Some(mode)
+}
+
+pub(crate) async fn copy_to(session: &Session, local: &Path, remote: &Path) -> Result<u64, Error> {
+    let mut file = fs::File::open(local).await.map_err(Error::LocalIo)?;
+    let metadata = file.metadata().await.map_err(Error::LocalIo)?;
+    let mode = metadata.permissions().mode() & 0o7777;
+
+    let mut child = sh(session, upload_script(remote))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .await?;
+
+    let mut stdin = child.stdin().take().expect("stdin is piped");
+    // stdin is moved into the future, so that it gets closed even on error.
+    //
+    // The file is hashed while it is sent, and compared with the checksum of
+    // the copy once the remote host has written it.
+    let copy = async move {
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        loop {
+            let n = file.read(&mut buffer).await.map_err(Error::LocalIo)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+            size += n as u64;
+            stdin
+                .write_all(&buffer[..n])
+                .await
+                .map_err(Error::ChildIo)?;
+        }
+        stdin.shutdown().await.map_err(Error::ChildIo)?;
+        Ok::<_, Error>((size, format!("{:x}", hasher.finalize())))
+    };
+
+    // If the remote command fails early, the error is reported by its exit
+    // status rather than the broken pipe.
+    let copied = copy.await;
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(remote_failure(&output));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let id = lines
+        .next()
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| remote_failure(&output))?;
+    let remote_size = lines.next().and_then(|size| size.parse::<u64>().ok());
+    let remote_sha256 = lines.next().unwrap_or_default();
+
+    // The remote checksum is empty if no suitable tool is installed.
+    let verified = copied.and_then(|(size, sha256)| {
+        if remote_size == Some(size) && (remote_sha256.is_empty() || remote_sha256 == sha256) {
+            Ok(size)
+        } else {
+            Err(Error::CopyMismatch)
+        }
+    });
+
+    // The temporary file is removed if the copy failed or does not match.
+    let mode = verified.as_ref().ok().map(|_| mode);
+    let output = sh(session, finish_script(remote, id, mode))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await?;
+
+    let size = verified?;
+    if !output.status.success() {
+        return Err(remote_failure(&output));
+    }
+
+    Ok(size)
+}
+
+pub(crate) async fn copy_from(
+    session: &Session,
+    remote: &Path,
+    local: &Path,
+) -> Result<u64, Error> {
+    let mut child = sh(session, download_script(remote))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .await?;
+
+    let stdout = child.stdout().take().expect("stdout is piped");
+    let mut stdout = BufReader::new(stdout);
+
+    // The file is only moved to `local` once it has been verified.
+    let dir = match local.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let tmp = tempfile::Builder::new()
+        .prefix(".openssh-rs-")
+        .tempfile_in(dir)
+        .map_err(Error::LocalIo)?;
+
+    let copy = async {
+        let mut header = Vec::new();
+        for _ in 0..3 {
+            let mut line = String::new();
+            stdout.read_line(&mut line).await.map_err(Error::ChildIo)?;
+            header.push(line.trim_end_matches('\n').to_owned());
+        }
+
+        let file = tmp.as_file().try_clone().map_err(Error::LocalIo)?;
+        let mut file = fs::File::from_std(file);
+
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut hasher = Sha256::new();
+        let mut copied = 0;
+        loop {
+            let n = stdout.read(&mut buffer).await.map_err(Error::ChildIo)?;
+            if n == 0 {
+                break;
+            }
+            file.write_all(&buffer[..n]).await.map_err(Error::LocalIo)?;
+            hasher.update(&buffer[..n]);
+            copied += n as u64;
+        }
+        file.flush().await.map_err(Error::LocalIo)?;
+
+        Ok::<_, Error>((header, format!("{:x}", hasher.finalize()), copied))
+    };
+
+    let copied = copy.await;
+    // Stop reading, so that the remote command does not block on a full pipe.
+    drop(stdout);
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(remote_failure(&output));
+    }
+    let (header, sha256, copied) = copied?;
+
+    let mode = parse_mode(&header[0]).ok_or(Error::CopyMismatch)?;
+    let size = header[1].parse::<u64>().ok();
+    if size != Some(copied) || (!header[2].is_empty() && header[2] != sha256) {
+        return Err(Error::CopyMismatch);
+    }
+
+    fs::set_permissions(tmp.path(), Permissions::from_mode(mode))
+        .await
+        .map_err(Error::LocalIo)?;
+    tmp.persist(local)
+        .map_err(|err| Error::LocalIo(err.error))?;
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::{Command, Stdio as StdStdio};
+
+    fn run(script: &[u8], stdin: &[u8]) -> Output {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(OsStr::from_bytes(script))
+            .stdin(StdStdio::piped())
+            .stdout(StdStdio::piped())
+            .stderr(StdStdio::piped())
+            .spawn()
+            .unwrap();
+        io::Write::write_all(&mut child.stdin.take().unwrap(), stdin).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    #[test]
+    fn upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("it's a file");
+        let content = b"some content\n";
+
+        let output = run(&upload_script(&remote), content);
+        assert!(output.status.success(), "{:?}", output);
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<_> = stdout.lines().collect();
+        assert_eq!(lines[1..], [&content.len().to_string(), &*sha256(content)]);
+        assert!(!remote.exists());
+
+        let id = lines[0].parse().unwrap();
+        let output = run(&finish_script(&remote, id, Some(0o640)), b"");
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(std::fs::read(&remote).unwrap(), content);
+        let mode = std::fs::metadata(&remote).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o640);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let output = run(&upload_script(&remote), b"other");
+        assert!(output.status.success(), "{:?}", output);
+        let id = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let output = run(&finish_script(&remote, id, None), b"");
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(std::fs::read(&remote).unwrap(), content);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn download() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = dir.path().join("file");
+        let content = b"line\nanother line\n";
+        std::fs::write(&remote, content).unwrap();
+        std::fs::set_permissions(&remote, Permissions::from_mode(0o751)).unwrap();
+
+        let output = run(&download_script(&remote), b"");
+        assert!(output.status.success(), "{:?}", output);
+
+        let expected = format!("-rwxr-x--x\n{}\n{}\n", content.len(), sha256(content));
+        let (header, rest) = output.stdout.split_at(expected.len());
+        assert_eq!(header, expected.as_bytes());
+        assert_eq!(rest, content);
+
+        let output = run(&download_script(&dir.path().join("missing")), b"");
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+    }
+
+    #[test]
+    fn mode_parsing() {
+        assert_eq!(parse_mode("-rw-r--r--"), Some(0o644));
+        assert_eq!(parse_mode("-rwsr-S--T"), Some(0o7740));
+        assert_eq!(parse_mode("drwxrwxrwt"), Some(0o1777));
+        assert_eq!(parse_mode("-rwxr-xr-x."), None);
+        assert_eq!(parse_mode("-rwzr-xr-x"), None);
+    }
+}
@@ -0,0 +1,200 @@
+//! Streams to sockets reachable from the remote host, see [`Session::connect_tcp`].
+
+use super::{Error, Session};
+
+use std::ffi::OsString;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::{ExitStatus, Stdio};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::process::{ChildStderr, ChildStdin, ChildStdout, Command};
+
+/// A bidirectional stream to a socket connected to from the remote host, created by
+/// [`Session::connect_tcp`] or [`Session::connect_unix`].
+///
+/// Data written to the stream is sent to the remote socket, and data the socket sends is read
+/// from the stream. Shutting down the writing half closes the sending side of the connection.
+///
+/// The stream is carried by an `ssh -W` process talking to the master of the session, which is
+/// killed once the stream is dropped. Like the port forwardings of the session, the stream breaks
+/// once the [`Session`] is closed.
+pub struct DirectStream {
+    /// Waits for ssh to exit. Owns the child, which is killed once the stream is dropped.
+    exit: Pin<Box<dyn Future<Output = io::Result<ExitStatus>> + Send + Sync>>,
+    exit_status: Option<ExitStatus>,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+    stderr: ChildStderr,
+    stderr_buf: Vec<u8>,
+}
+
+impl fmt::Debug for DirectStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirectStream")
+            .field("exit_status", &self.exit_status)
+            .field("stdin", &self.stdin)
+            .field("stdout", &self.stdout)
+            .field("stderr", &self.stderr)
+            .finish()
+    }
+}
+
+impl DirectStream {
+    pub(crate) async fn connect(session: &Session, target: OsString) -> Result<Self, Error> {
+        let mut child = Command::new("ssh")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .arg("-S")
+            .arg(session.control_socket())
+            .args(["-o", "BatchMode=yes", "-W"])
+            .arg(target)
+            // ssh ignores the destination, since it talks to the master at `-S`.
+            .arg("none")
+            .spawn()
+            .map_err(Error::Ssh)?;
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+
+        Ok(Self {
+            exit: Box::pin(async move { child.wait().await }),
+            exit_status: None,
+            stdin: Some(stdin),
+            stdout,
+            stderr,
+            stderr_buf: Vec::new(),
+        })
+    }
+
+    /// Once stdout is closed, wait for ssh to exit to find out whether the connection was
+    /// closed by the remote socket or could not be established, in which case the
+    /// diagnostics of ssh describe the error.
+    fn poll_error(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let mut buf = [0; 256];
+            let mut buf = ReadBuf::new(&mut buf);
+            if let Poll::Ready(res) = Pin::new(&mut self.stderr).poll_read(cx, &mut buf) {
+                res?;
+            } else {
+                return Poll::Pending;
+            }
+            if buf.filled().is_empty() {
+                break;
+            }
+            self.stderr_buf.extend_from_slice(buf.filled());
+        }
+
+        let status = match self.exit_status {
+            Some(status) => status,
+            None => match self.exit.as_mut().poll(cx) {
+                Poll::Ready(res) => *self.exit_status.insert(res?),
+                Poll::Pending => return Poll::Pending,
+            },
+        };
+        if status.success() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let stderr = String::from_utf8_lossy(&self.stderr_buf);
+        let stderr = stderr.trim();
+
+        Poll::Ready(Err(if stderr.is_empty() {
+            io::Error::new(io::ErrorKind::Other, format!("ssh exited with {status}"))
+        } else if stderr.contains("Connection refused") {
+            io::Error::new(io::ErrorKind::ConnectionRefused, stderr)
+        } else {
+            io::Error::new(io::ErrorKind::Other, stderr)
+        }))
+    }
+}
+
+/// Constructs the argument of `ssh -W` for `host:port`.
+pub(crate) fn tcp_target(host: &str, port: u16) -> OsString {
+    if host.contains(':') {
+        format!("[{host}]:{port}").into()
+    } else {
+        format!("{host}:{port}").into()
+    }
+}
+
+/// Constructs the argument of `ssh -W` for a unix socket at `path`.
+pub(crate) fn unix_target(path: &Path) -> OsString {
+    path.as_os_str().to_owned()
+}
+
+impl AsyncRead for DirectStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        let filled = buf.filled().len();
+        if let Poll::Ready(res) = Pin::new(&mut this.stdout).poll_read(cx, buf) {
+            res?;
+        } else {
+            return Poll::Pending;
+        }
+
+        if buf.filled().len() == filled && buf.remaining() != 0 {
+            this.poll_error(cx)
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+impl AsyncWrite for DirectStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().stdin {
+            Some(stdin) => Pin::new(stdin).poll_write(cx, buf),
+            None => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().stdin {
+            Some(stdin) => Pin::new(stdin).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(stdin) = &mut this.stdin {
+            if let Poll::Ready(res) = Pin::new(stdin).poll_flush(cx) {
+                res?;
+            } else {
+                return Poll::Pending;
+            }
+        }
+        // Closing stdin makes ssh send EOF to the remote socket.
+        this.stdin = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        assert_eq!(tcp_target("localhost", 80), "localhost:80");
+        assert_eq!(tcp_target("::1", 80), "[::1]:80");
+        assert_eq!(unix_target(Path::new("/run/app.sock")), "/run/app.sock");
+    }
+}
@@ -1,4 +1,5 @@
 use std::io;
+use std::path::{Path, PathBuf};
 
 /// Errors that occur when interacting with a remote process.
 #[derive(Debug, thiserror::Error)]
@@ -8,13 +9,18 @@ pub enum Error {
     #[error("the master connection failed")]
     Master(#[source] io::Error),
 
-    /// Failed to establish initial connection to the remote host.
+    /// Failed to establish initial connection to the remote host due to
+    /// a local error, e.g. the ssh master could not be executed.
     #[error("failed to connect to the remote host")]
     Connect(#[source] io::Error),
 
+    /// The ssh master failed to connect to or to authenticate with the remote host.
+    ///
+    /// See [`ConnectError`] for the possible causes.
+    #[error("failed to connect to the remote host")]
+    ConnectFailed(#[source] ConnectError),
+
     /// Failed to run the `ssh` command locally.
-    #[cfg(feature = "process-mux")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
     #[error("the local ssh command could not be executed")]
     Ssh(#[source] io::Error),
 
@@ -30,6 +36,22 @@ pub enum Error {
     #[error("invalid command: Command contains null byte.")]
     InvalidCommand,
 
+    /// An sftp operation failed.
+    #[cfg(feature = "sftp")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sftp")))]
+    #[error("the sftp operation failed")]
+    Sftp(#[source] openssh_sftp_client::Error),
+
+    /// A local file could not be accessed.
+    #[error("failed to access a local file")]
+    LocalIo(#[source] io::Error),
+
+    /// The file copied by [`Session::copy_to`](crate::Session::copy_to) or
+    /// [`Session::copy_from`](crate::Session::copy_from) does not match the
+    /// size or sha256 checksum of the source file.
+    #[error("the copied file does not match the source file")]
+    CopyMismatch,
+
     /// The remote process failed.
     #[error("the remote command could not be executed")]
     Remote(#[source] io::Error),
@@ -68,17 +90,126 @@ pub enum Error {
     #[error("failure while accessing standard i/o of remote process")]
     ChildIo(#[source] io::Error),
 
+    /// The remote host refused to open another channel for a remote command, because the limit on
+    /// the number of sessions per connection (`MaxSessions` in `sshd_config`) was reached, or no
+    /// channel became free within the timeout set with
+    /// [`SessionBuilder::max_sessions_timeout`](crate::SessionBuilder::max_sessions_timeout).
+    ///
+    /// With the process implementation, a refused channel can only be detected by
+    /// [`OwningCommand::output`](crate::OwningCommand::output) or if stderr is otherwise captured
+    /// by this crate, and is reported as [`Error::RemoteProcessTerminated`] otherwise.
+    #[error("the remote host refused to open another session")]
+    TooManySessions,
+
+    /// The remote command did not finish within the timeout.
+    ///
+    /// The connection to the remote process has been severed, but note that
+    /// this does _not_ terminate the remote process, unless it was spawned with
+    /// [`OwningCommand::track_pid`](crate::OwningCommand::track_pid).
+    #[error("the remote command timed out")]
+    Timeout {
+        /// Output written to stdout before the timeout, if it was piped.
+        stdout: Vec<u8>,
+        /// Output written to stderr before the timeout, if it was piped.
+        stderr: Vec<u8>,
+    },
+
+    /// Signals can only be sent to remote processes spawned with
+    /// [`OwningCommand::track_pid`](crate::OwningCommand::track_pid), see
+    /// [`Child::signal`](crate::Child::signal).
+    #[error("the signal cannot be delivered to the remote process")]
+    SignalUnsupported,
+
+    /// The remote process was not spawned with a
+    /// [`pty`](crate::OwningCommand::pty).
+    #[error("the remote process does not have a pty")]
+    NoPty,
+
+    /// The [`current_dir`](crate::OwningCommand::current_dir) of the remote command
+    /// does not exist or cannot be entered.
+    #[error("the working directory {0:?} of the remote command does not exist")]
+    CwdNotFound(PathBuf),
+
+    /// The remote shell could not record the pid of a command spawned with
+    /// [`OwningCommand::track_pid`](crate::OwningCommand::track_pid), e.g. because the
+    /// remote temporary directory is not writable, so the command was not run.
+    #[error("the pid of the remote process could not be recorded")]
+    PidFileNotWritten,
+
     /// The command has some env variables that it expects to carry over ssh.
-    /// However, OverSsh does not support passing env variables over ssh.
+    ///
+    /// This is no longer returned, since [`OverSsh`](crate::OverSsh) now carries
+    /// env variables over to the remote process.
+    #[deprecated(
+        note = "`OverSsh::over_ssh` now carries env variables over to the remote process"
+    )]
     #[error("rejected runing a command over ssh that expects env variables to be carried over to remote.")]
     CommandHasEnv,
 
     /// The command expects to be in a specific working directory in remote.
-    /// However, OverSsh does not support setting a working directory for commands to be executed over ssh.
+    ///
+    /// This is no longer returned, since [`OverSsh`](crate::OverSsh) now carries
+    /// the working directory over to the remote process.
+    #[deprecated(
+        note = "`OverSsh::over_ssh` now carries the working directory over to the remote process"
+    )]
     #[error("rejected runing a command over ssh that expects a specific working directory to be carried over to remote.")]
     CommandHasCwd,
+
+    /// An algorithm passed to [`SessionBuilder::ciphers`](crate::SessionBuilder::ciphers) or
+    /// a similar option is not supported by the local `ssh`.
+    #[error("{algorithm:?} is not supported by ssh for {option}")]
+    UnsupportedAlgorithm {
+        /// The ssh option, e.g. `Ciphers`.
+        option: &'static str,
+        /// The unsupported algorithm.
+        algorithm: String,
+    },
+
+    /// An option passed to [`SessionBuilder::option`](crate::SessionBuilder::option) is
+    /// controlled by this crate, or rejected by `ssh`.
+    #[error("invalid ssh option {keyword:?}: {reason}")]
+    InvalidOption {
+        /// The keyword of the option.
+        keyword: String,
+        /// Why the option is invalid, e.g. the error reported by `ssh`.
+        reason: String,
+    },
+
+    /// A key passed to [`KnownHosts::Pinned`](crate::KnownHosts::Pinned) is malformed.
+    #[error("invalid host key {key:?}: {reason}")]
+    InvalidHostKey {
+        /// The key or fingerprint.
+        key: String,
+        /// What is wrong with the key.
+        reason: String,
+    },
+
+    /// An ssh configuration file passed to
+    /// [`ConfigFile::parse`](crate::ssh_config::ConfigFile::parse) or
+    /// [`ConfigFile::load`](crate::ssh_config::ConfigFile::load) is malformed.
+    #[error("invalid ssh configuration on line {line}: {reason}")]
+    InvalidConfig {
+        /// The file containing the error, `None` if the configuration was parsed from a string.
+        path: Option<PathBuf>,
+        /// The number of the line containing the error, starting at 1, or 0 if the error
+        /// concerns the whole file.
+        line: usize,
+        /// What is wrong with the line.
+        reason: String,
+    },
+
+    /// A URI passed to [`HttpConnector`](crate::HttpConnector) has no host to connect to.
+    #[cfg(feature = "http")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "http")))]
+    #[error("URI {0} has no host")]
+    UriWithoutHost(String),
 }
 
+/// Reason given by the ssh multiplex master when the remote host refused to
+/// open a session channel.
+pub(crate) const SESSION_REFUSED: &[u8] = b"Session open refused by peer";
+
 #[cfg(feature = "native-mux")]
 impl From<openssh_mux_client::Error> for Error {
     fn from(err: openssh_mux_client::Error) -> Self {
@@ -97,89 +228,384 @@ impl From<openssh_mux_client::Error> for Error {
 
                 _ => Error::SshMux(err),
             },
+            openssh_mux_client::Error::RequestFailure(reason)
+                if reason.as_bytes() == SESSION_REFUSED =>
+            {
+                Error::TooManySessions
+            }
             _ => Error::SshMux(err),
         }
     }
 }
 
 impl Error {
-    pub(crate) fn interpret_ssh_error(stderr: &str) -> Self {
-        // we want to turn the string-only ssh error into something a little more "handleable".
-        // we do this by trying to interpret the output from `ssh`. this is error-prone, but
-        // the best we can do. if you find ways to impove this, even just through heuristics,
-        // please file an issue or PR :)
-        //
-        // format is:
-        //
-        //     ssh: ssh error: io error
-        let mut stderr = stderr.trim();
-        stderr = stderr.strip_prefix("ssh: ").unwrap_or(stderr);
-        if stderr.starts_with("Warning: Permanently added ") {
-            // added to hosts file -- let's ignore that message
-            stderr = stderr.split_once('\n').map(|x| x.1.trim()).unwrap_or("");
+    pub(crate) fn interpret_ssh_error(log: &str, jump_hosts: &[Box<str>]) -> Self {
+        Error::ConnectFailed(ConnectError::from_log(log, jump_hosts))
+    }
+}
+
+/// Cause of a failure to connect to the remote host.
+///
+/// It is produced by interpreting the log of the ssh master, which is the best we can do
+/// since `ssh` only reports errors in human-readable form. If the log is not recognized,
+/// [`ConnectError::Other`] is returned with the log as-is.
+#[derive(Debug, Clone, Eq, PartialEq, thiserror::Error)]
+#[non_exhaustive]
+pub enum ConnectError {
+    /// The host key of the remote host does not match the one in the known hosts file.
+    #[error("the host key of the remote host has changed{}", fmt_fingerprint(.fingerprint))]
+    HostKeyMismatch {
+        /// Fingerprint of the key sent by the remote host, e.g. `SHA256:...`.
+        fingerprint: Option<Box<str>>,
+        /// Known hosts file and (1-based) line number of the offending key.
+        known_hosts_line: Option<(Box<Path>, usize)>,
+    },
+
+    /// The host key of the remote host is not in the known hosts file and
+    /// [`KnownHosts::Strict`](crate::KnownHosts::Strict) is used.
+    #[error("the host key of the remote host is not known")]
+    HostKeyUnknown,
+
+    /// None of the authentication methods succeeded.
+    #[error("permission denied (offered authentication methods: {})", .methods_offered.join(","))]
+    AuthFailed {
+        /// Authentication methods the server accepts, e.g. `publickey` or `password`.
+        methods_offered: Vec<Box<str>>,
+    },
+
+    /// The hostname of the remote host cannot be resolved.
+    #[error("could not resolve hostname {host}")]
+    DnsFailure {
+        /// The hostname that cannot be resolved.
+        host: Box<str>,
+    },
+
+    /// The remote host refused the connection.
+    #[error("connection refused")]
+    ConnectionRefused,
+
+    /// Timed out while connecting to the remote host.
+    #[error("connection timed out")]
+    Timeout,
+
+    /// The remote host is not reachable from this machine.
+    #[error("network is unreachable")]
+    NetworkUnreachable,
+
+    /// The `ProxyCommand` used to connect to the remote host failed.
+    #[error("proxy failed: {message}")]
+    ProxyFailed {
+        /// The log of the ssh master.
+        message: Box<str>,
+    },
+
+    /// One of the [jump hosts](crate::SessionBuilder::jump_hosts) failed, either
+    /// because it is not reachable itself or because it cannot connect to the next hop.
+    #[error("jump host {hop} failed")]
+    JumpHostFailed {
+        /// The jump host as passed to [`SessionBuilder::jump_hosts`](crate::SessionBuilder::jump_hosts).
+        hop: Box<str>,
+    },
+
+    /// Unrecognized failure.
+    #[error("{message}")]
+    Other {
+        /// The log of the ssh master.
+        message: Box<str>,
+    },
+}
+
+fn fmt_fingerprint(fingerprint: &Option<Box<str>>) -> String {
+    fingerprint
+        .as_ref()
+        .map(|fingerprint| format!(" (new fingerprint {})", fingerprint))
+        .unwrap_or_default()
+}
+
+/// Extract the hostname from a jump host specification `[user@]host[:port]`.
+fn hop_hostname(hop: &str) -> &str {
+    let hop = hop.strip_prefix("ssh://").unwrap_or(hop);
+    let hop = hop
+        .rsplit_once('@')
+        .map(|(_user, host)| host)
+        .unwrap_or(hop);
+
+    match hop.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') && port.parse::<u16>().is_ok() => host,
+        _ => hop,
+    }
+}
+
+impl ConnectError {
+    /// Interpret the log of the ssh master.
+    ///
+    /// `jump_hosts` are used to tell failures of the jump hosts from
+    /// failures of the destination.
+    pub(crate) fn from_log(log: &str, jump_hosts: &[Box<str>]) -> Self {
+        let lines: Vec<&str> = log
+            .lines()
+            .map(|line| line.trim_end_matches('\r').trim())
+            .map(|line| line.strip_prefix("ssh: ").unwrap_or(line))
+            .filter(|line| !line.is_empty() && !line.starts_with("Warning: Permanently added "))
+            .collect();
+
+        let message = || lines.join("\n").into_boxed_str();
+
+        let jump_host = |host: &str| {
+            jump_hosts
+                .iter()
+                .find(|hop| hop_hostname(hop) == host)
+                .map(|hop| ConnectError::JumpHostFailed { hop: hop.clone() })
+        };
+        // The last jump host is the one connecting to the destination.
+        let last_jump_host = || {
+            jump_hosts
+                .last()
+                .map(|hop| ConnectError::JumpHostFailed { hop: hop.clone() })
+        };
+
+        // Host key verification comes first, since ssh could print other
+        // errors after refusing the host key.
+        if lines
+            .iter()
+            .any(|line| line.contains("REMOTE HOST IDENTIFICATION HAS CHANGED"))
+        {
+            let fingerprint = lines
+                .iter()
+                .position(|line| line.contains("key sent by the remote host is"))
+                .and_then(|i| lines.get(i + 1))
+                .map(|fingerprint| Box::from(fingerprint.trim_end_matches('.')));
+
+            let known_hosts_line = lines.iter().find_map(|line| {
+                let (_, location) = line.strip_prefix("Offending ")?.split_once(" key in ")?;
+                let (path, lineno) = location.rsplit_once(':')?;
+                Some((Path::new(path).into(), lineno.parse().ok()?))
+            });
+
+            return ConnectError::HostKeyMismatch {
+                fingerprint,
+                known_hosts_line,
+            };
+        }
+
+        if lines
+            .iter()
+            .any(|line| line.starts_with("No ") && line.contains("host key is known for"))
+            || lines.contains(&"Host key verification failed.")
+        {
+            return ConnectError::HostKeyUnknown;
         }
-        let mut kind = io::ErrorKind::ConnectionAborted;
-        let mut err = stderr.splitn(2, ": ");
-        if let Some(ssh_error) = err.next() {
-            if ssh_error.starts_with("Could not resolve") {
-                // match what `std` gives: https://github.com/rust-lang/rust/blob/a5de254862477924bcd8b9e1bff7eadd6ffb5e2a/src/libstd/sys/unix/net.rs#L40
-                // we _could_ match on "Name or service not known" from io_error,
-                // but my guess is that the ssh error is more stable.
-                kind = io::ErrorKind::Other;
+
+        for line in &lines {
+            if let Some(rest) = line.strip_prefix("Could not resolve hostname ") {
+                let host = rest.split_once(':').map(|(host, _)| host).unwrap_or(rest);
+                return jump_host(host)
+                    .unwrap_or_else(|| ConnectError::DnsFailure { host: host.into() });
             }
 
-            if let Some(io_error) = err.next() {
-                match io_error {
-                    "Network is unreachable" => {
-                        kind = io::ErrorKind::Other;
-                    }
-                    "Connection refused" => {
-                        kind = io::ErrorKind::ConnectionRefused;
-                    }
-                    e if ssh_error.starts_with("connect to host")
-                        && e == "Connection timed out" =>
-                    {
-                        kind = io::ErrorKind::TimedOut;
-                    }
-                    e if ssh_error.starts_with("connect to host") && e == "Operation timed out" => {
-                        // this is the macOS version of "connection timed out"
-                        kind = io::ErrorKind::TimedOut;
-                    }
-                    e if ssh_error.starts_with("connect to host") && e == "Permission denied" => {
-                        // this is the macOS version of "network is unreachable".
-                        kind = io::ErrorKind::Other;
-                    }
-                    e if e.contains("Permission denied (") => {
-                        kind = io::ErrorKind::PermissionDenied;
+            // format is:
+            //
+            //     connect to host HOST port PORT: io error
+            if let Some(rest) = line.strip_prefix("connect to host ") {
+                let (target, io_error) = rest.split_once(": ").unwrap_or((rest, ""));
+                let host = target.split(" port ").next().unwrap_or(target);
+
+                let err = match io_error {
+                    "Connection refused" => ConnectError::ConnectionRefused,
+                    // "Operation timed out" is the macOS version of "connection timed out"
+                    "Connection timed out" | "Operation timed out" => ConnectError::Timeout,
+                    // "Permission denied" is the macOS version of "network is unreachable"
+                    "Network is unreachable" | "No route to host" | "Permission denied" => {
+                        ConnectError::NetworkUnreachable
                     }
-                    _ => {}
-                }
+                    _ => ConnectError::Other { message: message() },
+                };
+
+                return jump_host(host).unwrap_or(err);
+            }
+
+            if line.starts_with("Connection timed out during banner exchange") {
+                return ConnectError::Timeout;
+            }
+        }
+
+        // The authentication of a jump host can fail as well, so only check
+        // this after looking for jump host failures above.
+        if let Some(line) = lines
+            .iter()
+            .rev()
+            .find(|line| line.contains("Permission denied ("))
+        {
+            let methods_offered = line
+                .rsplit_once("Permission denied (")
+                .and_then(|(_, methods)| methods.split_once(')'))
+                .map(|(methods, _)| methods.split(',').map(Box::from).collect())
+                .unwrap_or_default();
+
+            return ConnectError::AuthFailed { methods_offered };
+        }
+
+        // A jump host could not open a channel to the next hop.
+        if lines.iter().any(|line| {
+            (line.starts_with("channel ") && line.contains("open failed"))
+                || line.starts_with("stdio forwarding failed")
+        }) {
+            if let Some(err) = last_jump_host() {
+                return err;
             }
         }
 
-        // NOTE: we may want to provide more structured connection errors than just io::Error?
-        // NOTE: can we re-use this method for non-connect cases?
-        Error::Connect(io::Error::new(kind, stderr))
+        // The connection is established through a proxy (`ProxyCommand` or
+        // `ProxyJump`), which exited before the ssh handshake completed.
+        if lines.iter().any(|line| line.contains("UNKNOWN port 65535")) {
+            return last_jump_host()
+                .unwrap_or_else(|| ConnectError::ProxyFailed { message: message() });
+        }
+
+        ConnectError::Other { message: message() }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{io, Error};
+    use super::{io, ConnectError, Error, Path};
 
     #[test]
     fn parse_error() {
         let err = "ssh: Warning: Permanently added \'login.csail.mit.edu,128.52.131.0\' (ECDSA) to the list of known hosts.\r\nopenssh-tester@login.csail.mit.edu: Permission denied (publickey,gssapi-keyex,gssapi-with-mic,password,keyboard-interactive).";
-        let err = Error::interpret_ssh_error(err);
-        let target = io::Error::new(io::ErrorKind::PermissionDenied, "openssh-tester@login.csail.mit.edu: Permission denied (publickey,gssapi-keyex,gssapi-with-mic,password,keyboard-interactive).");
-        if let Error::Connect(e) = err {
-            assert_eq!(e.kind(), target.kind());
-            assert_eq!(format!("{}", e), format!("{}", target));
+        let err = Error::interpret_ssh_error(err, &[]);
+        if let Error::ConnectFailed(ConnectError::AuthFailed { methods_offered }) = err {
+            assert_eq!(
+                methods_offered,
+                [
+                    "publickey",
+                    "gssapi-keyex",
+                    "gssapi-with-mic",
+                    "password",
+                    "keyboard-interactive"
+                ]
+                .map(Box::from)
+            );
         } else {
             unreachable!("{:?}", err);
         }
     }
 
+    #[test]
+    fn parse_connect_errors() {
+        let parse = |log: &str| ConnectError::from_log(log, &[]);
+
+        assert_eq!(
+            parse("ssh: Could not resolve hostname bad-host: Name or service not known\r\n"),
+            ConnectError::DnsFailure {
+                host: "bad-host".into()
+            }
+        );
+        assert_eq!(
+            parse("ssh: connect to host 127.0.0.1 port 9: Connection refused\r\n"),
+            ConnectError::ConnectionRefused
+        );
+        assert_eq!(
+            parse("ssh: connect to host 192.0.2.1 port 22: Connection timed out\r\n"),
+            ConnectError::Timeout
+        );
+        assert_eq!(
+            parse("ssh: connect to host 192.0.2.1 port 22: Operation timed out\r\n"),
+            ConnectError::Timeout
+        );
+        assert_eq!(
+            parse("ssh: connect to host 192.0.2.1 port 22: Network is unreachable\r\n"),
+            ConnectError::NetworkUnreachable
+        );
+        assert_eq!(
+            parse("No ED25519 host key is known for [127.0.0.1]:2222 and you have requested strict checking.\r\nHost key verification failed.\r\n"),
+            ConnectError::HostKeyUnknown
+        );
+        assert_eq!(
+            parse("kex_exchange_identification: Connection closed by remote host\r\nConnection closed by UNKNOWN port 65535\r\n"),
+            ConnectError::ProxyFailed {
+                message: "kex_exchange_identification: Connection closed by remote host\nConnection closed by UNKNOWN port 65535".into()
+            }
+        );
+        assert_eq!(
+            parse("something unexpected\r\n"),
+            ConnectError::Other {
+                message: "something unexpected".into()
+            }
+        );
+    }
+
+    #[cfg(feature = "native-mux")]
+    #[test]
+    fn refused_session() {
+        let err = Error::from(openssh_mux_client::Error::RequestFailure(
+            "Session open refused by peer".into(),
+        ));
+        assert!(matches!(err, Error::TooManySessions), "{:?}", err);
+
+        let err = Error::from(openssh_mux_client::Error::RequestFailure(
+            "Port forwarding failed".into(),
+        ));
+        assert!(matches!(err, Error::SshMux(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn parse_host_key_mismatch() {
+        let log = "\
+@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\r
+@    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @\r
+@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\r
+IT IS POSSIBLE THAT SOMEONE IS DOING SOMETHING NASTY!\r
+Someone could be eavesdropping on you right now (man-in-the-middle attack)!\r
+It is also possible that a host key has just been changed.\r
+The fingerprint for the ED25519 key sent by the remote host is\r
+SHA256:pMz1Rlk6PZbcuqSYqqJ+bSM5ESsTE0v+lw4ejRLnFFk.\r
+Please contact your system administrator.\r
+Add correct host key in /home/user/.ssh/hosts:1 to get rid of this message.\r
+Offending ED25519 key in /home/user/.ssh/hosts:1:3\r
+Host key for [127.0.0.1]:2222 has changed and you have requested strict checking.\r
+Host key verification failed.\r
+";
+        assert_eq!(
+            ConnectError::from_log(log, &[]),
+            ConnectError::HostKeyMismatch {
+                fingerprint: Some("SHA256:pMz1Rlk6PZbcuqSYqqJ+bSM5ESsTE0v+lw4ejRLnFFk".into()),
+                known_hosts_line: Some((Path::new("/home/user/.ssh/hosts:1").into(), 3)),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_jump_host_errors() {
+        let jump_hosts = ["user@bastion:2222".into(), "inner".into()];
+
+        assert_eq!(
+            ConnectError::from_log(
+                "ssh: Could not resolve hostname bastion: Name or service not known\r\nkex_exchange_identification: Connection closed by remote host\r\nConnection closed by UNKNOWN port 65535\r\n",
+                &jump_hosts
+            ),
+            ConnectError::JumpHostFailed {
+                hop: "user@bastion:2222".into()
+            }
+        );
+        assert_eq!(
+            ConnectError::from_log(
+                "channel 0: open failed: connect failed: Connection refused\r\nstdio forwarding failed\r\nkex_exchange_identification: Connection closed by remote host\r\nConnection closed by UNKNOWN port 65535\r\n",
+                &jump_hosts
+            ),
+            ConnectError::JumpHostFailed {
+                hop: "inner".into()
+            }
+        );
+        assert_eq!(
+            ConnectError::from_log(
+                "ssh: connect to host target port 22: Connection refused\r\n",
+                &jump_hosts
+            ),
+            ConnectError::ConnectionRefused
+        );
+    }
+
     #[test]
     fn error_sanity() {
         use std::error::Error as _;
@@ -0,0 +1,252 @@
+//! Running a command on many hosts at once.
+
+use super::child::with_deadline;
+use super::{ConnectError, Error, OwningCommand, Session, SessionBuilder};
+
+use std::fmt;
+use std::process::Output;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::TempDir;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// A set of hosts to run a command on concurrently, see [`Fleet::run`].
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "process-mux")]
+/// # #[tokio::main]
+/// # async fn main() {
+/// use openssh::{Fleet, SessionBuilder};
+/// use std::time::Duration;
+///
+/// let hosts = (1..=200).map(|i| format!("me@host{i}.example.com"));
+///
+/// let mut fleet = Fleet::new(SessionBuilder::default(), hosts);
+/// fleet.timeout(Duration::from_secs(30));
+///
+/// let mut run = fleet.run(|session| session.arc_command("uptime"));
+/// while let Some((host, result)) = run.next().await {
+///     match result {
+///         Ok(output) => println!("{host}: {}", String::from_utf8_lossy(&output.stdout)),
+///         Err(err) => eprintln!("{host}: {err}"),
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Fleet {
+    builder: SessionBuilder,
+    new_session: fn(TempDir) -> Session,
+    destinations: Vec<String>,
+    max_concurrency: usize,
+    timeout: Option<Duration>,
+}
+
+impl Fleet {
+    /// Default of [`Fleet::max_concurrency`].
+    pub const DEFAULT_MAX_CONCURRENCY: usize = 32;
+
+    /// Create a fleet of `destinations`, connecting to each of them with `builder` using the
+    /// process implementation (see [`SessionBuilder::connect`]).
+    #[cfg(feature = "process-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
+    pub fn new<I>(builder: SessionBuilder, destinations: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self::with_session(builder, destinations, Session::new_process_mux)
+    }
+
+    /// Create a fleet of `destinations`, connecting to each of them with `builder` using the
+    /// native mux implementation (see [`SessionBuilder::connect_mux`]).
+    #[cfg(feature = "native-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
+    pub fn new_mux<I>(builder: SessionBuilder, destinations: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self::with_session(builder, destinations, Session::new_native_mux)
+    }
+
+    fn with_session<I>(
+        builder: SessionBuilder,
+        destinations: I,
+        new_session: fn(TempDir) -> Session,
+    ) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self {
+            builder,
+            new_session,
+            destinations: destinations.into_iter().map(Into::into).collect(),
+            max_concurrency: Self::DEFAULT_MAX_CONCURRENCY,
+            timeout: None,
+        }
+    }
+
+    /// Set how many hosts are connected to and run the command at the same time.
+    ///
+    /// Defaults to [`Fleet::DEFAULT_MAX_CONCURRENCY`].
+    ///
+    /// # Panics
+    ///
+    /// If `max` is `0`.
+    pub fn max_concurrency(&mut self, max: usize) -> &mut Self {
+        assert_ne!(max, 0, "max_concurrency must not be 0");
+        self.max_concurrency = max;
+        self
+    }
+
+    /// Set how long connecting to a single host and running the command on it may take.
+    ///
+    /// If connecting takes too long, [`ConnectError::Timeout`] is returned for that host. The
+    /// `ConnectTimeout` of the [`SessionBuilder`] is lowered to the timeout as well. If the
+    /// command does not finish in the remaining time, [`Error::Timeout`] is returned, carrying
+    /// the output collected so far, see [`OwningCommand::timeout`].
+    ///
+    /// Defaults to `None`, i.e. no timeout.
+    pub fn timeout(&mut self, d: Duration) -> &mut Self {
+        self.timeout = Some(d);
+        self
+    }
+
+    /// Connect to every host and run the command created by `command` on it, collecting its
+    /// output as with [`OwningCommand::output`].
+    ///
+    /// The session to each host is closed once the command finished. The hosts are processed
+    /// in the background and their results are returned by [`FleetRun::next`] in the order they
+    /// finish. Dropping the [`FleetRun`] cancels the hosts that did not finish yet.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn run<F>(&self, command: F) -> FleetRun
+    where
+        F: Fn(Arc<Session>) -> OwningCommand<Arc<Session>> + Send + Sync + 'static,
+    {
+        let command = Arc::new(command);
+        let slots = Arc::new(Semaphore::new(self.max_concurrency));
+        let mut tasks = JoinSet::new();
+
+        let mut builder = self.builder.clone();
+        if let Some(time_limit) = self.timeout {
+            builder.limit_connect_timeout(time_limit);
+        }
+
+        for destination in &self.destinations {
+            let builder = builder.clone();
+            let new_session = self.new_session;
+            let time_limit = self.timeout;
+            let command = command.clone();
+            let slots = slots.clone();
+            let destination = destination.clone();
+
+            tasks.spawn(async move {
+                let _slot = slots
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed");
+
+                let deadline = time_limit.map(|time_limit| Instant::now() + time_limit);
+
+                // ssh is killed if connecting is cut short by the deadline.
+                let connect = builder.connect_impl(&destination, new_session);
+                let session = match with_deadline(deadline, connect).await {
+                    Some(Ok(session)) => Arc::new(session),
+                    Some(Err(err)) => return (destination, Err(err)),
+                    None => {
+                        let err = Error::ConnectFailed(ConnectError::Timeout);
+                        return (destination, Err(err));
+                    }
+                };
+
+                let mut cmd = command(session.clone());
+                if let Some(deadline) = deadline {
+                    cmd.limit_timeout(deadline.saturating_duration_since(Instant::now()));
+                }
+                let output = cmd.output().await;
+                // The command holds on to the session.
+                drop(cmd);
+
+                // The result of the command is what matters, so errors while
+                // closing the session are ignored.
+                if let Ok(session) = Arc::try_unwrap(session) {
+                    session.close().await.ok();
+                }
+
+                (destination, output)
+            });
+        }
+
+        FleetRun { tasks }
+    }
+}
+
+/// Hosts of a [`Fleet`] running a command, created by [`Fleet::run`].
+pub struct FleetRun {
+    tasks: JoinSet<(String, Result<Output, Error>)>,
+}
+
+impl fmt::Debug for FleetRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FleetRun")
+            .field("pending", &self.tasks.len())
+            .finish()
+    }
+}
+
+impl FleetRun {
+    /// Wait for the next host to finish, returning its destination and the output of the
+    /// command, or `None` once all hosts finished.
+    pub async fn next(&mut self) -> Option<(String, Result<Output, Error>)> {
+        match self.tasks.join_next().await? {
+            Ok(result) => Some(result),
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    /// Wait for all hosts to finish and summarise the results.
+    pub async fn collect(mut self) -> FleetReport {
+        let mut report = FleetReport::default();
+
+        while let Some((destination, result)) = self.next().await {
+            match result {
+                Ok(output) => report.outputs.push((destination, output)),
+                Err(err) => report.failures.push((destination, err)),
+            }
+        }
+
+        report
+    }
+}
+
+/// Results of running a command on a [`Fleet`], created by [`FleetRun::collect`].
+///
+/// Both lists are in the order the hosts finished.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct FleetReport {
+    /// Hosts the command ran on, with its output.
+    ///
+    /// Note that the command may still have exited with a non-zero status.
+    pub outputs: Vec<(String, Output)>,
+
+    /// Hosts that could not be connected to or the command failed to run on.
+    pub failures: Vec<(String, Error)>,
+}
+
+impl FleetReport {
+    /// Return `true` if the command ran and exited successfully on every host.
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+            && self
+                .outputs
+                .iter()
+                .all(|(_, output)| output.status.success())
+    }
+}
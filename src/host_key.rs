@@ -0,0 +1,235 @@
+//! Host keys trusted with [`KnownHosts::Pinned`].
+
+use super::{ConnectError, Error, SessionBuilder};
+
+#[cfg(doc)]
+use super::KnownHosts;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, Engine, GeneralPurpose, GeneralPurposeConfig};
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+
+/// Standard base64, with or without padding, since fingerprints are printed without it.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// A host key trusted with [`KnownHosts::Pinned`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum HostKey {
+    /// A public key as found in `known_hosts` and `.pub` files, e.g.
+    /// `ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAA...`, optionally followed by a comment.
+    PublicKey(String),
+
+    /// The SHA256 fingerprint of a public key as printed by `ssh-keygen -l`, e.g.
+    /// `SHA256:1+sStAd/KgbebOyJbt1eHWla0nl5XRGebt0J/hSa5vc`.
+    ///
+    /// The key itself is obtained by scanning the host with `ssh-keyscan`, which connects
+    /// to the host directly, so the host has to be reachable without
+    /// [jump hosts](SessionBuilder::jump_hosts) or a `ProxyCommand`.
+    Fingerprint(String),
+}
+
+/// A key to write to the known hosts file, or a fingerprint to find the key of.
+enum Pin {
+    Key { algorithm: String, key: String },
+    Fingerprint([u8; 32]),
+}
+
+impl HostKey {
+    fn to_pin(&self) -> Result<Pin, Error> {
+        let invalid = |key: &str, reason: &str| Error::InvalidHostKey {
+            key: key.to_owned(),
+            reason: reason.to_owned(),
+        };
+
+        match self {
+            HostKey::PublicKey(public_key) => {
+                let mut parts = public_key.split_whitespace();
+                let (algorithm, key) = match (parts.next(), parts.next()) {
+                    (Some(algorithm), Some(key)) => (algorithm, key),
+                    _ => return Err(invalid(public_key, "expected an algorithm and a key")),
+                };
+                if BASE64.decode(key).is_err() {
+                    return Err(invalid(public_key, "the key is not valid base64"));
+                }
+
+                Ok(Pin::Key {
+                    algorithm: algorithm.to_owned(),
+                    key: key.to_owned(),
+                })
+            }
+            HostKey::Fingerprint(fingerprint) => {
+                let digest = fingerprint
+                    .strip_prefix("SHA256:")
+                    .ok_or_else(|| invalid(fingerprint, "expected a SHA256 fingerprint"))?;
+
+                match BASE64.decode(digest).map(<[u8; 32]>::try_from) {
+                    Ok(Ok(digest)) => Ok(Pin::Fingerprint(digest)),
+                    _ => Err(invalid(fingerprint, "the digest is not 32 bytes of base64")),
+                }
+            }
+        }
+    }
+}
+
+/// Write a known hosts file to `path` trusting exactly `keys` for every host.
+///
+/// Fingerprints are resolved by scanning the keys of `destination`, as resolved by `builder`.
+pub(crate) async fn write_known_hosts(
+    path: &Path,
+    keys: &[HostKey],
+    builder: &SessionBuilder,
+    destination: &str,
+) -> Result<(), Error> {
+    let mut lines = Vec::new();
+    let mut fingerprints = Vec::new();
+    for key in keys {
+        match key.to_pin()? {
+            Pin::Key { algorithm, key } => lines.push(known_hosts_line(&algorithm, &key)),
+            Pin::Fingerprint(digest) => fingerprints.push(digest),
+        }
+    }
+
+    if !fingerprints.is_empty() {
+        let scanned = scan(builder, destination).await?;
+        lines.extend(
+            scanned_keys(&scanned, &fingerprints)
+                .map(|(algorithm, key)| known_hosts_line(algorithm, key)),
+        );
+
+        // With an empty file, ssh would report the host key as unknown rather than changed.
+        if lines.is_empty() {
+            return Err(Error::ConnectFailed(ConnectError::HostKeyMismatch {
+                fingerprint: None,
+                known_hosts_line: None,
+            }));
+        }
+    }
+
+    // The keys match any host, since the file is only used for this session.
+    fs::write(path, lines.concat()).map_err(Error::Connect)
+}
+
+/// Run `ssh-keyscan` against the host `destination` resolves to and return its output.
+async fn scan(builder: &SessionBuilder, destination: &str) -> Result<String, Error> {
+    let config = builder.effective_config(destination).await?;
+    let host_name = config.host_name.as_deref().unwrap_or(destination);
+
+    let mut cmd = Command::new("ssh-keyscan");
+    cmd.stdin(Stdio::null()).stderr(Stdio::null());
+    if let Some(port) = config.port {
+        cmd.arg("-p").arg(port.to_string());
+    }
+    if let Some(timeout) = config.get("ConnectTimeout").filter(|t| *t != "none") {
+        cmd.arg("-T").arg(timeout);
+    }
+
+    let output = cmd.arg(host_name).output().await.map_err(Error::Connect)?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(Error::Connect(io::Error::new(
+            io::ErrorKind::Other,
+            format!("ssh-keyscan failed for {host_name}"),
+        )))
+    }
+}
+
+/// The keys in the output of `ssh-keyscan` whose fingerprints are among `fingerprints`.
+fn scanned_keys<'a>(
+    scanned: &'a str,
+    fingerprints: &'a [[u8; 32]],
+) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+    scanned.lines().filter_map(move |line| {
+        if line.starts_with('#') {
+            return None;
+        }
+        let mut parts = line.split_whitespace().skip(1);
+        let (algorithm, key) = (parts.next()?, parts.next()?);
+
+        let digest = Sha256::digest(BASE64.decode(key).ok()?);
+        fingerprints
+            .contains(&digest.into())
+            .then_some((algorithm, key))
+    })
+}
+
+fn known_hosts_line(algorithm: &str, key: &str) -> String {
+    format!("* {algorithm} {key}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIHNpXN6/WYkv8TKcQkcnptPRr9tR7zNSLIvLtFARlS4u";
+    const FINGERPRINT: &str = "SHA256:1+sStAd/KgbebOyJbt1eHWla0nl5XRGebt0J/hSa5vc";
+
+    #[test]
+    fn base64() {
+        assert_eq!(BASE64.decode("").unwrap(), b"");
+        assert_eq!(BASE64.decode("Zm8=").unwrap(), b"fo");
+        assert_eq!(BASE64.decode("Zm9v").unwrap(), b"foo");
+        assert_eq!(BASE64.decode("Zm9vYg").unwrap(), b"foob");
+        assert!(BASE64.decode("Zm9vY").is_err());
+        assert!(BASE64.decode("Zm9v!").is_err());
+    }
+
+    #[test]
+    fn pins() {
+        let key = HostKey::PublicKey(format!("ssh-ed25519 {KEY} test"));
+        match key.to_pin().unwrap() {
+            Pin::Key { algorithm, key } => {
+                assert_eq!(algorithm, "ssh-ed25519");
+                assert_eq!(key, KEY);
+            }
+            Pin::Fingerprint(_) => unreachable!(),
+        }
+
+        for key in [
+            HostKey::PublicKey(KEY.to_owned()),
+            HostKey::PublicKey("ssh-ed25519 not-base64".to_owned()),
+            HostKey::Fingerprint("MD5:ab:cd".to_owned()),
+            HostKey::Fingerprint("SHA256:Zm9v".to_owned()),
+        ] {
+            assert!(
+                matches!(key.to_pin(), Err(Error::InvalidHostKey { .. })),
+                "{:?}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn fingerprints() {
+        let digest = match HostKey::Fingerprint(FINGERPRINT.to_owned())
+            .to_pin()
+            .unwrap()
+        {
+            Pin::Fingerprint(digest) => digest,
+            Pin::Key { .. } => unreachable!(),
+        };
+
+        let scanned = format!(
+            "# 127.0.0.1:22 SSH-2.0-OpenSSH_9.2\n\
+             127.0.0.1 ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQ==\n\
+             127.0.0.1 ssh-ed25519 {KEY}\n"
+        );
+        let fingerprints = [digest];
+        let keys: Vec<_> = scanned_keys(&scanned, &fingerprints).collect();
+        assert_eq!(keys, [("ssh-ed25519", KEY)]);
+        assert_eq!(
+            known_hosts_line(keys[0].0, keys[0].1),
+            format!("* ssh-ed25519 {KEY}\n")
+        );
+    }
+}
@@ -16,7 +16,9 @@
 //! [established](Session::connect), and subsequent command invocations re-use the same connection.
 //!
 //! Note that the maximum number of multiplexed remote commands is 10 by default. This value can be
-//! increased by changing the `MaxSessions` setting in [`sshd_config`].
+//! increased by changing the `MaxSessions` setting in [`sshd_config`]. Additional remote commands
+//! fail (with [`Error::TooManySessions`] where this can be detected), unless
+//! [`SessionBuilder::max_sessions`] is set to make them wait for a free channel instead.
 //!
 //! Much like with [`std::process::Command`], you have multiple
 //! options when it comes to launching a remote command. You can
@@ -73,9 +75,13 @@
 //!
 //! # Authentication
 //!
-//! This library supports only password-less authentication schemes. If running `ssh` to a target
-//! host requires you to provide input on standard input (`STDIN`), then this crate will not work
-//! for you. You should set up keypair-based authentication instead.
+//! By default, this library supports only password-less authentication schemes, since `ssh` is
+//! run with `BatchMode=yes` and without any input on standard input (`STDIN`). You should set up
+//! keypair-based authentication if you can.
+//!
+//! If the host requires a password, a one-time password or the passphrase of a private key,
+//! register a callback with [`SessionBuilder::askpass`]. It is invoked through `SSH_ASKPASS`
+//! for every prompt issued by `ssh` while connecting.
 //!
 //! # Errors
 //!
@@ -86,6 +92,10 @@
 //! errors from this crate will almost necessarily be worse than those of a native SSH
 //! implementation. Sorry in advance :)
 //!
+//! Failures to establish the connection are reported as [`Error::ConnectFailed`] and carry a
+//! [`ConnectError`] describing the cause (e.g. an unknown host key or a failed authentication)
+//! that is extracted from the log of the ssh master.
+//!
 //! This also means that you may see strange errors when the remote process is terminated by a
 //! signal (such as through `kill` or `pkill`). When this happens, all the local ssh program sees
 //! is that the remote process disappeared, and so it returns with an error. It does not
@@ -166,7 +176,25 @@ mod session;
 pub use session::Session;
 
 mod builder;
-pub use builder::{ControlPersist, KnownHosts, SessionBuilder};
+pub use builder::{AlgorithmList, ControlPersist, KnownHosts, SessionBuilder};
+
+pub mod ssh_config;
+pub use ssh_config::SshConfig;
+
+mod host_key;
+pub use host_key::HostKey;
+
+mod pool;
+pub use pool::SessionPool;
+
+mod fleet;
+pub use fleet::{Fleet, FleetReport, FleetRun};
+
+mod reconnect;
+pub use reconnect::{ReconnectEvent, ReconnectPolicy, ReconnectingSession};
+
+mod askpass;
+pub use askpass::{Prompt, PromptKind, SecretString};
 
 mod command;
 pub use command::{OverSsh, OwningCommand};
@@ -175,13 +203,29 @@ pub type Command<'s> = OwningCommand<&'s Session>;
 
 mod escape;
 
+mod copy;
+
+mod sync;
+pub use sync::{SyncOptions, SyncReport};
+
+mod wrapper;
+
+mod pty;
+pub use pty::{PtyConfig, TerminalMode};
+
 mod child;
-pub use child::Child;
+pub use child::{Child, Signal};
 /// Convenience [`Child`] alias when working with a session reference.
 pub type RemoteChild<'a> = Child<&'a Session>;
 
+#[cfg(feature = "sftp")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sftp")))]
+pub mod sftp;
+#[cfg(feature = "sftp")]
+pub use sftp::Sftp;
+
 mod error;
-pub use error::Error;
+pub use error::{ConnectError, Error};
 
 #[cfg(feature = "process-mux")]
 pub(crate) mod process_impl;
@@ -196,6 +240,18 @@ pub mod changelog;
 mod port_forwarding;
 pub use port_forwarding::*;
 
+mod registry;
+pub use registry::{ChannelInfo, ForwardInfo};
+
+mod direct_stream;
+pub use direct_stream::DirectStream;
+
+#[cfg(feature = "http")]
+mod connector;
+#[cfg(feature = "http")]
+#[cfg_attr(docsrs, doc(cfg(feature = "http")))]
+pub use connector::HttpConnector;
+
 /// Types to create and interact with the Remote Process
 pub mod process {
     pub use super::{ChildStderr, ChildStdin, ChildStdout, Command, RemoteChild, Stdio};
@@ -2,19 +2,38 @@ use super::Error;
 
 use std::io;
 use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
 use std::process::ExitStatus;
 
-use openssh_mux_client::{EstablishedSession, SessionStatus};
+use openssh_mux_client::{Connection, EstablishedSession, SessionStatus};
 
 #[derive(Debug)]
 pub(crate) struct RemoteChild {
     established_session: EstablishedSession,
+    ctl: Box<Path>,
 }
 
 impl RemoteChild {
-    pub(crate) fn new(established_session: EstablishedSession) -> Self {
+    pub(crate) fn new(established_session: EstablishedSession, ctl: Box<Path>) -> Self {
         Self {
             established_session,
+            ctl,
+        }
+    }
+
+    /// Notify the ssh multiplex master that the size of the tty has changed.
+    pub(crate) async fn window_changed(&self) -> Result<(), Error> {
+        // This is exactly what the ssh multiplex client does: the master
+        // then sends the new size of every tty it got from its clients.
+        let pid = Connection::connect(&self.ctl)
+            .await?
+            .send_alive_check()
+            .await?;
+
+        if unsafe { libc::kill(pid.get() as libc::pid_t, libc::SIGWINCH) } == -1 {
+            Err(Error::ChildIo(io::Error::last_os_error()))
+        } else {
+            Ok(())
         }
     }
 
@@ -30,30 +49,37 @@ impl RemoteChild {
     }
 
     pub(crate) async fn wait(self) -> Result<ExitStatus, Error> {
-        let session_status = self
-            .established_session
-            .wait()
-            .await
-            .map_err(|(err, _established_session)| err)?;
-
-        match session_status {
-            SessionStatus::TtyAllocFail(_established_session) => {
-                unreachable!("native_mux_impl never allocates a tty")
-            }
-            SessionStatus::Exited { exit_value } => {
-                if let Some(val) = exit_value {
-                    if val == 127 {
-                        Err(Error::Remote(io::Error::new(
-                            io::ErrorKind::NotFound,
-                            "remote command not found",
-                        )))
-                    } else {
-                        Ok(ExitStatusExt::from_raw((val as i32) << 8))
-                    }
-                } else {
-                    Err(Error::RemoteProcessTerminated)
+        let mut established_session = self.established_session;
+
+        let exit_value = loop {
+            let session_status = established_session
+                .wait()
+                .await
+                .map_err(|(err, _established_session)| err)?;
+
+            match session_status {
+                // Like ssh, keep running the remote process without a tty.
+                SessionStatus::TtyAllocFail(session) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("the remote host failed to allocate a tty");
+
+                    established_session = session;
                 }
+                SessionStatus::Exited { exit_value } => break exit_value,
+            }
+        };
+
+        if let Some(val) = exit_value {
+            if val == 127 {
+                Err(Error::Remote(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "remote command not found",
+                )))
+            } else {
+                Ok(ExitStatusExt::from_raw((val as i32) << 8))
             }
+        } else {
+            Err(Error::RemoteProcessTerminated)
         }
     }
 }
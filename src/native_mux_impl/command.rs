@@ -1,10 +1,15 @@
+use super::stdio::Fd;
 use super::Error;
 use super::RemoteChild;
 use super::{ChildStderr, ChildStdin, ChildStdout, Stdio};
 
+use crate::pty::Tty;
+use crate::wrapper::Wrapper;
+
 use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
 
 use openssh_mux_client::{Connection, NonZeroByteSlice, Session};
@@ -33,6 +38,20 @@ impl Command {
         }
     }
 
+    /// The command line to run, or the name of the subsystem.
+    pub(crate) fn command_line(&self) -> &[u8] {
+        &self.cmd
+    }
+
+    pub(crate) fn is_subsystem(&self) -> bool {
+        self.subsystem
+    }
+
+    /// Create a new command that runs `cmd` on the same master.
+    pub(crate) fn sibling(&self, cmd: Vec<u8>) -> Self {
+        Self::new(self.ctl.clone(), cmd, false)
+    }
+
     pub(crate) fn raw_arg<S: AsRef<OsStr>>(&mut self, arg: S) {
         self.cmd.push(b' ');
         self.cmd.extend_from_slice(arg.as_ref().as_bytes());
@@ -52,6 +71,8 @@ impl Command {
 
     pub(crate) async fn spawn(
         &mut self,
+        wrapper: Wrapper<'_>,
+        tty: Option<Tty<'_>>,
     ) -> Result<
         (
             RemoteChild,
@@ -61,33 +82,52 @@ impl Command {
         ),
         Error,
     > {
-        let (stdin, child_stdin) = self.stdin_v.to_stdin()?;
-        let (stdout, child_stdout) = self.stdout_v.to_stdout()?;
         let (stderr, child_stderr) = self.stderr_v.to_stderr()?;
 
+        let ((stdin, child_stdin), (stdout, child_stdout)) = match tty {
+            // The master reads the terminal modes and size from stdin.
+            Some(tty) => {
+                let slave = tty.pty.slave().as_raw_fd();
+                ((Fd::Borrowed(slave), None), (Fd::Borrowed(slave), None))
+            }
+            None => (self.stdin_v.to_stdin()?, self.stdout_v.to_stdout()?),
+        };
+
         let stdios = [
             stdin.as_raw_fd_or_null_fd()?,
             stdout.as_raw_fd_or_null_fd()?,
             stderr.as_raw_fd_or_null_fd()?,
         ];
 
-        let cmd = NonZeroByteSlice::new(&self.cmd).ok_or(Error::InvalidCommand)?;
+        let cmd = wrapper.wrap(&self.cmd, self.subsystem)?;
+        let cmd = NonZeroByteSlice::new(&cmd).ok_or(Error::InvalidCommand)?;
 
         #[cfg(feature = "tracing")]
         tracing::debug!(cmd = String::from_utf8_lossy(cmd.into_inner()).as_ref());
 
+        let term = tty.and_then(|tty| NonZeroByteSlice::new(tty.term.as_bytes()));
+
         let session = Session::builder()
             .cmd(Cow::Borrowed(cmd))
             .subsystem(self.subsystem)
-            .build();
+            .tty(tty.is_some());
+        let session = match term {
+            Some(term) => session.term(Cow::Borrowed(term)).build(),
+            None => session.build(),
+        };
 
         let established_session = Connection::connect(&self.ctl)
             .await?
             .open_new_session(&session, &stdios)
             .await?;
 
+        if let Some(tty) = tty {
+            // The master has read the terminal modes by now.
+            tty.pty.make_raw().map_err(Error::ChildIo)?;
+        }
+
         Ok((
-            RemoteChild::new(established_session),
+            RemoteChild::new(established_session, self.ctl.clone()),
             child_stdin,
             child_stdout,
             child_stderr,
@@ -1,11 +1,16 @@
-use super::{Command, Error};
+use crate::port_forwarding::forwarding_args;
+
+use super::{Command, Error, ForwardType};
 
 use std::ffi::OsStr;
+use std::io;
 use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
+use std::process::Stdio;
 
 use openssh_mux_client::{shutdown_mux_master, Connection};
 use tempfile::TempDir;
+use tokio::process;
 
 #[derive(Debug)]
 pub(crate) struct Session {
@@ -54,17 +59,41 @@ impl Session {
         forward_type: crate::ForwardType,
         listen_socket: crate::Socket<'_>,
         connect_socket: crate::Socket<'_>,
-    ) -> Result<(), Error> {
+    ) -> Result<Option<u16>, Error> {
+        let forward_type = match forward_type {
+            crate::ForwardType::Local => ForwardType::Local,
+            // The master replies to remote forwardings on port 0 with the port the
+            // server allocated, which the mux client does not support, so let ssh
+            // request them and print the port.
+            crate::ForwardType::Remote if listen_socket.port() == Some(0) => {
+                let stdout = self
+                    .control_forward("forward", forward_type, listen_socket, connect_socket)
+                    .await?;
+                return match stdout.trim().parse() {
+                    Ok(port) => Ok(Some(port)),
+                    Err(_) => Err(Error::Ssh(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("ssh did not print the allocated port: {:?}", stdout.trim()),
+                    ))),
+                };
+            }
+            crate::ForwardType::Remote => ForwardType::Remote,
+            // The master acknowledges dynamic forwardings with a plain `Ok`, while
+            // the mux client expects the allocated port, so let ssh request them.
+            crate::ForwardType::Dynamic => {
+                return self
+                    .control_forward("forward", forward_type, listen_socket, connect_socket)
+                    .await
+                    .map(|_| None);
+            }
+        };
+
         Connection::connect(&self.ctl)
             .await?
-            .request_port_forward(
-                forward_type.into(),
-                &listen_socket.into(),
-                &connect_socket.into(),
-            )
+            .request_port_forward(forward_type, &listen_socket.into(), &connect_socket.into())
             .await?;
 
-        Ok(())
+        Ok(None)
     }
 
     pub(crate) async fn close_port_forward(
@@ -73,18 +102,61 @@ impl Session {
         listen_socket: crate::Socket<'_>,
         connect_socket: crate::Socket<'_>,
     ) -> Result<(), Error> {
+        let forward_type = match forward_type {
+            crate::ForwardType::Local => ForwardType::Local,
+            crate::ForwardType::Remote => ForwardType::Remote,
+            // The mux client cannot cancel dynamic forwardings, so let ssh do it.
+            crate::ForwardType::Dynamic => {
+                return self
+                    .control_forward("cancel", forward_type, listen_socket, connect_socket)
+                    .await
+                    .map(|_| ());
+            }
+        };
+
         Connection::connect(&self.ctl)
             .await?
-            .close_port_forward(
-                forward_type.into(),
-                &listen_socket.into(),
-                &connect_socket.into(),
-            )
+            .close_port_forward(forward_type, &listen_socket.into(), &connect_socket.into())
             .await?;
 
         Ok(())
     }
 
+    /// Request or cancel a forwarding with `ssh -O <operation>`, returning what ssh printed.
+    async fn control_forward(
+        &self,
+        operation: &str,
+        forward_type: crate::ForwardType,
+        listen_socket: crate::Socket<'_>,
+        connect_socket: crate::Socket<'_>,
+    ) -> Result<String, Error> {
+        let output = process::Command::new("ssh")
+            .stdin(Stdio::null())
+            .arg("-S")
+            .arg(&*self.ctl)
+            .args(["-O", operation])
+            .args(forwarding_args(
+                forward_type,
+                &listen_socket,
+                &connect_socket,
+            ))
+            // ssh ignores the destination, since it talks to the master at `-S`.
+            .arg("none")
+            .output()
+            .await
+            .map_err(Error::Ssh)?;
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(Error::Ssh(io::Error::new(
+                io::ErrorKind::Other,
+                stderr.trim(),
+            )))
+        }
+    }
+
     async fn close_impl(&self) -> Result<(), Error> {
         Connection::connect(&self.ctl)
             .await?
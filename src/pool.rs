@@ -0,0 +1,290 @@
+//! Pool of [`Session`]s, keyed by their destination.
+
+use super::{Error, Session, SessionBuilder};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+
+use tempfile::TempDir;
+use tokio::sync::{Mutex, Semaphore};
+
+#[derive(Debug)]
+struct Slot {
+    session: Option<Arc<Session>>,
+    last_used: Instant,
+}
+
+impl Slot {
+    /// Take the session out of the slot if it has not been handed out for
+    /// longer than `idle_timeout`.
+    fn take_idle(&mut self, idle_timeout: Duration) -> Option<Arc<Session>> {
+        let session = self.session.as_ref()?;
+
+        if Arc::strong_count(session) > 1 {
+            // The session is still in use, so it only becomes idle once the
+            // last handle to it is dropped.
+            self.last_used = Instant::now();
+            None
+        } else if self.last_used.elapsed() >= idle_timeout {
+            self.session.take()
+        } else {
+            None
+        }
+    }
+}
+
+/// A pool of [`Session`]s to many hosts, which connects to each destination
+/// on first use and then shares the connection.
+///
+/// Sessions are handed out as `Arc<Session>`, so that they can be used with
+/// [`Session::arc_command`] and friends. Before a pooled session is handed
+/// out, its master connection is verified with [`Session::check`], and a new
+/// connection is established if it died.
+///
+/// Sessions that have not been handed out for longer than the
+/// [idle timeout](SessionPool::idle_timeout) and are no longer in use are
+/// closed whenever the pool is used, or explicitly by
+/// [`evict_idle`](SessionPool::evict_idle).
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "process-mux")]
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), openssh::Error> {
+/// use openssh::{KnownHosts, SessionBuilder, SessionPool};
+///
+/// let mut builder = SessionBuilder::default();
+/// builder.known_hosts_check(KnownHosts::Strict);
+///
+/// let pool = SessionPool::new(builder);
+///
+/// for host in ["me@host1.example.com", "me@host2.example.com"] {
+///     let session = pool.get(host).await?;
+///     let uptime = session.arc_command("uptime").output().await?;
+///     eprintln!("{host}: {}", String::from_utf8_lossy(&uptime.stdout));
+/// }
+///
+/// pool.close().await?;
+/// # Ok(()) }
+/// ```
+pub struct SessionPool {
+    builder: SessionBuilder,
+    new_session: fn(TempDir) -> Session,
+    idle_timeout: Duration,
+    connects: Semaphore,
+    slots: StdMutex<HashMap<String, Arc<Mutex<Slot>>>>,
+}
+
+impl fmt::Debug for SessionPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let destinations: Vec<String> = self.lock_slots().keys().cloned().collect();
+
+        f.debug_struct("SessionPool")
+            .field("builder", &self.builder)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("destinations", &destinations)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionPool {
+    /// Default of [`SessionPool::idle_timeout`].
+    pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+    /// Default of [`SessionPool::max_concurrent_connects`].
+    pub const DEFAULT_MAX_CONCURRENT_CONNECTS: usize = 16;
+
+    /// Create a pool connecting to destinations with `builder`, using the
+    /// process implementation (see [`SessionBuilder::connect`]).
+    #[cfg(feature = "process-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
+    pub fn new(builder: SessionBuilder) -> Self {
+        Self::with_session(builder, Session::new_process_mux)
+    }
+
+    /// Create a pool connecting to destinations with `builder`, using the
+    /// native mux implementation (see [`SessionBuilder::connect_mux`]).
+    #[cfg(feature = "native-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
+    pub fn new_mux(builder: SessionBuilder) -> Self {
+        Self::with_session(builder, Session::new_native_mux)
+    }
+
+    fn with_session(builder: SessionBuilder, new_session: fn(TempDir) -> Session) -> Self {
+        Self {
+            builder,
+            new_session,
+            idle_timeout: Self::DEFAULT_IDLE_TIMEOUT,
+            connects: Semaphore::new(Self::DEFAULT_MAX_CONCURRENT_CONNECTS),
+            slots: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set how long a session may be unused before it is closed.
+    ///
+    /// Defaults to [`SessionPool::DEFAULT_IDLE_TIMEOUT`].
+    pub fn idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Set how many connections may be established at the same time.
+    ///
+    /// Further calls to [`get`](SessionPool::get) that need to connect wait
+    /// until one of the pending connects finished.
+    ///
+    /// Defaults to [`SessionPool::DEFAULT_MAX_CONCURRENT_CONNECTS`].
+    ///
+    /// # Panics
+    ///
+    /// If `max` is `0`.
+    pub fn max_concurrent_connects(&mut self, max: usize) -> &mut Self {
+        assert_ne!(max, 0, "max_concurrent_connects must not be 0");
+        self.connects = Semaphore::new(max);
+        self
+    }
+
+    fn lock_slots(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<Mutex<Slot>>>> {
+        // The map is never left in an inconsistent state, so it is fine to
+        // ignore poisoning.
+        self.slots.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Get the session to `destination`, connecting to it if there is no
+    /// session yet or if the pooled session is no longer connected.
+    ///
+    /// `destination` is passed to [`SessionBuilder::connect`] as is, and is
+    /// also the key sessions are pooled by.
+    pub async fn get(&self, destination: &str) -> Result<Arc<Session>, Error> {
+        self.evict_idle().await;
+
+        let slot = self
+            .lock_slots()
+            .entry(destination.to_owned())
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(Slot {
+                    session: None,
+                    last_used: Instant::now(),
+                }))
+            })
+            .clone();
+
+        // Holding the lock of the slot makes concurrent calls for the same
+        // destination share a single connect.
+        let mut slot = slot.lock().await;
+
+        if let Some(session) = slot.session.take() {
+            if session.check().await.is_ok() {
+                slot.session = Some(session.clone());
+                slot.last_used = Instant::now();
+                return Ok(session);
+            }
+
+            // The master is dead, so closing it can only fail.
+            if let Ok(session) = Arc::try_unwrap(session) {
+                session.close().await.ok();
+            }
+        }
+
+        let session = {
+            let _permit = self
+                .connects
+                .acquire()
+                .await
+                .expect("the semaphore is never closed");
+
+            let session = self
+                .builder
+                .connect_impl(destination, self.new_session)
+                .await?;
+            Arc::new(session)
+        };
+
+        slot.session = Some(session.clone());
+        slot.last_used = Instant::now();
+        Ok(session)
+    }
+
+    /// Close all sessions that are no longer in use and have not been handed
+    /// out for longer than the [idle timeout](SessionPool::idle_timeout).
+    ///
+    /// Returns the number of sessions closed. Errors while closing them are
+    /// ignored, since their connections are no longer used.
+    pub async fn evict_idle(&self) -> usize {
+        let mut evicted = Vec::new();
+
+        self.lock_slots().retain(|_, slot| {
+            // Slots that are shared or locked are being used right now.
+            if Arc::strong_count(slot) > 1 {
+                return true;
+            }
+            let mut slot = match slot.try_lock() {
+                Ok(slot) => slot,
+                Err(_) => return true,
+            };
+            evicted.extend(slot.take_idle(self.idle_timeout));
+            slot.session.is_some()
+        });
+
+        let count = evicted.len();
+        for session in evicted {
+            if let Ok(session) = Arc::try_unwrap(session) {
+                session.close().await.ok();
+            }
+        }
+        count
+    }
+
+    /// Close all sessions in the pool.
+    ///
+    /// Sessions that are still in use are closed once the last `Arc` to them
+    /// is dropped instead.
+    ///
+    /// Returns the first error encountered while closing the sessions.
+    pub async fn close(self) -> Result<(), Error> {
+        let slots = self
+            .slots
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner());
+
+        let mut res = Ok(());
+        for (_, slot) in slots {
+            let session = slot.lock().await.session.take();
+            if let Some(Ok(session)) = session.map(Arc::try_unwrap) {
+                let closed = session.close().await;
+                if res.is_ok() {
+                    res = closed;
+                }
+            }
+        }
+        res
+    }
+}
+
+#[cfg(all(test, feature = "process-mux"))]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    #[test]
+    fn idle_slots() {
+        let session = Session::resume(Path::new("/nonexistent").into(), None);
+        let mut slot = Slot {
+            session: Some(Arc::new(session)),
+            last_used: Instant::now(),
+        };
+
+        assert!(slot.take_idle(Duration::from_secs(60)).is_none());
+
+        // Sessions that are still in use are never idle.
+        let in_use = slot.session.clone().unwrap();
+        assert!(slot.take_idle(Duration::ZERO).is_none());
+        drop(in_use);
+
+        assert!(slot.take_idle(Duration::ZERO).is_some());
+        assert!(slot.session.is_none());
+        assert!(slot.take_idle(Duration::ZERO).is_none());
+    }
+}
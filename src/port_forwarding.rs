@@ -1,13 +1,17 @@
 #[cfg(feature = "native-mux")]
 use super::native_mux_impl;
-
-#[cfg(feature = "process-mux")]
-use std::ffi::OsStr;
+use super::{Error, Session};
 
 use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
+use std::io;
 use std::net::{self, SocketAddr};
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+
+use tokio::runtime::Handle;
 
 /// Type of forwarding
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -17,18 +21,34 @@ pub enum ForwardType {
 
     /// Forward requests to a port on the remote machine to local machine.
     Remote,
+
+    /// Run a SOCKS4/SOCKS5 proxy on a port on the local machine, which connects to the requested
+    /// destinations from the remote machine (`ssh -D`).
+    ///
+    /// The `connect_socket` of the port forwarding is ignored, since the destination is chosen
+    /// by the SOCKS client.
+    Dynamic,
 }
 
-#[cfg(feature = "native-mux")]
-impl From<ForwardType> for native_mux_impl::ForwardType {
-    fn from(fwd_type: ForwardType) -> Self {
-        use native_mux_impl::ForwardType::*;
+/// The option of `ssh` setting up the port forwarding, followed by its value.
+pub(crate) fn forwarding_args(
+    forward_type: ForwardType,
+    listen_socket: &Socket<'_>,
+    connect_socket: &Socket<'_>,
+) -> [OsString; 2] {
+    let (flag, connect_socket) = match forward_type {
+        ForwardType::Local => ("-L", Some(connect_socket)),
+        ForwardType::Remote => ("-R", Some(connect_socket)),
+        ForwardType::Dynamic => ("-D", None),
+    };
 
-        match fwd_type {
-            ForwardType::Local => Local,
-            ForwardType::Remote => Remote,
-        }
+    let mut forwarding = listen_socket.as_os_str().into_owned();
+    if let Some(connect_socket) = connect_socket {
+        forwarding.push(":");
+        forwarding.push(connect_socket.as_os_str());
     }
+
+    [flag.into(), forwarding]
 }
 
 /// TCP/Unix socket
@@ -116,7 +136,79 @@ impl Socket<'_> {
         }
     }
 
-    #[cfg(feature = "process-mux")]
+    /// Clone borrowed data so that the socket no longer borrows anything.
+    pub(crate) fn into_owned(self) -> Socket<'static> {
+        match self {
+            #[cfg(unix)]
+            Socket::UnixSocket { path } => Socket::UnixSocket {
+                path: Cow::Owned(path.into_owned()),
+            },
+            Socket::TcpSocket { host, port } => Socket::TcpSocket {
+                host: Cow::Owned(host.into_owned()),
+                port,
+            },
+        }
+    }
+
+    /// Return the port of a tcp socket, or `None` for a unix socket.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            #[cfg(unix)]
+            Socket::UnixSocket { .. } => None,
+            Socket::TcpSocket { port, .. } => Some(*port),
+        }
+    }
+
+    /// Replace port 0 of a tcp socket with `port`.
+    pub(crate) fn with_allocated_port(self, port: u16) -> Self {
+        match self {
+            Socket::TcpSocket { host, port: 0 } => Socket::TcpSocket { host, port },
+            socket => socket,
+        }
+    }
+
+    /// Pick a free port for a tcp socket on the local machine with port 0.
+    ///
+    /// Unlike for remote forwardings, ssh does not report the port it bound
+    /// for local forwardings on port 0, so a free port is chosen up front by
+    /// binding to it and releasing it again. Another process may take the port
+    /// in between, see [`is_taken`](Self::is_taken).
+    pub(crate) fn allocate_local_port(self) -> Result<Self, Error> {
+        let port = match self.bind_addr() {
+            Some((host, 0)) => net::TcpListener::bind((host, 0))
+                .and_then(|listener| listener.local_addr())
+                .map_err(Error::LocalIo)?
+                .port(),
+            _ => return Ok(self),
+        };
+
+        Ok(self.with_allocated_port(port))
+    }
+
+    /// Return `true` if the port of a tcp socket on the local machine is in use.
+    pub(crate) fn is_taken(&self) -> bool {
+        match self.bind_addr() {
+            Some((host, port)) => matches!(
+                net::TcpListener::bind((host, port)),
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse
+            ),
+            None => false,
+        }
+    }
+
+    /// The address ssh binds to for a tcp socket on the local machine.
+    fn bind_addr(&self) -> Option<(&str, u16)> {
+        match self {
+            // ssh binds to all interfaces for an empty address or `*`.
+            Socket::TcpSocket { host, port } => match &**host {
+                "" | "*" => Some(("0.0.0.0", *port)),
+                host => Some((host.trim_start_matches('[').trim_end_matches(']'), *port)),
+            },
+            #[cfg(unix)]
+            Socket::UnixSocket { .. } => None,
+        }
+    }
+
     pub(crate) fn as_os_str(&self) -> Cow<'_, OsStr> {
         match self {
             #[cfg(unix)]
@@ -153,3 +245,186 @@ impl<'a> fmt::Display for Socket<'a> {
         }
     }
 }
+
+/// A port forwarding that is closed once it is dropped, created by
+/// [`Session::port_forward`] and friends.
+///
+/// The guard remembers the arguments the forwarding was requested with, so
+/// that it can be closed without repeating them. It owns `S`, a pointer to the
+/// [`Session`], so that e.g. a `PortForward<Arc<Session>>` can be moved into a
+/// task to keep the forwarding open for as long as the task needs it.
+///
+/// Prefer [`close`](PortForward::close) over dropping the guard: dropping it
+/// closes the forwarding by running `ssh -O cancel` in the background, so the
+/// forwarding may still be open for a moment, and ignores any errors. Outside
+/// of a tokio runtime, dropping it blocks the current thread instead.
+#[derive(Debug)]
+pub struct PortForward<S: Deref<Target = Session>> {
+    session: S,
+    forward_type: ForwardType,
+    listen_socket: Socket<'static>,
+    /// `listen_socket` as passed to ssh when requesting the forwarding.
+    requested_listen_socket: Socket<'static>,
+    connect_socket: Socket<'static>,
+    closed: bool,
+}
+
+impl<S: Deref<Target = Session>> PortForward<S> {
+    pub(crate) fn new(
+        session: S,
+        forward_type: ForwardType,
+        listen_socket: Socket<'static>,
+        requested_listen_socket: Socket<'static>,
+        connect_socket: Socket<'static>,
+    ) -> Self {
+        Self {
+            session,
+            forward_type,
+            listen_socket,
+            requested_listen_socket,
+            connect_socket,
+            closed: false,
+        }
+    }
+
+    /// The session the port forwarding was requested on.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// The type of the port forwarding.
+    pub fn forward_type(&self) -> ForwardType {
+        self.forward_type
+    }
+
+    /// The socket the port forwarding listens on, with port 0 replaced by the
+    /// allocated port, see [`Session::request_port_forward`].
+    pub fn listen_socket(&self) -> &Socket<'static> {
+        &self.listen_socket
+    }
+
+    /// The socket the port forwarding connects to.
+    ///
+    /// Meaningless for [`ForwardType::Dynamic`].
+    pub fn connect_socket(&self) -> &Socket<'static> {
+        &self.connect_socket
+    }
+
+    /// Close the port forwarding, see [`Session::close_port_forward`].
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.closed = true;
+
+        self.session
+            .close_port_forward(
+                self.forward_type,
+                self.requested_listen_socket.clone(),
+                self.connect_socket.clone(),
+            )
+            .await
+    }
+}
+
+impl<S: Deref<Target = Session>> Drop for PortForward<S> {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+
+        let mut cmd = std::process::Command::new("ssh");
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .arg("-S")
+            .arg(self.session.control_socket())
+            .args(["-o", "BatchMode=yes", "-O", "cancel"])
+            .args(forwarding_args(
+                self.forward_type,
+                &self.requested_listen_socket,
+                &self.connect_socket,
+            ))
+            // ssh ignores the destination, since it talks to the master at `-S`.
+            .arg("none");
+
+        let registry = self.session.registry().clone();
+        let forward_type = self.forward_type;
+        let listen_socket = self.requested_listen_socket.clone();
+        let cancelled = move |res: io::Result<ExitStatus>| {
+            #[cfg(feature = "tracing")]
+            if let Err(err) = &res {
+                tracing::error!("Closing port forwarding failed: {}", err);
+            }
+
+            if matches!(res, Ok(status) if status.success()) {
+                registry.remove_forward(forward_type, &listen_socket);
+            }
+        };
+
+        // Guards are usually dropped in async code, where waiting for ssh
+        // would block the runtime.
+        match Handle::try_current() {
+            Ok(handle) => {
+                let mut cmd = tokio::process::Command::from(cmd);
+                handle.spawn(async move { cancelled(cmd.status().await) });
+            }
+            Err(_) => cancelled(cmd.status()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarding_args() {
+        let listen = Socket::new("127.0.0.1", 8080);
+        let connect = Socket::new("localhost", 80);
+
+        assert_eq!(
+            super::forwarding_args(ForwardType::Local, &listen, &connect),
+            ["-L", "127.0.0.1:8080:localhost:80"]
+        );
+        assert_eq!(
+            super::forwarding_args(ForwardType::Remote, &listen, &connect),
+            ["-R", "127.0.0.1:8080:localhost:80"]
+        );
+        assert_eq!(
+            super::forwarding_args(ForwardType::Dynamic, &listen, &connect),
+            ["-D", "127.0.0.1:8080"]
+        );
+    }
+
+    #[test]
+    fn allocate_local_port() {
+        let socket = Socket::new("127.0.0.1", 0).allocate_local_port().unwrap();
+        assert_ne!(socket.port(), Some(0));
+        // The port is free again, so ssh can bind to it.
+        net::TcpListener::bind(("127.0.0.1", socket.port().unwrap())).unwrap();
+
+        let socket = Socket::new("127.0.0.1", 2222);
+        assert_eq!(socket.clone().allocate_local_port().unwrap(), socket);
+    }
+
+    #[test]
+    fn taken_port() {
+        let socket = Socket::new("127.0.0.1", 0).allocate_local_port().unwrap();
+        assert!(!socket.is_taken());
+
+        // Someone else binds the port before ssh does.
+        let listener = net::TcpListener::bind(("127.0.0.1", socket.port().unwrap())).unwrap();
+        assert!(socket.is_taken());
+        drop(listener);
+        assert!(!socket.is_taken());
+
+        assert!(!Socket::from(Path::new("/tmp/socket")).is_taken());
+    }
+
+    #[test]
+    fn into_owned() {
+        let host = String::from("localhost");
+        let socket = Socket::new(&*host, 22).into_owned();
+        drop(host);
+
+        assert_eq!(socket, Socket::new("localhost", 22));
+    }
+}
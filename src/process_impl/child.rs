@@ -24,6 +24,18 @@ impl RemoteChild {
         Ok(())
     }
 
+    /// Notify ssh that the size of its tty has changed.
+    pub(crate) async fn window_changed(&self) -> Result<(), Error> {
+        // The ssh multiplex client relays SIGWINCH to the master.
+        if let Some(pid) = self.channel.id() {
+            if unsafe { libc::kill(pid as libc::pid_t, libc::SIGWINCH) } == -1 {
+                return Err(Error::ChildIo(io::Error::last_os_error()));
+            }
+        }
+
+        Ok(())
+    }
+
     pub(crate) async fn wait(mut self) -> Result<ExitStatus, Error> {
         match self.channel.wait().await {
             Err(e) => Err(Error::Remote(e)),
@@ -1,46 +1,80 @@
+use super::session::new_std_cmd;
 use super::Error;
 use super::RemoteChild;
 use super::{ChildStderr, ChildStdin, ChildStdout};
 
-use std::ffi::OsStr;
-use std::process::Stdio;
+use crate::pty::Tty;
+use crate::wrapper::{Env, Wrapper};
+use crate::Stdio;
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+use std::process::Stdio as StdStdio;
 
 use tokio::process;
 
 #[derive(Debug)]
 pub(crate) struct Command {
-    builder: process::Command,
+    cmd: Vec<u8>,
+    ctl: Box<Path>,
+    subsystem: bool,
+
+    stdin_v: Stdio,
+    stdout_v: Stdio,
+    stderr_v: Stdio,
 }
 
 impl Command {
-    pub(crate) fn new(mut builder: process::Command) -> Self {
-        // Disconnects the ssh session at `RemoteChild::drop`, but does
-        // not kill the remote process.
-        builder.kill_on_drop(true);
+    pub(crate) fn new(ctl: Box<Path>, cmd: Vec<u8>, subsystem: bool) -> Self {
+        Self {
+            cmd,
+            ctl,
+            subsystem,
+
+            stdin_v: Stdio::inherit(),
+            stdout_v: Stdio::inherit(),
+            stderr_v: Stdio::inherit(),
+        }
+    }
+
+    /// The command line to run, or the name of the subsystem.
+    pub(crate) fn command_line(&self) -> &[u8] {
+        &self.cmd
+    }
+
+    pub(crate) fn is_subsystem(&self) -> bool {
+        self.subsystem
+    }
 
-        Self { builder }
+    /// Create a new command that runs `cmd` on the same master.
+    pub(crate) fn sibling(&self, cmd: Vec<u8>) -> Self {
+        Self::new(self.ctl.clone(), cmd, false)
     }
 }
 
 impl Command {
     pub(crate) fn raw_arg<S: AsRef<OsStr>>(&mut self, arg: S) {
-        self.builder.arg(arg);
+        self.cmd.push(b' ');
+        self.cmd.extend_from_slice(arg.as_ref().as_bytes());
     }
 
     pub(crate) fn stdin<T: Into<Stdio>>(&mut self, cfg: T) {
-        self.builder.stdin(cfg);
+        self.stdin_v = cfg.into();
     }
 
     pub(crate) fn stdout<T: Into<Stdio>>(&mut self, cfg: T) {
-        self.builder.stdout(cfg);
+        self.stdout_v = cfg.into();
     }
 
     pub(crate) fn stderr<T: Into<Stdio>>(&mut self, cfg: T) {
-        self.builder.stderr(cfg);
+        self.stderr_v = cfg.into();
     }
 
     pub(crate) async fn spawn(
         &mut self,
+        mut wrapper: Wrapper<'_>,
+        tty: Option<Tty<'_>>,
     ) -> Result<
         (
             RemoteChild,
@@ -50,10 +84,72 @@ impl Command {
         ),
         Error,
     > {
+        // Prefer transmitting the environment over the ssh protocol and
+        // only fall back to the remote shell if that is not possible.
+        let set_env = wrapper.env.and_then(Env::to_send).map(|vars| {
+            let mut set_env = Vec::new();
+            for (key, val) in vars {
+                set_env.push(OsStr::new("-o").to_os_string());
+                set_env.push(set_env_option(key, val));
+            }
+            set_env
+        });
+        if set_env.is_some() {
+            wrapper.env = None;
+        }
+
+        let cmd = wrapper.wrap(&self.cmd, self.subsystem)?;
+
+        // NOTE: we pass -p 9 nine here (the "discard" port) to ensure that ssh does not
+        // succeed in establishing a _new_ connection if the master connection has failed.
+        let mut args = vec!["-p", "9"];
+        if tty.is_some() {
+            // Disable the escape character, so that all input is passed
+            // through unaltered.
+            args.extend_from_slice(&["-tt", "-e", "none"]);
+        } else {
+            args.push("-T");
+        }
+        if self.subsystem {
+            args.push("-s");
+        }
+
+        // The `ssh` command is created on every spawn, since the remote command
+        // is only known at this point.
+        let mut builder: process::Command = new_std_cmd(&self.ctl, &args).into();
+        builder
+            .args(set_env.iter().flatten())
+            .arg("--")
+            .arg(OsStr::from_bytes(&cmd))
+            .stderr(self.stderr_v.try_clone_std().map_err(Error::ChildIo)?)
+            // Disconnects the ssh session at `RemoteChild::drop`, but does
+            // not kill the remote process.
+            .kill_on_drop(true);
+
+        if let Some(tty) = tty {
+            // ssh reads the terminal modes and size from its stdin and puts
+            // it into raw mode by itself.
+            let slave = || {
+                tty.pty
+                    .slave()
+                    .try_clone_to_owned()
+                    .map(StdStdio::from)
+                    .map_err(Error::ChildIo)
+            };
+            builder
+                .stdin(slave()?)
+                .stdout(slave()?)
+                .env("TERM", tty.term);
+        } else {
+            builder
+                .stdin(self.stdin_v.try_clone_std().map_err(Error::ChildIo)?)
+                .stdout(self.stdout_v.try_clone_std().map_err(Error::ChildIo)?);
+        }
+
         #[cfg(feature = "tracing")]
-        tracing::debug!(cmd = ?self.builder.as_std());
+        tracing::debug!(cmd = ?builder.as_std());
 
-        let mut channel = self.builder.spawn().map_err(Error::Ssh)?;
+        let mut channel = builder.spawn().map_err(Error::Ssh)?;
 
         let child_stdin = channel.stdin.take();
         let child_stdout = channel.stdout.take();
@@ -67,3 +163,29 @@ impl Command {
         ))
     }
 }
+
+/// Build a `SetEnv=K=V` option, quoted the way the ssh config parser expects.
+fn set_env_option(key: &OsStr, val: &OsStr) -> OsString {
+    let mut option = b"SetEnv=\"".to_vec();
+    for byte in key.as_bytes().iter().chain(b"=").chain(val.as_bytes()) {
+        if matches!(byte, b'"' | b'\\' | b'\'') {
+            option.push(b'\\');
+        }
+        option.push(*byte);
+    }
+    option.push(b'"');
+    OsString::from_vec(option)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_env_quoting() {
+        assert_eq!(
+            set_env_option(OsStr::new("A"), OsStr::new(r#"a "b" \c' d"#)),
+            r#"SetEnv="A=a \"b\" \\c\' d""#
+        );
+    }
+}
@@ -1,8 +1,11 @@
+use crate::port_forwarding::forwarding_args;
+
 use super::{Command, Error, ForwardType, Socket};
 
 use std::ffi::OsStr;
 use std::fs;
 use std::io;
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 use std::process::Stdio;
 
@@ -10,6 +13,22 @@ use tokio::process;
 
 use tempfile::TempDir;
 
+/// Create a `ssh` command that talks to the master listening on `ctl`.
+pub(super) fn new_std_cmd(ctl: &Path, args: &[impl AsRef<OsStr>]) -> std::process::Command {
+    let mut cmd = std::process::Command::new("ssh");
+    cmd.stdin(Stdio::null())
+        .arg("-S")
+        .arg(ctl)
+        .arg("-o")
+        .arg("BatchMode=yes")
+        .args(args)
+        // ssh does not care about the addr as long as we have passed
+        // `-S &*self.ctl`.
+        // It is tested on OpenSSH 8.2p1, 8.9p1, 9.0p1
+        .arg("none");
+    cmd
+}
+
 #[derive(Debug)]
 pub(crate) struct Session {
     tempdir: Option<TempDir>,
@@ -38,18 +57,7 @@ impl Session {
     }
 
     fn new_std_cmd(&self, args: &[impl AsRef<OsStr>]) -> std::process::Command {
-        let mut cmd = std::process::Command::new("ssh");
-        cmd.stdin(Stdio::null())
-            .arg("-S")
-            .arg(&*self.ctl)
-            .arg("-o")
-            .arg("BatchMode=yes")
-            .args(args)
-            // ssh does not care about the addr as long as we have passed
-            // `-S &*self.ctl`.
-            // It is tested on OpenSSH 8.2p1, 8.9p1, 9.0p1
-            .arg("none");
-        cmd
+        new_std_cmd(&self.ctl, args)
     }
 
     fn new_cmd(&self, args: &[impl AsRef<OsStr>]) -> process::Command {
@@ -80,26 +88,12 @@ impl Session {
 
     pub(crate) fn raw_command<S: AsRef<OsStr>>(&self, program: S) -> Command {
         // XXX: Should we do a self.check() here first?
-
-        // NOTE: we pass -p 9 nine here (the "discard" port) to ensure that ssh does not
-        // succeed in establishing a _new_ connection if the master connection has failed.
-
-        let mut cmd = self.new_cmd(&["-T", "-p", "9"]);
-        cmd.arg("--").arg(program);
-
-        Command::new(cmd)
+        Command::new(self.ctl.clone(), program.as_ref().as_bytes().into(), false)
     }
 
     pub(crate) fn subsystem<S: AsRef<OsStr>>(&self, program: S) -> Command {
         // XXX: Should we do a self.check() here first?
-
-        // NOTE: we pass -p 9 nine here (the "discard" port) to ensure that ssh does not
-        // succeed in establishing a _new_ connection if the master connection has failed.
-
-        let mut cmd = self.new_cmd(&["-T", "-p", "9", "-s"]);
-        cmd.arg("--").arg(program);
-
-        Command::new(cmd)
+        Command::new(self.ctl.clone(), program.as_ref().as_bytes().into(), true)
     }
 
     pub(crate) async fn request_port_forward(
@@ -107,24 +101,37 @@ impl Session {
         forward_type: ForwardType,
         listen_socket: Socket<'_>,
         connect_socket: Socket<'_>,
-    ) -> Result<(), Error> {
-        let flag = match forward_type {
-            ForwardType::Local => OsStr::new("-L"),
-            ForwardType::Remote => OsStr::new("-R"),
+    ) -> Result<Option<u16>, Error> {
+        let [flag, forwarding] = forwarding_args(forward_type, &listen_socket, &connect_socket);
+
+        // Only `-O forward` prints the port the server allocated for a remote
+        // forwarding on port 0.
+        let allocates = forward_type == ForwardType::Remote && listen_socket.port() == Some(0);
+        let mode: &[&OsStr] = if allocates {
+            &[OsStr::new("-O"), OsStr::new("forward")]
+        } else {
+            &[OsStr::new("-fNT")]
         };
 
-        let mut forwarding = listen_socket.as_os_str().into_owned();
-        forwarding.push(":");
-        forwarding.push(connect_socket.as_os_str());
-
         let port_forwarding = self
-            .new_cmd(&[OsStr::new("-fNT"), flag, &*forwarding])
+            .new_cmd(&[mode, &[&*flag, &*forwarding]].concat())
             .output()
             .await
             .map_err(Error::Ssh)?;
 
         if port_forwarding.status.success() {
-            Ok(())
+            if !allocates {
+                return Ok(None);
+            }
+
+            let stdout = String::from_utf8_lossy(&port_forwarding.stdout);
+            match stdout.trim().parse() {
+                Ok(port) => Ok(Some(port)),
+                Err(_) => Err(Error::Ssh(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("ssh did not print the allocated port: {:?}", stdout.trim()),
+                ))),
+            }
         } else {
             let exit_err = String::from_utf8_lossy(&port_forwarding.stderr);
             let err = exit_err.trim();
@@ -145,17 +152,10 @@ impl Session {
         listen_socket: Socket<'_>,
         connect_socket: Socket<'_>,
     ) -> Result<(), Error> {
-        let flag = match forward_type {
-            ForwardType::Local => OsStr::new("-L"),
-            ForwardType::Remote => OsStr::new("-R"),
-        };
-
-        let mut forwarding = listen_socket.as_os_str().into_owned();
-        forwarding.push(":");
-        forwarding.push(connect_socket.as_os_str());
+        let [flag, forwarding] = forwarding_args(forward_type, &listen_socket, &connect_socket);
 
         let port_forwarding = self
-            .new_cmd(&[OsStr::new("-O"), OsStr::new("cancel"), flag, &*forwarding])
+            .new_cmd(&[OsStr::new("-O"), OsStr::new("cancel"), &flag, &forwarding])
             .output()
             .await
             .map_err(Error::Ssh)?;
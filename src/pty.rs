@@ -0,0 +1,392 @@
+//! Pseudo-terminals for remote processes.
+//!
+//! Neither `ssh` nor its multiplex master allow the terminal size and modes
+//! to be passed explicitly, instead they are read from the tty that is passed
+//! as stdin. So a local pty is created and configured for every remote
+//! process, its slave side is handed to ssh, while the master side becomes
+//! the stdin and stdout of [`Child`](crate::Child).
+
+use super::Signal;
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::raw::c_int;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+
+use libc::{termios, winsize};
+
+/// Configuration of the pseudo-terminal requested for a remote process, see
+/// [`OwningCommand::pty`](crate::OwningCommand::pty).
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PtyConfig {
+    /// Value of the `TERM` environment variable of the remote process.
+    pub term: String,
+    /// Width of the terminal in characters.
+    pub cols: u16,
+    /// Height of the terminal in rows.
+    pub rows: u16,
+    /// Terminal modes that are changed from the defaults of the local system.
+    ///
+    /// The value of a flag is `0` to unset it and anything else to set it,
+    /// the value of a control character is the character itself.
+    pub modes: Vec<(TerminalMode, u32)>,
+}
+
+impl Default for PtyConfig {
+    /// An 80x24 `xterm-256color` terminal.
+    fn default() -> Self {
+        Self {
+            term: "xterm-256color".to_owned(),
+            cols: 80,
+            rows: 24,
+            modes: Vec::new(),
+        }
+    }
+}
+
+/// Terminal modes, as described in section 8 of [RFC 4254].
+///
+/// [RFC 4254]: https://www.rfc-editor.org/rfc/rfc4254#section-8
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+#[allow(missing_docs)]
+pub enum TerminalMode {
+    // Control characters.
+    Vintr,
+    Vquit,
+    Verase,
+    Vkill,
+    Veof,
+    Veol,
+    Vstart,
+    Vstop,
+    Vsusp,
+    Vwerase,
+    Vlnext,
+
+    // Input flags.
+    Ignpar,
+    Parmrk,
+    Inpck,
+    Istrip,
+    Inlcr,
+    Igncr,
+    Icrnl,
+    Ixon,
+    Ixany,
+    Ixoff,
+    Imaxbel,
+
+    // Local flags.
+    Isig,
+    Icanon,
+    Echo,
+    Echoe,
+    Echok,
+    Echonl,
+    Noflsh,
+    Tostop,
+    Iexten,
+    Echoctl,
+    Echoke,
+
+    // Output flags.
+    Opost,
+    Onlcr,
+    Ocrnl,
+    Onocr,
+    Onlret,
+
+    // Control flags.
+    Cs7,
+    Cs8,
+    Parenb,
+    Parodd,
+}
+
+enum Flag {
+    Char(usize),
+    Input(libc::tcflag_t),
+    Local(libc::tcflag_t),
+    Output(libc::tcflag_t),
+    Control(libc::tcflag_t),
+}
+
+impl TerminalMode {
+    fn flag(self) -> Flag {
+        use Flag::*;
+        use TerminalMode::*;
+
+        match self {
+            Vintr => Char(libc::VINTR),
+            Vquit => Char(libc::VQUIT),
+            Verase => Char(libc::VERASE),
+            Vkill => Char(libc::VKILL),
+            Veof => Char(libc::VEOF),
+            Veol => Char(libc::VEOL),
+            Vstart => Char(libc::VSTART),
+            Vstop => Char(libc::VSTOP),
+            Vsusp => Char(libc::VSUSP),
+            Vwerase => Char(libc::VWERASE),
+            Vlnext => Char(libc::VLNEXT),
+
+            Ignpar => Input(libc::IGNPAR),
+            Parmrk => Input(libc::PARMRK),
+            Inpck => Input(libc::INPCK),
+            Istrip => Input(libc::ISTRIP),
+            Inlcr => Input(libc::INLCR),
+            Igncr => Input(libc::IGNCR),
+            Icrnl => Input(libc::ICRNL),
+            Ixon => Input(libc::IXON),
+            Ixany => Input(libc::IXANY),
+            Ixoff => Input(libc::IXOFF),
+            Imaxbel => Input(libc::IMAXBEL),
+
+            Isig => Local(libc::ISIG),
+            Icanon => Local(libc::ICANON),
+            Echo => Local(libc::ECHO),
+            Echoe => Local(libc::ECHOE),
+            Echok => Local(libc::ECHOK),
+            Echonl => Local(libc::ECHONL),
+            Noflsh => Local(libc::NOFLSH),
+            Tostop => Local(libc::TOSTOP),
+            Iexten => Local(libc::IEXTEN),
+            Echoctl => Local(libc::ECHOCTL),
+            Echoke => Local(libc::ECHOKE),
+
+            Opost => Output(libc::OPOST),
+            Onlcr => Output(libc::ONLCR),
+            Ocrnl => Output(libc::OCRNL),
+            Onocr => Output(libc::ONOCR),
+            Onlret => Output(libc::ONLRET),
+
+            Cs7 => Control(libc::CS7),
+            Cs8 => Control(libc::CS8),
+            Parenb => Control(libc::PARENB),
+            Parodd => Control(libc::PARODD),
+        }
+    }
+
+    fn apply(self, value: u32, termios: &mut termios) {
+        fn set(flags: &mut libc::tcflag_t, flag: libc::tcflag_t, value: u32) {
+            if value != 0 {
+                *flags |= flag;
+            } else {
+                *flags &= !flag;
+            }
+        }
+
+        match self.flag() {
+            Flag::Char(index) => termios.c_cc[index] = value as libc::cc_t,
+            Flag::Input(flag) => set(&mut termios.c_iflag, flag, value),
+            Flag::Local(flag) => set(&mut termios.c_lflag, flag, value),
+            Flag::Output(flag) => set(&mut termios.c_oflag, flag, value),
+            Flag::Control(flag) => {
+                if matches!(self, TerminalMode::Cs7 | TerminalMode::Cs8) {
+                    // The character size is a field, not a flag.
+                    termios.c_cflag &= !libc::CSIZE;
+                }
+                set(&mut termios.c_cflag, flag, value)
+            }
+        }
+    }
+}
+
+fn cvt(ret: c_int) -> io::Result<c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn get_termios(fd: BorrowedFd<'_>) -> io::Result<termios> {
+    let mut termios = MaybeUninit::uninit();
+    cvt(unsafe { libc::tcgetattr(fd.as_raw_fd(), termios.as_mut_ptr()) })?;
+    Ok(unsafe { termios.assume_init() })
+}
+
+fn set_termios(fd: BorrowedFd<'_>, termios: &termios) -> io::Result<()> {
+    cvt(unsafe { libc::tcsetattr(fd.as_raw_fd(), libc::TCSANOW, termios) }).map(drop)
+}
+
+fn set_window_size(fd: BorrowedFd<'_>, cols: u16, rows: u16) -> io::Result<()> {
+    let size = winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    cvt(unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCSWINSZ, &size) }).map(drop)
+}
+
+pub(crate) fn set_nonblocking(fd: BorrowedFd<'_>) -> io::Result<()> {
+    let flags = cvt(unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) })?;
+    cvt(unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) }).map(drop)
+}
+
+/// A newly created local pty.
+#[derive(Debug)]
+pub(crate) struct Pty {
+    master: OwnedFd,
+    slave: OwnedFd,
+}
+
+impl Pty {
+    pub(crate) fn open(config: &PtyConfig) -> io::Result<Self> {
+        let mut master: RawFd = -1;
+        let mut slave: RawFd = -1;
+        let size = winsize {
+            ws_row: config.rows,
+            ws_col: config.cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+
+        cvt(unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &size as *const _ as *mut _,
+            )
+        })?;
+
+        let pty = unsafe {
+            Self {
+                master: OwnedFd::from_raw_fd(master),
+                slave: OwnedFd::from_raw_fd(slave),
+            }
+        };
+
+        if !config.modes.is_empty() {
+            let mut termios = get_termios(pty.slave.as_fd())?;
+            for (mode, value) in &config.modes {
+                mode.apply(*value, &mut termios);
+            }
+            set_termios(pty.slave.as_fd(), &termios)?;
+        }
+
+        Ok(pty)
+    }
+
+    /// The slave side, to be passed to ssh.
+    pub(crate) fn slave(&self) -> BorrowedFd<'_> {
+        self.slave.as_fd()
+    }
+
+    /// Put the slave into raw mode, so that the local pty merely relays data
+    /// to and from the remote pty.
+    ///
+    /// This must only be called once ssh has read the terminal modes.
+    #[cfg_attr(not(feature = "native-mux"), allow(dead_code))]
+    pub(crate) fn make_raw(&self) -> io::Result<()> {
+        let mut termios = get_termios(self.slave.as_fd())?;
+        unsafe { libc::cfmakeraw(&mut termios) };
+        set_termios(self.slave.as_fd(), &termios)
+    }
+
+    /// Close the slave side and return the master side.
+    ///
+    /// Reading from the master side fails with `EIO` once every copy of the
+    /// slave side has been closed, i.e. once ssh has exited.
+    pub(crate) fn into_master(self) -> OwnedFd {
+        self.master
+    }
+}
+
+/// A pty passed to ssh when spawning a remote process.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Tty<'a> {
+    pub(crate) term: &'a str,
+    pub(crate) pty: &'a Pty,
+}
+
+/// Control over the pty of a running remote process.
+#[derive(Debug)]
+pub(crate) struct PtyControl {
+    master: OwnedFd,
+    intr: u8,
+    quit: u8,
+}
+
+impl PtyControl {
+    pub(crate) fn new(master: OwnedFd, config: &PtyConfig) -> Self {
+        let control_char = |wanted, default| {
+            config
+                .modes
+                .iter()
+                .rev()
+                .find(|(mode, _)| *mode == wanted)
+                .map(|(_, value)| *value as u8)
+                .unwrap_or(default)
+        };
+
+        Self {
+            master,
+            // ^C and ^\ are the defaults on every platform.
+            intr: control_char(TerminalMode::Vintr, 0x03),
+            quit: control_char(TerminalMode::Vquit, 0x1c),
+        }
+    }
+
+    pub(crate) fn resize(&self, cols: u16, rows: u16) -> io::Result<()> {
+        set_window_size(self.master.as_fd(), cols, rows)
+    }
+
+    /// Deliver `signal` by writing a control character to the terminal, which
+    /// only works for signals generated by the line discipline.
+    ///
+    /// Returns `None` if the signal cannot be delivered this way.
+    pub(crate) fn signal(&self, signal: Signal) -> Option<io::Result<()>> {
+        let ch = match signal {
+            Signal::Interrupt => self.intr,
+            Signal::Quit => self.quit,
+            _ => return None,
+        };
+
+        let ret = unsafe { libc::write(self.master.as_raw_fd(), (&ch as *const u8).cast(), 1) };
+        Some(if ret == 1 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_pty() {
+        let config = PtyConfig {
+            cols: 120,
+            rows: 40,
+            modes: vec![(TerminalMode::Echo, 0), (TerminalMode::Vintr, 0x07)],
+            ..Default::default()
+        };
+        let pty = Pty::open(&config).unwrap();
+
+        let termios = get_termios(pty.slave()).unwrap();
+        assert_eq!(termios.c_lflag & libc::ECHO, 0);
+        assert_eq!(termios.c_cc[libc::VINTR], 0x07);
+
+        let mut size = MaybeUninit::<winsize>::uninit();
+        cvt(unsafe { libc::ioctl(pty.slave().as_raw_fd(), libc::TIOCGWINSZ, size.as_mut_ptr()) })
+            .unwrap();
+        let size = unsafe { size.assume_init() };
+        assert_eq!((size.ws_col, size.ws_row), (120, 40));
+
+        pty.make_raw().unwrap();
+        let termios = get_termios(pty.slave()).unwrap();
+        assert_eq!(termios.c_lflag & libc::ICANON, 0);
+
+        let control = PtyControl::new(pty.into_master(), &config);
+        assert_eq!(control.intr, 0x07);
+        assert_eq!(control.quit, 0x1c);
+        control.resize(100, 30).unwrap();
+    }
+}
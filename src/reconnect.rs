@@ -0,0 +1,332 @@
+//! [`Session`] wrapper that relaunches the ssh master when it died.
+
+use super::{Error, OwningCommand, Session, SessionBuilder};
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::TempDir;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+/// How [`ReconnectingSession`] retries to connect once the master died.
+///
+/// The first attempt is made immediately, and the delay before each further
+/// attempt is doubled, starting at the [initial backoff](Self::initial_backoff)
+/// and capped at the [maximum backoff](Self::max_backoff).
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_attempts: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_attempts: 5,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Set the delay before the second attempt to connect.
+    ///
+    /// Defaults to 500ms.
+    pub fn initial_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Set the maximum delay between two attempts to connect.
+    ///
+    /// Defaults to 30s.
+    pub fn max_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Set how many times to try to connect before giving up and returning
+    /// the last error. `0` disables reconnecting altogether.
+    ///
+    /// Defaults to 5.
+    pub fn max_attempts(&mut self, attempts: u32) -> &mut Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Delay before the `attempt`th attempt, starting at 1.
+    fn backoff(&self, attempt: u32) -> Duration {
+        match attempt {
+            0 | 1 => Duration::ZERO,
+            _ => self
+                .initial_backoff
+                .checked_mul(1 << (attempt - 2).min(31))
+                .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff)),
+        }
+    }
+}
+
+/// Events reported to the callback registered with
+/// [`ReconnectingSession::on_event`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ReconnectEvent<'a> {
+    /// The master died, with the error returned by [`Session::check`].
+    Disconnected(&'a Error),
+
+    /// Attempt number `attempt` to connect, starting at 1, is about to be
+    /// made after waiting for `delay`.
+    Reconnecting {
+        /// Number of the attempt.
+        attempt: u32,
+        /// Time waited before the attempt.
+        delay: Duration,
+    },
+
+    /// An attempt to connect failed.
+    ReconnectFailed {
+        /// Number of the attempt.
+        attempt: u32,
+        /// Why the attempt failed.
+        error: &'a Error,
+    },
+
+    /// A new master was launched successfully.
+    Reconnected {
+        /// Number of the successful attempt.
+        attempt: u32,
+    },
+}
+
+type EventCallback = Box<dyn Fn(ReconnectEvent<'_>) + Send + Sync>;
+
+/// A [`Session`] that remembers how it was established, and transparently
+/// relaunches the ssh master if it exited, e.g. because the connection broke.
+///
+/// Before [`session`](ReconnectingSession::session) hands out the current
+/// session, its master is checked with [`Session::check`]. If it died, a new
+/// master is launched according to the [`ReconnectPolicy`].
+///
+/// Remote commands that were running when the master died still fail with
+/// [`Error::Disconnected`]. They are not restarted.
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "process-mux")]
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), openssh::Error> {
+/// use openssh::{ReconnectEvent, ReconnectPolicy, ReconnectingSession, SessionBuilder};
+///
+/// let mut session = ReconnectingSession::connect(
+///     SessionBuilder::default(),
+///     "me@ssh.example.com",
+///     ReconnectPolicy::default(),
+/// )
+/// .await?;
+/// session.on_event(|event| {
+///     if let ReconnectEvent::Disconnected(err) = event {
+///         eprintln!("reconnecting after: {err}");
+///     }
+/// });
+///
+/// let ls = session.command("ls").await?.output().await?;
+/// # Ok(()) }
+/// ```
+pub struct ReconnectingSession {
+    builder: SessionBuilder,
+    destination: String,
+    new_session: fn(TempDir) -> Session,
+    policy: ReconnectPolicy,
+    on_event: Option<EventCallback>,
+    session: Mutex<Arc<Session>>,
+}
+
+impl fmt::Debug for ReconnectingSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingSession")
+            .field("builder", &self.builder)
+            .field("destination", &self.destination)
+            .field("policy", &self.policy)
+            .field("session", &self.session)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ReconnectingSession {
+    /// Connect to `destination` with `builder` using the process
+    /// implementation (see [`SessionBuilder::connect`]), reconnecting
+    /// according to `policy` later on.
+    ///
+    /// The initial connect is not retried.
+    #[cfg(feature = "process-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
+    pub async fn connect(
+        builder: SessionBuilder,
+        destination: impl Into<String>,
+        policy: ReconnectPolicy,
+    ) -> Result<Self, Error> {
+        Self::connect_impl(
+            builder,
+            destination.into(),
+            policy,
+            Session::new_process_mux,
+        )
+        .await
+    }
+
+    /// Connect to `destination` with `builder` using the native mux
+    /// implementation (see [`SessionBuilder::connect_mux`]), reconnecting
+    /// according to `policy` later on.
+    ///
+    /// The initial connect is not retried.
+    #[cfg(feature = "native-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
+    pub async fn connect_mux(
+        builder: SessionBuilder,
+        destination: impl Into<String>,
+        policy: ReconnectPolicy,
+    ) -> Result<Self, Error> {
+        Self::connect_impl(builder, destination.into(), policy, Session::new_native_mux).await
+    }
+
+    async fn connect_impl(
+        builder: SessionBuilder,
+        destination: String,
+        policy: ReconnectPolicy,
+        new_session: fn(TempDir) -> Session,
+    ) -> Result<Self, Error> {
+        let session = builder.connect_impl(&destination, new_session).await?;
+
+        Ok(Self {
+            builder,
+            destination,
+            new_session,
+            policy,
+            on_event: None,
+            session: Mutex::new(Arc::new(session)),
+        })
+    }
+
+    /// Register a callback that is called whenever the master died and
+    /// while reconnecting, e.g. to log it.
+    pub fn on_event<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(ReconnectEvent<'_>) + Send + Sync + 'static,
+    {
+        self.on_event = Some(Box::new(callback));
+        self
+    }
+
+    fn emit(&self, event: ReconnectEvent<'_>) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(destination = %self.destination, ?event, "reconnecting session");
+
+        if let Some(on_event) = &self.on_event {
+            on_event(event);
+        }
+    }
+
+    /// Get the current session, launching a new master first if the
+    /// current one died.
+    ///
+    /// Concurrent calls wait for a single reconnect. If all attempts to
+    /// reconnect fail, the error of the last attempt is returned and the
+    /// next call tries again.
+    pub async fn session(&self) -> Result<Arc<Session>, Error> {
+        let mut session = self.session.lock().await;
+
+        let err = match session.check().await {
+            Ok(()) => return Ok(session.clone()),
+            Err(err) => err,
+        };
+        self.emit(ReconnectEvent::Disconnected(&err));
+
+        let mut last_err = err;
+        for attempt in 1..=self.policy.max_attempts {
+            let delay = self.policy.backoff(attempt);
+            self.emit(ReconnectEvent::Reconnecting { attempt, delay });
+            sleep(delay).await;
+
+            match self
+                .builder
+                .connect_impl(&self.destination, self.new_session)
+                .await
+            {
+                Ok(new_session) => {
+                    let old = std::mem::replace(&mut *session, Arc::new(new_session));
+                    // The old master is dead, so closing it can only fail.
+                    if let Ok(old) = Arc::try_unwrap(old) {
+                        old.close().await.ok();
+                    }
+
+                    self.emit(ReconnectEvent::Reconnected { attempt });
+                    return Ok(session.clone());
+                }
+                Err(err) => {
+                    self.emit(ReconnectEvent::ReconnectFailed {
+                        attempt,
+                        error: &err,
+                    });
+                    last_err = err;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+
+    /// Constructs a new [`OwningCommand`] for launching the program at path `program` on the
+    /// remote host, on the [current session](ReconnectingSession::session).
+    ///
+    /// See [`Session::command`] for details.
+    pub async fn command<'a, S: Into<Cow<'a, str>>>(
+        &self,
+        program: S,
+    ) -> Result<OwningCommand<Arc<Session>>, Error> {
+        Ok(Session::to_command(self.session().await?, program))
+    }
+
+    /// Constructs a new [`OwningCommand`] for launching the program at path `program` on the
+    /// remote host, on the [current session](ReconnectingSession::session).
+    ///
+    /// See [`Session::raw_command`] for details.
+    pub async fn raw_command<S: AsRef<OsStr>>(
+        &self,
+        program: S,
+    ) -> Result<OwningCommand<Arc<Session>>, Error> {
+        Ok(Session::to_raw_command(self.session().await?, program))
+    }
+
+    /// Terminate the current master.
+    ///
+    /// If the session is still in use, it is terminated once the last `Arc`
+    /// to it is dropped instead.
+    pub async fn close(self) -> Result<(), Error> {
+        match Arc::try_unwrap(self.session.into_inner()) {
+            Ok(session) => session.close().await,
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let mut policy = ReconnectPolicy::default();
+        policy
+            .initial_backoff(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(10));
+
+        let delays: Vec<u64> = (1..=6).map(|n| policy.backoff(n).as_secs()).collect();
+        assert_eq!(delays, [0, 1, 2, 4, 8, 10]);
+        assert_eq!(policy.backoff(100), Duration::from_secs(10));
+    }
+}
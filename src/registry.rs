@@ -0,0 +1,172 @@
+//! Bookkeeping of the port forwardings and channels opened through a [`Session`].
+//!
+//! The ssh multiplex protocol has no request to list what the master is
+//! currently doing, so the [`Session`] remembers it instead.
+
+use super::{ForwardType, Socket};
+
+#[cfg(doc)]
+use super::Session;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A port forwarding established with [`Session::request_port_forward`] and
+/// not closed yet, as returned by [`Session::list_forwards`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ForwardInfo {
+    /// Type of the port forwarding.
+    pub forward_type: ForwardType,
+
+    /// Socket the port forwarding listens on, with port 0 replaced by the
+    /// allocated port.
+    pub listen_socket: Socket<'static>,
+
+    /// Socket the port forwarding connects to, `None` for
+    /// [`ForwardType::Dynamic`].
+    pub connect_socket: Option<Socket<'static>>,
+}
+
+/// A remote command or subsystem spawned on a [`Session`] whose [`Child`](crate::Child)
+/// is still alive, as returned by [`Session::list_channels`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct ChannelInfo {
+    /// Identifier of the channel, unique within its [`Session`] and
+    /// increasing in the order the channels were opened.
+    pub id: u64,
+
+    /// The command line run on the remote host, or the name of the subsystem.
+    pub command: String,
+
+    /// Whether `command` is a subsystem, see [`Session::subsystem`].
+    pub subsystem: bool,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Forwardings along with the listen socket they were requested with.
+    forwards: Vec<(Socket<'static>, ForwardInfo)>,
+    channels: BTreeMap<u64, ChannelInfo>,
+    next_channel_id: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Registry(Arc<Mutex<Entries>>);
+
+impl Registry {
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        // The entries are never left in an inconsistent state, so it is fine
+        // to ignore poisoning.
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Record a forwarding that was requested with `requested_listen_socket`.
+    pub(crate) fn add_forward(&self, requested_listen_socket: Socket<'static>, info: ForwardInfo) {
+        self.lock().forwards.push((requested_listen_socket, info));
+    }
+
+    /// Forget the forwarding that was requested with `listen_socket`.
+    pub(crate) fn remove_forward(&self, forward_type: ForwardType, listen_socket: &Socket<'_>) {
+        let mut entries = self.lock();
+        if let Some(i) = entries.forwards.iter().position(|(requested, info)| {
+            info.forward_type == forward_type && requested == listen_socket
+        }) {
+            entries.forwards.remove(i);
+        }
+    }
+
+    pub(crate) fn forwards(&self) -> Vec<ForwardInfo> {
+        self.lock()
+            .forwards
+            .iter()
+            .map(|(_, info)| info.clone())
+            .collect()
+    }
+
+    /// Record a channel, which is forgotten once the returned guard is dropped.
+    pub(crate) fn open_channel(&self, command: String, subsystem: bool) -> ChannelGuard {
+        let mut entries = self.lock();
+        let id = entries.next_channel_id;
+        entries.next_channel_id += 1;
+        entries.channels.insert(
+            id,
+            ChannelInfo {
+                id,
+                command,
+                subsystem,
+            },
+        );
+
+        ChannelGuard {
+            registry: self.clone(),
+            id,
+        }
+    }
+
+    pub(crate) fn channels(&self) -> Vec<ChannelInfo> {
+        self.lock().channels.values().cloned().collect()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ChannelGuard {
+    registry: Registry,
+    id: u64,
+}
+
+impl Drop for ChannelGuard {
+    fn drop(&mut self) {
+        self.registry.lock().channels.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwards() {
+        let registry = Registry::default();
+        let info = ForwardInfo {
+            forward_type: ForwardType::Remote,
+            listen_socket: Socket::new("127.0.0.1", 40022),
+            connect_socket: Some(Socket::new("127.0.0.1", 22)),
+        };
+        registry.add_forward(Socket::new("127.0.0.1", 0), info.clone());
+        assert_eq!(registry.forwards(), [info]);
+
+        // Forwardings are identified by the socket they were requested with.
+        registry.remove_forward(ForwardType::Remote, &Socket::new("127.0.0.1", 40022));
+        assert_eq!(registry.forwards().len(), 1);
+        registry.remove_forward(ForwardType::Local, &Socket::new("127.0.0.1", 0));
+        assert_eq!(registry.forwards().len(), 1);
+        registry.remove_forward(ForwardType::Remote, &Socket::new("127.0.0.1", 0));
+        assert!(registry.forwards().is_empty());
+    }
+
+    #[test]
+    fn channels() {
+        let registry = Registry::default();
+        let ls = registry.open_channel("ls".into(), false);
+        let sftp = registry.open_channel("sftp".into(), true);
+
+        let ids: Vec<u64> = registry.channels().iter().map(|c| c.id).collect();
+        assert_eq!(ids, [0, 1]);
+
+        drop(ls);
+        assert_eq!(
+            registry.channels(),
+            [ChannelInfo {
+                id: 1,
+                command: "sftp".into(),
+                subsystem: true,
+            }]
+        );
+
+        drop(sftp);
+        assert!(registry.channels().is_empty());
+        assert_eq!(registry.open_channel("ls".into(), false).id, 2);
+    }
+}
@@ -1,4 +1,5 @@
-use super::{Error, ForwardType, KnownHosts, OwningCommand, SessionBuilder, Socket};
+use super::registry::Registry;
+use super::{Error, ForwardType, KnownHosts, OwningCommand, PortForward, SessionBuilder, Socket};
 
 #[cfg(feature = "process-mux")]
 use super::process_impl;
@@ -10,8 +11,12 @@ use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::ops::Deref;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 use tempfile::TempDir;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
 
 #[derive(Debug)]
 pub(crate) enum SessionImp {
@@ -49,11 +54,59 @@ macro_rules! delegate {
 /// When the `Session` is dropped, the connection to the remote host is severed, and any errors
 /// silently ignored. To disconnect and be alerted to errors, use [`close`](Session::close).
 #[derive(Debug)]
-pub struct Session(SessionImp);
+pub struct Session {
+    imp: SessionImp,
+    /// Only set for sessions connected with [`SessionBuilder`].
+    channel_limit: Option<ChannelLimit>,
+    registry: Registry,
+}
+
+/// Limit on the number of channels open at the same time, set with
+/// [`SessionBuilder::max_sessions`].
+#[derive(Clone, Debug)]
+pub(crate) struct ChannelLimit {
+    semaphore: Arc<Semaphore>,
+    timeout: Option<Duration>,
+}
 
-// TODO: UserKnownHostsFile for custom known host fingerprint.
+impl ChannelLimit {
+    pub(crate) fn new(max: usize, timeout: Option<Duration>) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            timeout,
+        }
+    }
+
+    /// Wait for a free channel, which is released once the permit is dropped.
+    pub(crate) async fn acquire(&self) -> Result<OwnedSemaphorePermit, Error> {
+        let acquire = self.semaphore.clone().acquire_owned();
+        let permit = match self.timeout {
+            Some(duration) => timeout(duration, acquire)
+                .await
+                .map_err(|_| Error::TooManySessions)?,
+            None => acquire.await,
+        };
+        Ok(permit.expect("the semaphore is never closed"))
+    }
+}
 
 impl Session {
+    fn from_imp(imp: SessionImp) -> Self {
+        Self {
+            imp,
+            channel_limit: None,
+            registry: Registry::default(),
+        }
+    }
+
+    pub(crate) fn set_channel_limit(&mut self, limit: Option<ChannelLimit>) {
+        self.channel_limit = limit;
+    }
+
+    pub(crate) fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
     /// The method for creating a [`Session`] and externally control the creation of TempDir.
     ///
     /// By using the built-in [`SessionBuilder`] in openssh, or a custom SessionBuilder,
@@ -97,7 +150,7 @@ impl Session {
     #[cfg(feature = "process-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
     pub fn new_process_mux(tempdir: TempDir) -> Self {
-        Self(SessionImp::ProcessImpl(process_impl::Session::new(tempdir)))
+        Self::from_imp(SessionImp::ProcessImpl(process_impl::Session::new(tempdir)))
     }
 
     /// The method for creating a [`Session`] and externally control the creation of TempDir.
@@ -142,7 +195,7 @@ impl Session {
     #[cfg(feature = "native-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
     pub fn new_native_mux(tempdir: TempDir) -> Self {
-        Self(SessionImp::NativeMuxImpl(native_mux_impl::Session::new(
+        Self::from_imp(SessionImp::NativeMuxImpl(native_mux_impl::Session::new(
             tempdir,
         )))
     }
@@ -161,7 +214,7 @@ impl Session {
     #[cfg(feature = "process-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
     pub fn resume(ctl: Box<Path>, master_log: Option<Box<Path>>) -> Self {
-        Self(SessionImp::ProcessImpl(process_impl::Session::resume(
+        Self::from_imp(SessionImp::ProcessImpl(process_impl::Session::resume(
             ctl, master_log,
         )))
     }
@@ -171,7 +224,7 @@ impl Session {
     #[cfg(feature = "native-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
     pub fn resume_mux(ctl: Box<Path>, master_log: Option<Box<Path>>) -> Self {
-        Self(SessionImp::NativeMuxImpl(native_mux_impl::Session::resume(
+        Self::from_imp(SessionImp::NativeMuxImpl(native_mux_impl::Session::resume(
             ctl, master_log,
         )))
     }
@@ -184,7 +237,7 @@ impl Session {
     ///
     /// If connecting requires interactive authentication based on `STDIN` (such as reading a
     /// password), the connection will fail. Consider setting up keypair-based authentication
-    /// instead.
+    /// instead, or use [`SessionBuilder::askpass`].
     ///
     /// For more options, see [`SessionBuilder`].
     #[cfg(feature = "process-mux")]
@@ -206,7 +259,7 @@ impl Session {
     ///
     /// If connecting requires interactive authentication based on `STDIN` (such as reading a
     /// password), the connection will fail. Consider setting up keypair-based authentication
-    /// instead.
+    /// instead, or use [`SessionBuilder::askpass`].
     ///
     /// For more options, see [`SessionBuilder`].
     #[cfg(feature = "native-mux")]
@@ -225,14 +278,14 @@ impl Session {
     #[cfg(not(windows))]
     #[cfg_attr(docsrs, doc(cfg(not(windows))))]
     pub async fn check(&self) -> Result<(), Error> {
-        delegate!(&self.0, imp, { imp.check().await })
+        delegate!(&self.imp, imp, { imp.check().await })
     }
 
     /// Get the SSH connection's control socket path.
     #[cfg(not(windows))]
     #[cfg_attr(docsrs, doc(cfg(not(windows))))]
     pub fn control_socket(&self) -> &Path {
-        delegate!(&self.0, imp, { imp.ctl() })
+        delegate!(&self.imp, imp, { imp.ctl() })
     }
 
     /// Constructs a new [`OwningCommand`] for launching the program at path `program` on the remote
@@ -344,10 +397,15 @@ impl Session {
         P: AsRef<OsStr>,
         S: Deref<Target = Session> + Clone,
     {
-        let session_impl = delegate!(&session.0, imp, {
+        let session_impl = delegate!(&session.imp, imp, {
             imp.raw_command(program.as_ref()).into()
         });
-        OwningCommand::new(session, session_impl)
+        let channel_limit = session.channel_limit.clone();
+        let registry = session.registry.clone();
+        let mut command = OwningCommand::new(session, session_impl);
+        command.set_channel_limit(channel_limit);
+        command.set_registry(registry);
+        command
     }
 
     /// Constructs a new [`OwningCommand`] for launching subsystem `program` on the remote
@@ -404,6 +462,37 @@ impl Session {
         Self::to_subsystem(self, program)
     }
 
+    /// Start an sftp session on the remote host, for transferring files.
+    ///
+    /// This runs the `sftp` [subsystem](Session::subsystem) and thus requires
+    /// it to be enabled on the remote host.
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// # #[cfg(feature = "native-mux")]
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn Error>> {
+    ///
+    /// use openssh::{Session, KnownHosts};
+    ///
+    /// let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
+    ///
+    /// let sftp = session.sftp().await?;
+    /// sftp.upload_with_progress("Cargo.toml", "/tmp/Cargo.toml", |progress| {
+    ///     println!("{} of {:?} bytes", progress.transferred, progress.total);
+    /// })
+    /// .await?;
+    /// assert!(sftp.metadata("/tmp/Cargo.toml").await?.len().is_some());
+    /// sftp.close().await?;
+    ///
+    /// # Ok(()) }
+    /// ```
+    #[cfg(feature = "sftp")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "sftp")))]
+    pub async fn sftp(&self) -> Result<crate::Sftp<'_>, Error> {
+        crate::Sftp::new(self).await
+    }
+
     /// Version of [`subsystem`](Self::subsystem) which stores an
     /// arbitrary shared-ownership pointer to a session making the
     /// resulting [`OwningCommand`] independent from the source
@@ -414,8 +503,15 @@ impl Session {
         P: AsRef<OsStr>,
         S: Deref<Target = Session> + Clone,
     {
-        let session_impl = delegate!(&session.0, imp, { imp.subsystem(program.as_ref()).into() });
-        OwningCommand::new(session, session_impl)
+        let session_impl = delegate!(&session.imp, imp, {
+            imp.subsystem(program.as_ref()).into()
+        });
+        let channel_limit = session.channel_limit.clone();
+        let registry = session.registry.clone();
+        let mut command = OwningCommand::new(session, session_impl);
+        command.set_channel_limit(channel_limit);
+        command.set_registry(registry);
+        command
     }
 
     /// Constructs a new [`OwningCommand`] that runs the provided shell command on the remote host.
@@ -463,47 +559,332 @@ impl Session {
         cmd
     }
 
-    /// Request to open a local/remote port forwarding.
+    /// Copy the local file `local` to `remote`, without requiring an sftp subsystem.
+    ///
+    /// The file is streamed through `cat` running in `sh` on the remote host and written to a
+    /// temporary file next to `remote`. Only once its size and sha256 checksum match `local`, the
+    /// permission bits of `local` are applied and it replaces `remote`. Otherwise
+    /// [`Error::CopyMismatch`] is returned.
+    ///
+    /// The checksum is computed with `sha256sum`, `shasum` or `openssl` on the remote host. If
+    /// none of them is available, only the size is verified.
+    ///
+    /// Returns the number of bytes copied.
+    pub async fn copy_to(
+        &self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+    ) -> Result<u64, Error> {
+        crate::copy::copy_to(self, local.as_ref(), remote.as_ref()).await
+    }
+
+    /// Copy the remote file `remote` to `local`, without requiring an sftp subsystem.
+    ///
+    /// This is the counterpart of [`copy_to`](Session::copy_to): the file is streamed through
+    /// `cat` and written to a temporary file next to `local`, which only replaces `local` once its
+    /// size and sha256 checksum match the ones computed on the remote host. The permission bits of
+    /// `remote` are preserved.
+    ///
+    /// Returns the number of bytes copied.
+    pub async fn copy_from(
+        &self,
+        remote: impl AsRef<Path>,
+        local: impl AsRef<Path>,
+    ) -> Result<u64, Error> {
+        crate::copy::copy_from(self, remote.as_ref(), local.as_ref()).await
+    }
+
+    /// Recursively synchronise the local directory `local` to the remote directory `remote`,
+    /// similar to `rsync -a`.
+    ///
+    /// The remote tree is listed with a single `find -printf` command, which requires GNU `find`
+    /// on the remote host, and `remote` is created if it does not exist. Regular files, directories
+    /// and symlinks that are missing or differ on the remote host are then streamed to the remote
+    /// `tar` over stdin, preserving their permission bits and modification times. Other kinds of
+    /// files are skipped.
+    ///
+    /// By default, regular files are considered different if their size, permission bits or
+    /// modification time differ. See [`SyncOptions`](crate::SyncOptions) for deleting remote
+    /// entries, comparing checksums and excluding entries.
+    ///
+    /// ```rust,no_run
+    /// # use std::error::Error;
+    /// # #[cfg(feature = "native-mux")]
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn Error>> {
+    ///
+    /// use openssh::{Session, KnownHosts, SyncOptions};
+    ///
+    /// let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
+    ///
+    /// let options = SyncOptions {
+    ///     delete: true,
+    ///     exclude: vec!["target".to_owned()],
+    ///     ..Default::default()
+    /// };
+    /// let report = session.sync_dir("my-project", "deploy/my-project", options).await?;
+    /// println!("updated {:?}", report.updated);
+    ///
+    /// # Ok(()) }
+    /// ```
+    pub async fn sync_dir(
+        &self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+        options: crate::SyncOptions,
+    ) -> Result<crate::SyncReport, Error> {
+        crate::sync::sync_dir(self, local.as_ref(), remote.as_ref(), &options).await
+    }
+
+    /// Request to open a local/remote/dynamic port forwarding.
     /// The `Socket` can be either a unix socket or a tcp socket.
     ///
     /// If `forward_type` == Local, then `listen_socket` on local machine will be
     /// forwarded to `connect_socket` on remote machine.
     ///
-    /// Otherwise, `listen_socket` on the remote machine will be forwarded to `connect_socket`
-    /// on the local machine.
+    /// If `forward_type` == Remote, then `listen_socket` on the remote machine will be forwarded
+    /// to `connect_socket` on the local machine.
+    ///
+    /// If `forward_type` == Dynamic, then a SOCKS proxy listens on `listen_socket` on the local
+    /// machine and connects to the requested destinations from the remote machine, just like
+    /// `ssh -D`. `connect_socket` is ignored in this case.
+    ///
+    /// Returns the socket the port forwarding actually listens on. It only differs from
+    /// `listen_socket` if that is a tcp socket with port 0: for remote forwardings, the port
+    /// is then allocated by the server, and for local/dynamic forwardings a free port on the
+    /// local machine is picked before requesting the forwarding. If another process takes that
+    /// port before ssh binds to it, a new port is picked.
+    ///
+    /// Note that a remote forwarding on port 0 has to be closed with port 0 as well, as it was
+    /// requested, while local/dynamic forwardings are closed with the returned socket.
     pub async fn request_port_forward(
         &self,
         forward_type: impl Into<ForwardType>,
         listen_socket: impl Into<Socket<'_>>,
         connect_socket: impl Into<Socket<'_>>,
-    ) -> Result<(), Error> {
-        delegate!(&self.0, imp, {
-            imp.request_port_forward(
-                forward_type.into(),
-                listen_socket.into(),
-                connect_socket.into(),
-            )
-            .await
+    ) -> Result<Socket<'static>, Error> {
+        /// How often a free local port is picked for a local/dynamic forwarding on port 0.
+        const LOCAL_PORT_ATTEMPTS: usize = 3;
+
+        let forward_type = forward_type.into();
+        let requested = listen_socket.into();
+        let connect_socket = connect_socket.into();
+
+        let pick_local_port = forward_type != ForwardType::Remote && requested.port() == Some(0);
+        let mut attempts = 1;
+        let (listen_socket, allocated_port) = loop {
+            let listen_socket = if pick_local_port {
+                requested.clone().allocate_local_port()?
+            } else {
+                requested.clone()
+            };
+
+            let res = self
+                .request_port_forward_impl(forward_type, &listen_socket, &connect_socket)
+                .await;
+            match res {
+                Ok(allocated_port) => break (listen_socket, allocated_port),
+                // Another process took the picked port before ssh could bind to it.
+                Err(_)
+                    if pick_local_port
+                        && attempts < LOCAL_PORT_ATTEMPTS
+                        && listen_socket.is_taken() =>
+                {
+                    attempts += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        let listen_socket = listen_socket.into_owned();
+        let bound_socket = match allocated_port {
+            Some(port) => listen_socket.clone().with_allocated_port(port),
+            None => listen_socket.clone(),
+        };
+
+        self.registry.add_forward(
+            listen_socket,
+            crate::ForwardInfo {
+                forward_type,
+                listen_socket: bound_socket.clone(),
+                connect_socket: (forward_type != ForwardType::Dynamic)
+                    .then(|| connect_socket.into_owned()),
+            },
+        );
+
+        Ok(bound_socket)
+    }
+
+    async fn request_port_forward_impl(
+        &self,
+        forward_type: ForwardType,
+        listen_socket: &Socket<'_>,
+        connect_socket: &Socket<'_>,
+    ) -> Result<Option<u16>, Error> {
+        delegate!(&self.imp, imp, {
+            imp.request_port_forward(forward_type, listen_socket.clone(), connect_socket.clone())
+                .await
         })
     }
 
-    /// Close a previously established local/remote port forwarding.
+    /// Version of [`request_port_forward`](Self::request_port_forward) that returns a
+    /// [`PortForward`] guard, which closes the port forwarding once it is dropped.
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "native-mux")]
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use openssh::{ForwardType, KnownHosts, Session, Socket};
+    ///
+    /// let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
+    ///
+    /// let forward = session
+    ///     .port_forward(
+    ///         ForwardType::Local,
+    ///         Socket::new("127.0.0.1", 8080),
+    ///         Socket::new("localhost", 80),
+    ///     )
+    ///     .await?;
+    ///
+    /// // Talk to port 80 on the remote host via 127.0.0.1:8080 ...
+    ///
+    /// forward.close().await?;
+    /// # Ok(()) }
+    /// ```
+    pub async fn port_forward(
+        &self,
+        forward_type: impl Into<ForwardType>,
+        listen_socket: impl Into<Socket<'_>>,
+        connect_socket: impl Into<Socket<'_>>,
+    ) -> Result<PortForward<&'_ Self>, Error> {
+        Self::to_port_forward(self, forward_type, listen_socket, connect_socket).await
+    }
+
+    /// Version of [`port_forward`](Self::port_forward) which stores an
+    /// `Arc<Session>`, so that the [`PortForward`] can be moved into a task.
+    pub async fn arc_port_forward(
+        self: std::sync::Arc<Session>,
+        forward_type: impl Into<ForwardType>,
+        listen_socket: impl Into<Socket<'_>>,
+        connect_socket: impl Into<Socket<'_>>,
+    ) -> Result<PortForward<std::sync::Arc<Session>>, Error> {
+        Self::to_port_forward(self, forward_type, listen_socket, connect_socket).await
+    }
+
+    /// Version of [`port_forward`](Self::port_forward) which stores an
+    /// arbitrary smart pointer to a [`Session`], more generic but less
+    /// convenient than [`arc_port_forward`](Self::arc_port_forward).
+    pub async fn to_port_forward<S>(
+        session: S,
+        forward_type: impl Into<ForwardType>,
+        listen_socket: impl Into<Socket<'_>>,
+        connect_socket: impl Into<Socket<'_>>,
+    ) -> Result<PortForward<S>, Error>
+    where
+        S: Deref<Target = Session>,
+    {
+        let forward_type = forward_type.into();
+        let listen_socket = listen_socket.into().into_owned();
+        let connect_socket = connect_socket.into().into_owned();
+
+        let bound_socket = session
+            .request_port_forward(forward_type, listen_socket.clone(), connect_socket.clone())
+            .await?;
+
+        // See `request_port_forward` for which socket closes the forwarding.
+        let close_socket = match forward_type {
+            ForwardType::Remote => listen_socket,
+            ForwardType::Local | ForwardType::Dynamic => bound_socket.clone(),
+        };
+
+        Ok(PortForward::new(
+            session,
+            forward_type,
+            bound_socket,
+            close_socket,
+            connect_socket,
+        ))
+    }
+
+    /// Close a previously established local/remote/dynamic port forwarding.
     ///
     /// The same set of arguments should be passed as when the port forwarding was requested.
+    /// See [`port_forward`](Self::port_forward) for a guard that remembers them.
     pub async fn close_port_forward(
         &self,
         forward_type: impl Into<ForwardType>,
         listen_socket: impl Into<Socket<'_>>,
         connect_socket: impl Into<Socket<'_>>,
     ) -> Result<(), Error> {
-        delegate!(&self.0, imp, {
-            imp.close_port_forward(
-                forward_type.into(),
-                listen_socket.into(),
-                connect_socket.into(),
-            )
-            .await
-        })
+        let forward_type = forward_type.into();
+        let listen_socket = listen_socket.into();
+
+        let res: Result<(), Error> = delegate!(&self.imp, imp, {
+            imp.close_port_forward(forward_type, listen_socket.clone(), connect_socket.into())
+                .await
+        });
+        res?;
+
+        self.registry.remove_forward(forward_type, &listen_socket);
+        Ok(())
+    }
+
+    /// List the port forwardings requested through this session that are still open.
+    ///
+    /// The ssh multiplex protocol has no way to ask the master for its port forwardings, so
+    /// this only includes the ones requested with [`request_port_forward`](Self::request_port_forward)
+    /// and friends on this `Session`, and not ones requested on the command line of the master,
+    /// through another [`Session`] resuming it, or forwardings the master closed on its own.
+    pub fn list_forwards(&self) -> Vec<crate::ForwardInfo> {
+        self.registry.forwards()
+    }
+
+    /// List the remote commands and subsystems spawned on this session whose
+    /// [`Child`](crate::Child) has not been dropped yet, in the order they were spawned.
+    ///
+    /// Like [`list_forwards`](Self::list_forwards), this only includes channels opened through
+    /// this `Session`. A channel is listed until its `Child` is dropped or waited for, even if the
+    /// remote command already exited.
+    pub fn list_channels(&self) -> Vec<crate::ChannelInfo> {
+        self.registry.channels()
+    }
+
+    /// Connect to `host:port` from the remote host and return a stream to the connection,
+    /// without listening on a local socket like [`request_port_forward`](Self::request_port_forward).
+    ///
+    /// `host` is resolved on the remote host. The stream is carried by an `ssh -W` process
+    /// talking to the master, for both the process and the native mux implementation, since the
+    /// latter does not support stdio forwarding yet.
+    ///
+    /// Since the connection is only established in the background, failing to connect is
+    /// reported by the first read from the [`DirectStream`](crate::DirectStream).
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "native-mux")]
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use openssh::{KnownHosts, Session};
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    ///
+    /// let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
+    ///
+    /// let mut stream = session.connect_tcp("localhost", 6379).await?;
+    /// stream.write_all(b"PING\r\n").await?;
+    ///
+    /// let mut pong = [0; 7];
+    /// stream.read_exact(&mut pong).await?;
+    /// # Ok(()) }
+    /// ```
+    pub async fn connect_tcp(&self, host: &str, port: u16) -> Result<crate::DirectStream, Error> {
+        crate::DirectStream::connect(self, crate::direct_stream::tcp_target(host, port)).await
+    }
+
+    /// Connect to the unix socket at `path` on the remote host and return a stream to the
+    /// connection, see [`connect_tcp`](Self::connect_tcp).
+    ///
+    /// `path` has to be absolute.
+    pub async fn connect_unix(&self, path: impl AsRef<Path>) -> Result<crate::DirectStream, Error> {
+        crate::DirectStream::connect(self, crate::direct_stream::unix_target(path.as_ref())).await
     }
 
     /// Terminate the remote connection.
@@ -511,7 +892,7 @@ impl Session {
     /// This destructor terminates the ssh multiplex server
     /// regardless of how it was created.
     pub async fn close(self) -> Result<(), Error> {
-        let res: Result<Option<TempDir>, Error> = delegate!(self.0, imp, { imp.close().await });
+        let res: Result<Option<TempDir>, Error> = delegate!(self.imp, imp, { imp.close().await });
 
         res?.map(TempDir::close)
             .transpose()
@@ -524,6 +905,26 @@ impl Session {
     ///
     /// Return (path to control socket, path to ssh multiplex output log)
     pub fn detach(self) -> (Box<Path>, Option<Box<Path>>) {
-        delegate!(self.0, imp, { imp.detach() })
+        delegate!(self.imp, imp, { imp.detach() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn channel_limit() {
+        let limit = ChannelLimit::new(2, Some(Duration::from_millis(10)));
+
+        let first = limit.acquire().await.unwrap();
+        let _second = limit.acquire().await.unwrap();
+        assert!(matches!(
+            limit.acquire().await.unwrap_err(),
+            Error::TooManySessions
+        ));
+
+        drop(first);
+        let _third = limit.acquire().await.unwrap();
     }
 }
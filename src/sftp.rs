@@ -0,0 +1,253 @@
+//! File transfer over the sftp subsystem of the remote host.
+//!
+//! This is a thin layer over [`openssh_sftp_client`], which takes care of
+//! spawning the `sftp` subsystem over an existing [`Session`] and of
+//! transferring whole files between the local and the remote host.
+
+use super::{Child, Error, Session, Stdio};
+
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use openssh_sftp_client::Sftp as SftpClient;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub use openssh_sftp_client::fs::DirEntry;
+pub use openssh_sftp_client::metadata::{FileType, MetaData, Permissions};
+
+/// Size of the chunks files are transferred in, which is the size of a
+/// read or write request most sftp servers support.
+const CHUNK_SIZE: u32 = 32 * 1024;
+
+/// Progress of a file transfer, passed to the callbacks of
+/// [`Sftp::upload_with_progress`] and [`Sftp::download_with_progress`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Progress {
+    /// Number of bytes transferred so far.
+    pub transferred: u64,
+
+    /// Size of the file being transferred, if known.
+    pub total: Option<u64>,
+}
+
+/// An sftp session on top of a [`Session`], created by [`Session::sftp`].
+///
+/// All remote paths are resolved by the sftp server, relative paths are
+/// usually relative to the home directory of the remote user.
+///
+/// When the `Sftp` is dropped, the sftp subsystem is terminated and any errors
+/// silently ignored. To be alerted to errors, use [`close`](Sftp::close).
+#[derive(Debug)]
+pub struct Sftp<'s> {
+    sftp: SftpClient,
+    child: Child<&'s Session>,
+}
+
+impl<'s> Sftp<'s> {
+    pub(crate) async fn new(session: &'s Session) -> Result<Sftp<'s>, Error> {
+        let mut child = session
+            .subsystem("sftp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .await?;
+
+        let stdin = child.stdin().take().expect("stdin is piped");
+        let stdout = child.stdout().take().expect("stdout is piped");
+
+        let sftp = SftpClient::new(stdin, stdout, Default::default())
+            .await
+            .map_err(Error::Sftp)?;
+
+        Ok(Self { sftp, child })
+    }
+
+    /// Copy the local file `local` to `remote`, replacing `remote` if it exists.
+    ///
+    /// Returns the number of bytes copied.
+    pub async fn upload(
+        &self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+    ) -> Result<u64, Error> {
+        self.upload_with_progress(local, remote, |_| ()).await
+    }
+
+    /// Like [`upload`](Sftp::upload), but calls `progress` every time a chunk
+    /// of the file has been written to the remote host.
+    pub async fn upload_with_progress<F: FnMut(Progress)>(
+        &self,
+        local: impl AsRef<Path>,
+        remote: impl AsRef<Path>,
+        mut progress: F,
+    ) -> Result<u64, Error> {
+        let mut local = fs::File::open(local).await.map_err(Error::LocalIo)?;
+        let total = local.metadata().await.map_err(Error::LocalIo)?.len();
+
+        let mut remote = self.sftp.create(remote).await.map_err(Error::Sftp)?;
+
+        let mut buffer = vec![0; CHUNK_SIZE as usize];
+        let mut transferred = 0;
+
+        loop {
+            let n = local.read(&mut buffer).await.map_err(Error::LocalIo)?;
+            if n == 0 {
+                break;
+            }
+
+            remote.write_all(&buffer[..n]).await.map_err(Error::Sftp)?;
+
+            transferred += n as u64;
+            progress(Progress {
+                transferred,
+                total: Some(total),
+            });
+        }
+
+        remote.close().await.map_err(Error::Sftp)?;
+
+        Ok(transferred)
+    }
+
+    /// Copy the remote file `remote` to `local`, replacing `local` if it exists.
+    ///
+    /// Returns the number of bytes copied.
+    pub async fn download(
+        &self,
+        remote: impl AsRef<Path>,
+        local: impl AsRef<Path>,
+    ) -> Result<u64, Error> {
+        self.download_with_progress(remote, local, |_| ()).await
+    }
+
+    /// Like [`download`](Sftp::download), but calls `progress` every time a
+    /// chunk of the file has been received from the remote host.
+    pub async fn download_with_progress<F: FnMut(Progress)>(
+        &self,
+        remote: impl AsRef<Path>,
+        local: impl AsRef<Path>,
+        mut progress: F,
+    ) -> Result<u64, Error> {
+        let mut remote = self.sftp.open(remote).await.map_err(Error::Sftp)?;
+        let total = remote.metadata().await.map_err(Error::Sftp)?.len();
+
+        let mut local = fs::File::create(local).await.map_err(Error::LocalIo)?;
+
+        let mut transferred = 0;
+
+        while let Some(chunk) = remote
+            .read(CHUNK_SIZE, Default::default())
+            .await
+            .map_err(Error::Sftp)?
+        {
+            local.write_all(&chunk).await.map_err(Error::LocalIo)?;
+
+            transferred += chunk.len() as u64;
+            progress(Progress { transferred, total });
+        }
+
+        // tokio writes to the file in the background, so make sure that
+        // all the data actually made it to the file.
+        local.flush().await.map_err(Error::LocalIo)?;
+        remote.close().await.map_err(Error::Sftp)?;
+
+        Ok(transferred)
+    }
+
+    /// Read the entire content of the remote file `remote`.
+    pub async fn read_to_vec(&self, remote: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
+        let content = self.sftp.fs().read(remote).await.map_err(Error::Sftp)?;
+        Ok(content.to_vec())
+    }
+
+    /// Write `content` to the remote file `remote`, replacing it if it exists.
+    pub async fn write_all(
+        &self,
+        remote: impl AsRef<Path>,
+        content: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        self.sftp
+            .fs()
+            .write(remote, content)
+            .await
+            .map_err(Error::Sftp)
+    }
+
+    /// Query the metadata of `remote`, following symlinks.
+    pub async fn metadata(&self, remote: impl AsRef<Path>) -> Result<MetaData, Error> {
+        self.sftp.fs().metadata(remote).await.map_err(Error::Sftp)
+    }
+
+    /// List the entries of the remote directory `remote`, excluding `.` and `..`.
+    pub async fn read_dir(&self, remote: impl AsRef<Path>) -> Result<Vec<DirEntry>, Error> {
+        let dir = self.sftp.fs().open_dir(remote).await.map_err(Error::Sftp)?;
+
+        let read_dir = dir.read_dir();
+        tokio::pin!(read_dir);
+
+        let mut entries = Vec::new();
+        while let Some(entry) = Next(read_dir.as_mut()).await {
+            let entry = entry.map_err(Error::Sftp)?;
+            if entry.filename() != Path::new(".") && entry.filename() != Path::new("..") {
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Remove the remote file or empty directory `remote`.
+    ///
+    /// Symlinks are removed rather than followed.
+    pub async fn remove(&self, remote: impl AsRef<Path>) -> Result<(), Error> {
+        let mut fs = self.sftp.fs();
+
+        let metadata = fs
+            .symlink_metadata(remote.as_ref())
+            .await
+            .map_err(Error::Sftp)?;
+
+        if metadata.file_type().map_or(false, |ty| ty.is_dir()) {
+            fs.remove_dir(remote).await
+        } else {
+            fs.remove_file(remote).await
+        }
+        .map_err(Error::Sftp)
+    }
+
+    /// Rename the remote file or directory `from` to `to`.
+    pub async fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<(), Error> {
+        self.sftp.fs().rename(from, to).await.map_err(Error::Sftp)
+    }
+
+    /// Close the sftp session and wait for the sftp subsystem to exit.
+    pub async fn close(self) -> Result<(), Error> {
+        self.sftp.close().await.map_err(Error::Sftp)?;
+
+        let status = self.child.wait().await?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::Sftp(openssh_sftp_client::Error::SftpServerFailure(
+                status,
+            )))
+        }
+    }
+}
+
+/// Future returning the next item of a [`Stream`].
+struct Next<'a, S: ?Sized>(Pin<&'a mut S>);
+
+impl<S: Stream + ?Sized> Future for Next<'_, S> {
+    type Output = Option<S::Item>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll_next(cx)
+    }
+}
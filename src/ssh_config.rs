@@ -0,0 +1,1111 @@
+//! Configuration of the `ssh` client.
+//!
+//! [`SshConfig`] is the configuration ssh uses for a destination. It is either resolved
+//! by ssh itself with [`SessionBuilder::effective_config`], or without running ssh by
+//! [`ConfigFile::resolve`].
+//!
+//! [`ConfigFile`] parses OpenSSH client configuration files (see `ssh_config(5)`), and
+//! [`SessionBuilder::to_config_file`] generates one from the options of a builder.
+
+#[cfg(doc)]
+use super::SessionBuilder;
+
+use super::Error;
+
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Maximum nesting of `Include` directives, the same as ssh's.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Options that may be given multiple times, all other options take the
+/// first value obtained.
+const CUMULATIVE: &[&str] = &[
+    "certificatefile",
+    "dynamicforward",
+    "identityfile",
+    "localforward",
+    "remoteforward",
+    "sendenv",
+    "setenv",
+];
+
+/// Options whose values are paths that may start with `~`.
+const PATHS: &[&str] = &[
+    "certificatefile",
+    "controlpath",
+    "identityagent",
+    "identityfile",
+    "userknownhostsfile",
+];
+
+/// Options whose values `%` tokens are expanded in, besides [`PATHS`].
+const TOKENS: &[&str] = &[
+    "knownhostscommand",
+    "localcommand",
+    "localforward",
+    "proxycommand",
+    "remotecommand",
+    "remoteforward",
+];
+
+/// Configuration ssh uses for a destination, as returned by
+/// [`SessionBuilder::effective_config`] and [`ConfigFile::resolve`].
+///
+/// The most commonly needed options are available as fields, and every
+/// option can be looked up by its keyword with [`get`](SshConfig::get).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct SshConfig {
+    /// The user to log in as (`User`).
+    pub user: Option<String>,
+
+    /// The host to connect to (`HostName`).
+    pub host_name: Option<String>,
+
+    /// The port to connect to (`Port`).
+    pub port: Option<u16>,
+
+    /// The files to read the identities for authentication from (`IdentityFile`), which may
+    /// still refer to the home directory with `~`.
+    pub identity_files: Vec<PathBuf>,
+
+    /// The jump hosts to connect through (`ProxyJump`).
+    pub proxy_jump: Option<String>,
+
+    /// All options in order, with lowercase keywords.
+    entries: Vec<(String, String)>,
+}
+
+impl SshConfig {
+    /// Parse the output of `ssh -G`, which prints one lowercase keyword
+    /// followed by its value per line.
+    pub(crate) fn from_effective(output: &str) -> Self {
+        let entries = output
+            .lines()
+            .filter_map(|line| {
+                let (keyword, value) = line.trim().split_once(' ')?;
+                Some((keyword.to_ascii_lowercase(), value.trim().to_owned()))
+            })
+            .collect();
+
+        Self::from_entries(entries)
+    }
+
+    fn from_entries(entries: Vec<(String, String)>) -> Self {
+        let mut config = Self {
+            entries,
+            ..Self::default()
+        };
+
+        config.user = config.get("User").map(str::to_owned);
+        config.host_name = config.get("HostName").map(str::to_owned);
+        config.port = config.get("Port").and_then(|port| port.parse().ok());
+        config.identity_files = config.get_all("IdentityFile").map(PathBuf::from).collect();
+        config.proxy_jump = config
+            .get("ProxyJump")
+            .filter(|jump| !jump.eq_ignore_ascii_case("none"))
+            .map(str::to_owned);
+
+        config
+    }
+
+    /// Return the value of the option `keyword`, which is case-insensitive.
+    ///
+    /// For options that may be given multiple times, such as `IdentityFile`,
+    /// this is the first value, see [`get_all`](SshConfig::get_all).
+    pub fn get(&self, keyword: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(keyword))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Return all values of the option `keyword`, which is case-insensitive.
+    pub fn get_all<'a>(&'a self, keyword: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(keyword))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Return all options as pairs of lowercase keyword and value, in order.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(keyword, value)| (keyword.as_str(), value.as_str()))
+    }
+}
+
+/// An OpenSSH client configuration file, see `ssh_config(5)`.
+///
+/// The file can be [parsed](ConfigFile::parse) from a string or [loaded](ConfigFile::load)
+/// along with the files it includes, and then [resolved](ConfigFile::resolve) for a host
+/// without running ssh. Formatting the file with [`Display`](fmt::Display) produces a
+/// configuration file again, without the comments of the original.
+///
+/// ```rust
+/// use openssh::ssh_config::ConfigFile;
+///
+/// let file = ConfigFile::parse(
+///     "Host *.internal\n\
+///      \x20   ProxyJump bastion.example.com\n\
+///      \x20   IdentityFile ~/.ssh/%h\n\
+///      Host *\n\
+///      \x20   User deploy\n",
+/// )?;
+///
+/// let config = file.resolve("db.internal");
+/// assert_eq!(config.user.as_deref(), Some("deploy"));
+/// assert_eq!(config.proxy_jump.as_deref(), Some("bastion.example.com"));
+/// # Ok::<(), openssh::Error>(())
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ConfigFile {
+    path: Option<PathBuf>,
+    /// The options before the first `Host` or `Match` line, followed by one block per line.
+    blocks: Vec<Block>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Block {
+    /// The `Host` or `Match` line starting the block, `None` for the options
+    /// at the start of the file which apply to every host.
+    header: Option<Header>,
+    lines: Vec<Line>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Header {
+    /// The line as written, without the leading whitespace.
+    line: String,
+    condition: Condition,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Condition {
+    /// `Host`, matched against the host given on the command line.
+    Host(Vec<String>),
+    /// `Match`, all of whose criteria have to match.
+    Match(Vec<Criterion>),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Criterion {
+    negated: bool,
+    /// Lowercase name of the criterion, e.g. `host`.
+    name: String,
+    arg: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Line {
+    Option {
+        keyword: String,
+        value: String,
+    },
+    Include {
+        value: String,
+        /// The included files, empty if the file was [parsed](ConfigFile::parse).
+        files: Vec<ConfigFile>,
+    },
+}
+
+impl ConfigFile {
+    /// Parse the configuration file `config`.
+    ///
+    /// `Include` directives are kept, but not followed, use [`load`](ConfigFile::load) to
+    /// read the included files as well.
+    pub fn parse(config: &str) -> Result<Self, Error> {
+        parse(config, None)
+    }
+
+    /// Read and parse the configuration file at `path`, along with the files it includes.
+    ///
+    /// As in ssh, relative paths of included files are relative to `/etc/ssh` for files in
+    /// `/etc/ssh` and to `~/.ssh` otherwise, and included files that do not exist are ignored.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let base = if path.starts_with("/etc/ssh") {
+            PathBuf::from("/etc/ssh")
+        } else {
+            home_dir().unwrap_or_default().join(".ssh")
+        };
+
+        load(path, &base, 0)
+    }
+
+    pub(crate) fn from_host(host: &str, options: Vec<(&'static str, String)>) -> Self {
+        let lines = options
+            .into_iter()
+            .map(|(keyword, value)| Line::Option {
+                keyword: keyword.to_owned(),
+                value,
+            })
+            .collect();
+
+        Self {
+            path: None,
+            blocks: vec![Block {
+                header: Some(Header {
+                    line: format!("Host {}", quote(host)),
+                    condition: Condition::Host(vec![host.to_owned()]),
+                }),
+                lines,
+            }],
+        }
+    }
+
+    /// Append an option with a keyword that is not known in advance to the last block.
+    pub(crate) fn push_option(&mut self, keyword: &str, value: &str) {
+        self.push_line(Line::Option {
+            keyword: keyword.to_owned(),
+            value: value.to_owned(),
+        });
+    }
+
+    /// Append an `Include` to the last block, without reading the file.
+    pub(crate) fn push_include(&mut self, value: &str) {
+        self.push_line(Line::Include {
+            value: value.to_owned(),
+            files: Vec::new(),
+        });
+    }
+
+    fn push_line(&mut self, line: Line) {
+        match self.blocks.last_mut() {
+            Some(block) => block.lines.push(line),
+            None => self.blocks.push(Block {
+                header: None,
+                lines: vec![line],
+            }),
+        }
+    }
+
+    /// The path the file was [loaded](ConfigFile::load) from.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Resolve the configuration ssh would use for `destination` according to this file.
+    ///
+    /// `destination` is a host or an alias, optionally with a user as in `user@host` or
+    /// given as `ssh://[user@]host[:port]`, and the user and port given that way take
+    /// precedence over the file like in ssh.
+    ///
+    /// `User`, `HostName` and `Port` default to the local user, the host and 22, but the
+    /// other defaults of ssh are not filled in. `%` tokens such as `%h`, `%p` and `%r` are
+    /// expanded and `~` is replaced by the home directory in the options that support them.
+    ///
+    /// Since no command is run, `Match exec` never matches, and neither does
+    /// `Match localnetwork`. No host name is canonicalized, so `Match canonical` matches
+    /// in the same pass as `Match final`. Criteria depending on the session, such as
+    /// `Match version` or `Match sessiontype`, and criteria unknown to this crate never
+    /// match, even when negated.
+    pub fn resolve(&self, destination: &str) -> SshConfig {
+        let (user, host, port) = parse_destination(destination);
+
+        let mut resolver = Resolver {
+            host,
+            local_user: local_user(),
+            final_pass: false,
+            entries: Vec::new(),
+        };
+        if let Some(user) = user {
+            resolver.set("user", user);
+        }
+        if let Some(port) = port {
+            resolver.set("port", port);
+        }
+
+        resolver.apply(self);
+        if self.has_final() {
+            resolver.final_pass = true;
+            resolver.apply(self);
+        }
+
+        resolver.finish()
+    }
+
+    /// Whether any `Match` requests a final pass.
+    fn has_final(&self) -> bool {
+        self.blocks.iter().any(|block| {
+            let header = block.header.as_ref().map(|header| &header.condition);
+            let final_criterion = matches!(header, Some(Condition::Match(criteria))
+                if criteria.iter().any(|c| c.name == "final" || c.name == "canonical"));
+
+            final_criterion
+                || block.lines.iter().any(|line| match line {
+                    Line::Include { files, .. } => files.iter().any(ConfigFile::has_final),
+                    Line::Option { .. } => false,
+                })
+        })
+    }
+}
+
+impl fmt::Display for ConfigFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for block in &self.blocks {
+            let indent = if let Some(header) = &block.header {
+                if !first {
+                    writeln!(f)?;
+                }
+                writeln!(f, "{}", header.line)?;
+                "    "
+            } else {
+                ""
+            };
+            first = false;
+
+            for line in &block.lines {
+                match line {
+                    Line::Option { keyword, value } => writeln!(f, "{indent}{keyword} {value}")?,
+                    Line::Include { value, .. } => writeln!(f, "{indent}Include {value}")?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn load(path: &Path, base: &Path, depth: usize) -> Result<ConfigFile, Error> {
+    let config = fs::read_to_string(path).map_err(Error::LocalIo)?;
+    let mut file = parse(&config, Some(path))?;
+
+    for line in file.blocks.iter_mut().flat_map(|block| &mut block.lines) {
+        if let Line::Include { value, files } = line {
+            if depth == MAX_INCLUDE_DEPTH {
+                // This is the first `Include` of the file.
+                let line = config
+                    .lines()
+                    .position(|line| split_keyword(line.trim()).0.eq_ignore_ascii_case("include"))
+                    .map_or(0, |i| i + 1);
+                return Err(Error::InvalidConfig {
+                    path: Some(path.to_owned()),
+                    line,
+                    reason: "too many nested includes".to_owned(),
+                });
+            }
+
+            // The arguments were checked while parsing.
+            for pattern in split_args(value).unwrap_or_default() {
+                let pattern = expand_tilde(&pattern);
+                for include in glob(&base.join(&*pattern)) {
+                    files.push(load(&include, base, depth + 1)?);
+                }
+            }
+        }
+    }
+
+    Ok(file)
+}
+
+fn parse(config: &str, path: Option<&Path>) -> Result<ConfigFile, Error> {
+    let mut blocks = vec![Block {
+        header: None,
+        lines: Vec::new(),
+    }];
+
+    for (i, line) in config.lines().enumerate() {
+        let err = |reason: String| Error::InvalidConfig {
+            path: path.map(Path::to_owned),
+            line: i + 1,
+            reason,
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, value) = split_keyword(line);
+        if value.is_empty() {
+            return Err(err(format!("missing argument for {keyword}")));
+        }
+        let args = split_args(value).map_err(err)?;
+
+        let condition = if keyword.eq_ignore_ascii_case("host") {
+            Condition::Host(args)
+        } else if keyword.eq_ignore_ascii_case("match") {
+            Condition::Match(parse_criteria(args).map_err(err)?)
+        } else {
+            let line = if keyword.eq_ignore_ascii_case("include") {
+                Line::Include {
+                    value: value.to_owned(),
+                    files: Vec::new(),
+                }
+            } else {
+                Line::Option {
+                    keyword: keyword.to_owned(),
+                    value: value.to_owned(),
+                }
+            };
+            blocks
+                .last_mut()
+                .expect("there is a block")
+                .lines
+                .push(line);
+            continue;
+        };
+
+        blocks.push(Block {
+            header: Some(Header {
+                line: format!("{keyword} {value}"),
+                condition,
+            }),
+            lines: Vec::new(),
+        });
+    }
+
+    if blocks[0].lines.is_empty() {
+        blocks.remove(0);
+    }
+
+    Ok(ConfigFile {
+        path: path.map(Path::to_owned),
+        blocks,
+    })
+}
+
+/// Split `line` into the keyword and the value, which are separated by whitespace
+/// and optionally `=`.
+fn split_keyword(line: &str) -> (&str, &str) {
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let (keyword, rest) = line.split_at(end);
+
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+    (keyword, rest.trim_start())
+}
+
+/// Split `value` into whitespace-separated arguments the way ssh does: parts of an argument
+/// may be quoted with `"` or `'`, and `\` escapes quotes, backslashes and unquoted spaces.
+fn split_args(value: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = value.trim().chars().peekable();
+
+    while chars.peek().is_some() {
+        if chars.next_if(|c| c.is_whitespace()).is_some() {
+            continue;
+        }
+
+        let mut arg = String::new();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let escaped = chars.next_if(|&next| {
+                        matches!(next, '\\' | '"' | '\'') || (quote.is_none() && next == ' ')
+                    });
+                    arg.push(escaped.unwrap_or(c));
+                }
+                c if quote == Some(c) => quote = None,
+                '"' | '\'' if quote.is_none() => quote = Some(c),
+                c if quote.is_none() && c.is_whitespace() => break,
+                c => arg.push(c),
+            }
+        }
+        if quote.is_some() {
+            return Err(format!("unterminated quote in {value:?}"));
+        }
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+fn parse_criteria(args: Vec<String>) -> Result<Vec<Criterion>, String> {
+    let mut criteria = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let (negated, name) = match arg.strip_prefix('!') {
+            Some(name) => (true, name.to_ascii_lowercase()),
+            None => (false, arg.to_ascii_lowercase()),
+        };
+
+        let arg = match name.as_str() {
+            "all" | "canonical" | "final" => None,
+            "command" | "exec" | "host" | "localnetwork" | "localuser" | "originalhost"
+            | "sessiontype" | "tagged" | "user" | "version" => Some(
+                args.next()
+                    .ok_or_else(|| format!("missing argument for Match {name}"))?,
+            ),
+            // Criteria added by later versions of ssh, assumed to take an argument like
+            // the recent ones do. They never match.
+            _ => args.next(),
+        };
+
+        criteria.push(Criterion { negated, name, arg });
+    }
+
+    Ok(criteria)
+}
+
+/// Quote `value` for a configuration file if it contains whitespace, quotes or backslashes.
+pub(crate) fn quote(value: &str) -> Cow<'_, str> {
+    if value.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\')) {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+        Cow::Owned(format!("\"{escaped}\""))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Split `destination` into the user, host and port.
+fn parse_destination(destination: &str) -> (Option<&str>, &str, Option<&str>) {
+    let (destination, url) = match destination.strip_prefix("ssh://") {
+        Some(destination) => (destination, true),
+        None => (destination, false),
+    };
+
+    let (user, mut host) = match destination.rsplit_once('@') {
+        Some((user, host)) => (Some(user), host),
+        None => (None, destination),
+    };
+
+    let mut port = None;
+    if url {
+        if let Some((h, p)) = host.rsplit_once(':') {
+            if p.parse::<u16>().is_ok() {
+                host = h;
+                port = Some(p);
+            }
+        }
+    }
+
+    (user, host, port)
+}
+
+struct Resolver<'a> {
+    /// The host as given on the command line.
+    host: &'a str,
+    local_user: String,
+    final_pass: bool,
+    entries: Vec<(String, String)>,
+}
+
+impl Resolver<'_> {
+    fn get(&self, keyword: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == keyword)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn set(&mut self, keyword: &str, value: &str) {
+        let keyword = keyword.to_ascii_lowercase();
+        // Unquote values consisting of a single quoted argument, e.g. a path.
+        let args = split_args(value).unwrap_or_default();
+        let value = match &args[..] {
+            [arg] if value.starts_with(['"', '\'']) => arg.as_str(),
+            _ => value,
+        };
+
+        let is_set = if CUMULATIVE.contains(&keyword.as_str()) {
+            // Like ssh, skip duplicates, which the final pass would otherwise add.
+            self.entries
+                .iter()
+                .any(|(k, v)| *k == keyword && v == value)
+        } else {
+            self.get(&keyword).is_some()
+        };
+        if !is_set {
+            self.entries.push((keyword, value.to_owned()));
+        }
+    }
+
+    fn apply(&mut self, file: &ConfigFile) {
+        for block in &file.blocks {
+            if let Some(header) = &block.header {
+                if !self.matches(&header.condition) {
+                    continue;
+                }
+            }
+
+            for line in &block.lines {
+                match line {
+                    Line::Option { keyword, value } => self.set(keyword, value),
+                    Line::Include { files, .. } => files.iter().for_each(|file| self.apply(file)),
+                }
+            }
+        }
+    }
+
+    /// The host name to connect to, as far as it is known.
+    fn host_name(&self) -> String {
+        match self.get("hostname") {
+            Some(host_name) => expand_host_name(host_name, self.host),
+            None => self.host.to_owned(),
+        }
+    }
+
+    fn user(&self) -> &str {
+        self.get("user").unwrap_or(&self.local_user)
+    }
+
+    fn matches(&self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Host(patterns) => match_list(patterns, self.host, true),
+            Condition::Match(criteria) => criteria.iter().all(|criterion| {
+                let arg = criterion.arg.as_deref().unwrap_or_default();
+                let list: Vec<String> = arg.split(',').map(str::to_owned).collect();
+
+                let matched = match criterion.name.as_str() {
+                    "all" => true,
+                    "canonical" | "final" => self.final_pass,
+                    "host" => match_list(&list, &self.host_name(), true),
+                    "originalhost" => match_list(&list, self.host, true),
+                    "user" => match_list(&list, self.user(), false),
+                    "localuser" => match_list(&list, &self.local_user, false),
+                    "tagged" => match_list(&list, self.get("tag").unwrap_or_default(), false),
+                    // Running commands or inspecting the network is out of scope.
+                    "exec" | "localnetwork" => false,
+                    // The remote command, session type and ssh version are not known here,
+                    // so these and unknown criteria never match, even when negated.
+                    _ => return false,
+                };
+
+                matched != criterion.negated
+            }),
+        }
+    }
+
+    fn finish(mut self) -> SshConfig {
+        let host_name = self.host_name();
+        self.entries.retain(|(keyword, _)| keyword != "hostname");
+        self.entries.push(("hostname".to_owned(), host_name));
+        if self.get("user").is_none() {
+            self.entries
+                .push(("user".to_owned(), self.local_user.clone()));
+        }
+        if self.get("port").is_none() {
+            self.entries.push(("port".to_owned(), "22".to_owned()));
+        }
+
+        let tokens = Tokens {
+            host: self.host,
+            host_name: self.get("hostname").unwrap_or_default(),
+            port: self.get("port").unwrap_or_default(),
+            user: self.get("user").unwrap_or_default(),
+            local_user: &self.local_user,
+            proxy_jump: self.get("proxyjump").unwrap_or_default(),
+            host_key_alias: self.get("hostkeyalias").unwrap_or(self.host),
+        }
+        .into_owned();
+
+        for (keyword, value) in &mut self.entries {
+            let keyword = keyword.as_str();
+            if PATHS.contains(&keyword) {
+                *value = tokens.expand(&expand_tilde(value));
+            } else if TOKENS.contains(&keyword) {
+                *value = tokens.expand(value);
+            }
+        }
+
+        SshConfig::from_entries(self.entries)
+    }
+}
+
+/// Values of the `%` tokens, see the TOKENS section of `ssh_config(5)`.
+struct Tokens<S> {
+    host: S,
+    host_name: S,
+    port: S,
+    user: S,
+    local_user: S,
+    proxy_jump: S,
+    host_key_alias: S,
+}
+
+impl Tokens<&str> {
+    fn into_owned(self) -> Tokens<String> {
+        Tokens {
+            host: self.host.to_owned(),
+            host_name: self.host_name.to_owned(),
+            port: self.port.to_owned(),
+            user: self.user.to_owned(),
+            local_user: self.local_user.to_owned(),
+            proxy_jump: self.proxy_jump.to_owned(),
+            host_key_alias: self.host_key_alias.to_owned(),
+        }
+    }
+}
+
+impl Tokens<String> {
+    /// Expand the tokens in `value`, leaving unknown tokens as they are.
+    fn expand(&self, value: &str) -> String {
+        let mut expanded = String::with_capacity(value.len());
+        let mut chars = value.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+
+            let token = chars.next();
+            match token {
+                Some('%') => expanded.push('%'),
+                Some('d') => expanded.push_str(&home_dir().unwrap_or_default().to_string_lossy()),
+                Some('h') => expanded.push_str(&self.host_name),
+                Some('j') => expanded.push_str(&self.proxy_jump),
+                Some('k') => expanded.push_str(&self.host_key_alias),
+                Some('L') => {
+                    let host_name = local_host_name();
+                    expanded.push_str(host_name.split('.').next().unwrap_or_default());
+                }
+                Some('l') => expanded.push_str(&local_host_name()),
+                Some('n') => expanded.push_str(&self.host),
+                Some('p') => expanded.push_str(&self.port),
+                Some('r') => expanded.push_str(&self.user),
+                Some('u') => expanded.push_str(&self.local_user),
+                _ => {
+                    expanded.push('%');
+                    expanded.extend(token);
+                }
+            }
+        }
+
+        expanded
+    }
+}
+
+/// Expand `HostName`, which only supports `%h` and `%%`.
+fn expand_host_name(host_name: &str, host: &str) -> String {
+    host_name
+        .split("%%")
+        .map(|part| part.replace("%h", host))
+        .collect::<Vec<_>>()
+        .join("%")
+}
+
+/// Whether `s` matches the comma- or whitespace-separated `patterns`, which may be negated
+/// with `!`. A negated pattern that matches overrides all others.
+fn match_list(patterns: &[String], s: &str, ignore_case: bool) -> bool {
+    let mut matched = false;
+
+    for pattern in patterns.iter().flat_map(|pattern| pattern.split(',')) {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+
+        let is_match = if ignore_case {
+            wildcard_match(
+                pattern.to_ascii_lowercase().as_bytes(),
+                s.to_ascii_lowercase().as_bytes(),
+            )
+        } else {
+            wildcard_match(pattern.as_bytes(), s.as_bytes())
+        };
+
+        if is_match {
+            if negated {
+                return false;
+            }
+            matched = true;
+        }
+    }
+
+    matched
+}
+
+/// Whether `s` matches `pattern`, in which `*` matches any number of characters and `?`
+/// exactly one.
+fn wildcard_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| wildcard_match(rest, &s[i..])),
+        Some((b'?', rest)) => !s.is_empty() && wildcard_match(rest, &s[1..]),
+        Some((c, rest)) => s.first() == Some(c) && wildcard_match(rest, &s[1..]),
+    }
+}
+
+/// The existing paths matching `pattern`, in which any component may contain wildcards,
+/// in sorted order.
+fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::new()];
+
+    for component in pattern.components() {
+        let component = component.as_os_str();
+        let component_str = component.to_string_lossy();
+
+        if !component_str.contains(['*', '?']) {
+            for path in &mut paths {
+                path.push(component);
+            }
+            continue;
+        }
+
+        let mut matched = Vec::new();
+        for path in &paths {
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                // Like the shell, wildcards do not match hidden files.
+                if name.starts_with('.') && !component_str.starts_with('.') {
+                    continue;
+                }
+                if wildcard_match(component_str.as_bytes(), name.as_bytes()) {
+                    matched.push(path.join(&*name));
+                }
+            }
+        }
+        matched.sort();
+        paths = matched;
+    }
+
+    paths.retain(|path| path.exists());
+    paths
+}
+
+fn expand_tilde(path: &str) -> Cow<'_, str> {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => match home_dir() {
+            Some(home) => Cow::Owned(format!("{}{}", home.display(), rest)),
+            None => Cow::Borrowed(path),
+        },
+        _ => Cow::Borrowed(path),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    #[allow(deprecated)]
+    std::env::home_dir()
+}
+
+fn local_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_default()
+}
+
+fn local_host_name() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: `buf` is valid for writes of its length.
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if ret != 0 {
+        return String::new();
+    }
+    // The name is truncated without a terminating nul byte if it is too long.
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effective() {
+        let config = SshConfig::from_effective(
+            "host example\n\
+             user test-user\n\
+             hostname 192.0.2.1\n\
+             port 2222\n\
+             identityfile ~/.ssh/id_ed25519\n\
+             identityfile ~/.ssh/id_rsa\n\
+             sendenv LANG LC_*\n",
+        );
+
+        assert_eq!(config.user.as_deref(), Some("test-user"));
+        assert_eq!(config.host_name.as_deref(), Some("192.0.2.1"));
+        assert_eq!(config.port, Some(2222));
+        assert_eq!(
+            config.identity_files,
+            [
+                PathBuf::from("~/.ssh/id_ed25519"),
+                PathBuf::from("~/.ssh/id_rsa")
+            ]
+        );
+        assert_eq!(config.proxy_jump, None);
+        assert_eq!(config.get("SendEnv"), Some("LANG LC_*"));
+        assert_eq!(config.get("ProxyCommand"), None);
+        assert_eq!(config.entries().count(), 7);
+    }
+
+    #[test]
+    fn parse_lines() {
+        assert_eq!(split_keyword("Port 22"), ("Port", "22"));
+        assert_eq!(split_keyword("Port=22"), ("Port", "22"));
+        assert_eq!(split_keyword("Port = 22"), ("Port", "22"));
+        assert_eq!(
+            split_args(r#"a "b c"  d"#).unwrap(),
+            ["a".to_owned(), "b c".to_owned(), "d".to_owned()]
+        );
+        assert_eq!(
+            split_args(r#"'a "b'"c d" e\ f \"g\\ \h"#).unwrap(),
+            [r#"a "bc d"#, "e f", r#""g\"#, r"\h"].map(str::to_owned)
+        );
+        assert!(split_args(r#"a "b"#).is_err());
+        assert!(split_args(r#"a 'b"#).is_err());
+        assert_eq!(quote(r#"a\b"c"#), r#""a\\b\"c""#);
+        assert_eq!(split_args(&quote(r#"a \b"c"#)).unwrap(), [r#"a \b"c"#]);
+
+        for (config, line) in [
+            ("Port 22\nHost\n", 2),
+            ("Match version\n", 1),
+            ("Match user\n", 1),
+            ("IdentityFile \"~/key\n", 1),
+        ] {
+            match ConfigFile::parse(config).unwrap_err() {
+                Error::InvalidConfig { line: l, .. } => assert_eq!(l, line, "{config}"),
+                e => unreachable!("{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(wildcard_match(b"*.example.com", b"a.example.com"));
+        assert!(wildcard_match(b"db?", b"db1"));
+        assert!(!wildcard_match(b"db?", b"db"));
+        assert!(!wildcard_match(b"*.example.com", b"example.com"));
+
+        let list = |s: &str| vec![s.to_owned()];
+        assert!(match_list(
+            &list("*.internal,!secret.internal"),
+            "db.internal",
+            true
+        ));
+        assert!(!match_list(
+            &list("*.internal,!secret.internal"),
+            "secret.internal",
+            true
+        ));
+        assert!(!match_list(&list("!secret.internal"), "db.internal", true));
+        assert!(match_list(&list("DB"), "db", true));
+        assert!(!match_list(&list("Admin"), "admin", false));
+    }
+
+    #[test]
+    fn resolve() {
+        let file = ConfigFile::parse(
+            "# top-level options apply to every host\n\
+             Compression yes\n\
+             \n\
+             Host db !db.example.com\n\
+             \x20   HostName %h.internal\n\
+             \x20   IdentityFile ~/.ssh/db_%r_%p\n\
+             \x20   ProxyCommand ssh -W %h:%p %j\n\
+             \n\
+             Match host *.internal user admin\n\
+             \x20   Port 2222\n\
+             \x20   ProxyJump bastion\n\
+             \n\
+             Match final\n\
+             \x20   ServerAliveInterval 30\n\
+             \n\
+             Match version OpenSSH_* !sessiontype none !nosuch x\n\
+             \x20   Port 1\n\
+             \n\
+             Host *\n\
+             \x20   User=deploy\n\
+             \x20   Port 22\n\
+             \x20   IdentityFile \"~/.ssh/id ed25519\"\n\
+             \x20   IdentityFile '~/.ssh/id \\\\ \"rsa\"'\n\
+             \x20   Compression no\n",
+        )
+        .unwrap();
+
+        let home = home_dir().unwrap_or_default();
+        let home = home.display();
+
+        let config = file.resolve("db");
+        assert_eq!(config.host_name.as_deref(), Some("db.internal"));
+        assert_eq!(config.user.as_deref(), Some("deploy"));
+        assert_eq!(config.port, Some(22));
+        assert_eq!(config.proxy_jump, None);
+        assert_eq!(config.get("compression"), Some("yes"));
+        assert_eq!(config.get("ServerAliveInterval"), Some("30"));
+        assert_eq!(
+            config.identity_files,
+            [
+                PathBuf::from(format!("{home}/.ssh/db_deploy_22")),
+                PathBuf::from(format!("{home}/.ssh/id ed25519")),
+                PathBuf::from(format!(r#"{home}/.ssh/id \ "rsa""#)),
+            ]
+        );
+
+        // The user given with the destination takes precedence.
+        let config = file.resolve("ssh://admin@db:2200");
+        assert_eq!(config.user.as_deref(), Some("admin"));
+        assert_eq!(config.port, Some(2200));
+        assert_eq!(config.proxy_jump.as_deref(), Some("bastion"));
+        assert_eq!(
+            config.get("ProxyCommand"),
+            Some("ssh -W db.internal:2200 bastion")
+        );
+
+        let config = file.resolve("admin@db.example.com");
+        assert_eq!(config.host_name.as_deref(), Some("db.example.com"));
+        assert_eq!(config.port, Some(22));
+        assert_eq!(config.proxy_jump, None);
+    }
+
+    #[test]
+    fn display() {
+        let config = "Compression yes\n\
+                      \n\
+                      Host db\n\
+                      \x20   HostName=%h.internal\n\
+                      \x20   Include \"~/.ssh/db config\"\n\
+                      \n\
+                      Match !final\n\
+                      \x20   User deploy\n";
+        let file = ConfigFile::parse(config).unwrap();
+
+        let expected = config.replace("HostName=", "HostName ");
+        assert_eq!(file.to_string(), expected);
+        assert_eq!(ConfigFile::parse(&expected).unwrap(), file);
+    }
+
+    #[test]
+    fn load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        fs::create_dir(dir.path().join("conf.d")).unwrap();
+        fs::write(
+            &path,
+            format!(
+                "Host db\n    Include {}/conf.d/*.conf\nHost *\n    Port 22\n",
+                dir.path().display()
+            ),
+        )
+        .unwrap();
+        fs::write(dir.path().join("conf.d/b.conf"), "Port 2200\n").unwrap();
+        fs::write(dir.path().join("conf.d/a.conf"), "User a\nHost *\nPort 2\n").unwrap();
+        fs::write(dir.path().join("conf.d/.hidden.conf"), "User hidden\n").unwrap();
+
+        let file = ConfigFile::load(&path).unwrap();
+        assert_eq!(file.path(), Some(&*path));
+
+        // The includes only apply to `db`, but `Host` lines in included files
+        // start new blocks within them.
+        let config = file.resolve("db");
+        assert_eq!(config.user.as_deref(), Some("a"));
+        assert_eq!(config.port, Some(2));
+        assert_eq!(file.resolve("web").port, Some(22));
+
+        fs::write(&path, format!("Port 22\nInclude {}\n", path.display())).unwrap();
+        match ConfigFile::load(&path).unwrap_err() {
+            Error::InvalidConfig { line, reason, .. } => {
+                assert_eq!(line, 2);
+                assert!(reason.contains("nested"));
+            }
+            e => unreachable!("{:?}", e),
+        }
+    }
+}
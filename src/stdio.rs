@@ -65,6 +65,20 @@ impl Stdio {
     }
 }
 
+impl Stdio {
+    /// Convert to [`process::Stdio`] without consuming `self`, so that
+    /// commands can be spawned more than once.
+    #[cfg(feature = "process-mux")]
+    pub(crate) fn try_clone_std(&self) -> io::Result<process::Stdio> {
+        Ok(match &self.0 {
+            StdioImpl::Null => process::Stdio::null(),
+            StdioImpl::Pipe => process::Stdio::piped(),
+            StdioImpl::Inherit => process::Stdio::inherit(),
+            StdioImpl::Fd(fd) => process::Stdio::from(fd.try_clone()?),
+        })
+    }
+}
+
 impl From<Stdio> for process::Stdio {
     fn from(stdio: Stdio) -> Self {
         match stdio.0 {
@@ -137,7 +151,11 @@ pub struct ChildStdin(PipeWriter);
 
 /// Stdout for the remote child.
 #[derive(Debug)]
-pub struct ChildStdout(PipeReader);
+pub struct ChildStdout(
+    PipeReader,
+    /// Whether this is the master side of a pty.
+    bool,
+);
 
 /// Stderr for the remote child.
 #[derive(Debug)]
@@ -151,6 +169,10 @@ pub(crate) trait TryFromChildIo<T>: Sized {
 
 macro_rules! impl_from_impl_child_io {
     (process, $type:ident, $inner:ty) => {
+        impl_from_impl_child_io!(process, $type, $inner, Self);
+    };
+
+    (process, $type:ident, $inner:ty, $new:expr) => {
         impl TryFromChildIo<tokio::process::$type> for $type {
             type Error = Error;
 
@@ -158,30 +180,34 @@ macro_rules! impl_from_impl_child_io {
                 let fd = arg.into_owned_fd().map_err(Error::ChildIo)?;
 
                 <$inner>::from_owned_fd(fd)
-                    .map(Self)
+                    .map($new)
                     .map_err(Error::ChildIo)
             }
         }
     };
 
     (native_mux, $type:ident) => {
+        impl_from_impl_child_io!(native_mux, $type, Self);
+    };
+
+    (native_mux, $type:ident, $new:expr) => {
         #[cfg(feature = "native-mux")]
         impl TryFromChildIo<native_mux_impl::$type> for $type {
             type Error = Error;
 
             fn try_from(arg: native_mux_impl::$type) -> Result<Self, Self::Error> {
-                Ok(Self(arg))
+                Ok($new(arg))
             }
         }
     };
 }
 
 impl_from_impl_child_io!(process, ChildStdin, PipeWriter);
-impl_from_impl_child_io!(process, ChildStdout, PipeReader);
+impl_from_impl_child_io!(process, ChildStdout, PipeReader, ChildStdout::from_pipe);
 impl_from_impl_child_io!(process, ChildStderr, PipeReader);
 
 impl_from_impl_child_io!(native_mux, ChildStdin);
-impl_from_impl_child_io!(native_mux, ChildStdout);
+impl_from_impl_child_io!(native_mux, ChildStdout, ChildStdout::from_pipe);
 impl_from_impl_child_io!(native_mux, ChildStderr);
 
 macro_rules! impl_child_stdio {
@@ -211,6 +237,10 @@ macro_rules! impl_child_stdio {
     };
 
     (AsyncRead, $type:ty) => {
+        impl_child_stdio!(AsyncRead, $type, |_| false);
+    };
+
+    (AsyncRead, $type:ty, $is_pty:expr) => {
         impl_child_stdio!(AsRawFd, $type);
         impl_child_stdio!(AsFd, $type);
         impl_child_stdio!(into_owned_fd, $type);
@@ -221,7 +251,15 @@ macro_rules! impl_child_stdio {
                 cx: &mut Context<'_>,
                 buf: &mut ReadBuf<'_>,
             ) -> Poll<io::Result<()>> {
-                Pin::new(&mut self.0).poll_read(cx, buf)
+                let is_pty = $is_pty(&*self);
+                match Pin::new(&mut self.0).poll_read(cx, buf) {
+                    // The master side of a pty reports `EIO` instead of EOF
+                    // once the slave side is closed.
+                    Poll::Ready(Err(err)) if is_pty && err.raw_os_error() == Some(libc::EIO) => {
+                        Poll::Ready(Ok(()))
+                    }
+                    poll => poll,
+                }
             }
         }
     };
@@ -266,6 +304,26 @@ macro_rules! impl_child_stdio {
     };
 }
 
+impl ChildStdin {
+    /// Write to the master side of a pty.
+    pub(crate) fn from_pty(master: OwnedFd) -> io::Result<Self> {
+        crate::pty::set_nonblocking(master.as_fd())?;
+        PipeWriter::from_file_unchecked(File::from(master)).map(Self)
+    }
+}
+
+impl ChildStdout {
+    fn from_pipe(reader: PipeReader) -> Self {
+        Self(reader, false)
+    }
+
+    /// Read from the master side of a pty.
+    pub(crate) fn from_pty(master: OwnedFd) -> io::Result<Self> {
+        crate::pty::set_nonblocking(master.as_fd())?;
+        PipeReader::from_file_unchecked(File::from(master)).map(|reader| Self(reader, true))
+    }
+}
+
 impl_child_stdio!(AsyncWrite, ChildStdin);
-impl_child_stdio!(AsyncRead, ChildStdout);
+impl_child_stdio!(AsyncRead, ChildStdout, |stdout: &ChildStdout| stdout.1);
 impl_child_stdio!(AsyncRead, ChildStderr);
@@ -0,0 +1,669 @@
+//! Recursive, rsync-like synchronisation of a local directory to the remote host.
+//!
+//! The remote tree is listed with a single GNU `find -printf` command and
+//! compared to the local tree. Changed entries are then streamed to the
+//! remote `tar` as an archive over stdin.
+
+use super::copy::{remote_failure, sh, CHUNK_SIZE, SHA256};
+use super::escape::escape;
+use super::{Error, Session, Stdio};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::task;
+
+/// Number of paths passed to a single remote command.
+const PATHS_PER_COMMAND: usize = 256;
+
+/// Options for [`Session::sync_dir`].
+#[derive(Clone, Debug, Default)]
+pub struct SyncOptions {
+    /// Delete remote entries that do not exist locally.
+    pub delete: bool,
+
+    /// Compare regular files of the same size by their sha256 checksum instead of by their
+    /// modification time.
+    ///
+    /// This requires `sha256sum`, `shasum` or `openssl` on the remote host.
+    pub checksum: bool,
+
+    /// Glob patterns of entries to exclude, both locally and remotely.
+    ///
+    /// `*` matches any sequence of characters except `/` and `?` matches any single character
+    /// except `/`. A pattern without a `/` is matched against the name of every entry, while a
+    /// pattern with a `/` is matched against the whole path relative to the synchronised
+    /// directory. Excluding a directory also excludes everything in it, and excluded remote
+    /// entries are never deleted.
+    pub exclude: Vec<String>,
+}
+
+/// Changes made by [`Session::sync_dir`], as paths relative to the synchronised directory.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct SyncReport {
+    /// Entries that did not exist on the remote host.
+    pub created: Vec<PathBuf>,
+
+    /// Entries that existed on the remote host, but differed.
+    pub updated: Vec<PathBuf>,
+
+    /// Remote entries that were deleted.
+    pub deleted: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Kind {
+    File { size: u64, mtime: u64 },
+    Dir { mtime: u64 },
+    Symlink(Vec<u8>),
+    Other,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Node {
+    kind: Kind,
+    mode: u32,
+}
+
+type Tree = BTreeMap<PathBuf, Node>;
+
+/// Match `text` against the glob `pattern`.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => {
+            // Try every possible length of the sequence matched by `*`.
+            let max = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=max).any(|i| glob(rest, &text[i..]))
+        }
+        Some((&c, rest)) => match text.split_first() {
+            Some((&t, text)) if t == c || (c == b'?' && t != b'/') => glob(rest, text),
+            _ => false,
+        },
+    }
+}
+
+fn is_excluded(exclude: &[String], path: &Path) -> bool {
+    let mut prefix = PathBuf::new();
+    for component in path {
+        prefix.push(component);
+        let excluded = exclude.iter().any(|pattern| {
+            let text = if pattern.contains('/') {
+                prefix.as_os_str()
+            } else {
+                component
+            };
+            glob(pattern.as_bytes(), text.as_bytes())
+        });
+        if excluded {
+            return true;
+        }
+    }
+    false
+}
+
+async fn walk_local(root: &Path, exclude: &[String]) -> Result<Tree, Error> {
+    let mut tree = Tree::new();
+    let mut dirs = vec![PathBuf::new()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = fs::read_dir(root.join(&dir))
+            .await
+            .map_err(Error::LocalIo)?;
+
+        while let Some(entry) = read_dir.next_entry().await.map_err(Error::LocalIo)? {
+            let path = dir.join(entry.file_name());
+            if is_excluded(exclude, &path) {
+                continue;
+            }
+
+            let metadata = fs::symlink_metadata(entry.path())
+                .await
+                .map_err(Error::LocalIo)?;
+            let file_type = metadata.file_type();
+
+            let kind = if file_type.is_dir() {
+                dirs.push(path.clone());
+                Kind::Dir {
+                    mtime: metadata.mtime().max(0) as u64,
+                }
+            } else if file_type.is_file() {
+                Kind::File {
+                    size: metadata.len(),
+                    mtime: metadata.mtime().max(0) as u64,
+                }
+            } else if file_type.is_symlink() {
+                let target = fs::read_link(entry.path()).await.map_err(Error::LocalIo)?;
+                Kind::Symlink(target.into_os_string().into_vec())
+            } else {
+                // Sockets, fifos and devices are not transferred.
+                continue;
+            };
+
+            let mode = metadata.mode() & 0o7777;
+            tree.insert(path, Node { kind, mode });
+        }
+    }
+
+    Ok(tree)
+}
+
+/// Script creating `remote` if necessary and listing its content.
+fn list_script(remote: &Path) -> Vec<u8> {
+    let mut script = b"d=".to_vec();
+    script.extend_from_slice(escape(remote.as_os_str()).as_bytes());
+    script.extend_from_slice(
+        b"\nmkdir -p -- \"$d\" && cd -- \"$d\" && \
+          exec find . -mindepth 1 -printf '%y\\0%m\\0%s\\0%T@\\0%l\\0%P\\0'\n",
+    );
+    script
+}
+
+fn parse_listing(listing: &[u8], exclude: &[String]) -> Option<Tree> {
+    let mut fields = listing.split(|&c| c == 0);
+    let mut tree = Tree::new();
+
+    loop {
+        let ty = match fields.next() {
+            Some(ty) if !ty.is_empty() => ty,
+            // The listing is terminated by a nul byte.
+            _ => break,
+        };
+        let mode = std::str::from_utf8(fields.next()?).ok()?;
+        let size = std::str::from_utf8(fields.next()?).ok()?;
+        let mtime = std::str::from_utf8(fields.next()?).ok()?;
+        let target = fields.next()?;
+        let path = PathBuf::from(OsStr::from_bytes(fields.next()?));
+
+        // find prints the modification time with a fractional part.
+        let mtime = mtime.split('.').next()?.parse().unwrap_or(0);
+        let kind = match ty {
+            b"f" => Kind::File {
+                size: size.parse().ok()?,
+                mtime,
+            },
+            b"d" => Kind::Dir { mtime },
+            b"l" => Kind::Symlink(target.to_vec()),
+            _ => Kind::Other,
+        };
+        let mode = u32::from_str_radix(mode, 8).ok()?;
+
+        if !is_excluded(exclude, &path) {
+            tree.insert(path, Node { kind, mode });
+        }
+    }
+
+    Some(tree)
+}
+
+/// Regular files whose content needs to be compared by checksum.
+fn checksum_candidates(local: &Tree, remote: &Tree) -> Vec<PathBuf> {
+    local
+        .iter()
+        .filter_map(|(path, node)| match (&node.kind, &remote.get(path)?.kind) {
+            (Kind::File { size, .. }, Kind::File { size: remote, .. }) if size == remote => {
+                Some(path.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct Plan {
+    report: SyncReport,
+    /// Remote entries to be removed before the transfer.
+    remove: Vec<PathBuf>,
+    /// Local entries to be transferred.
+    transfer: Vec<PathBuf>,
+}
+
+/// Compare the trees, where `same_content` is the set of files known to have
+/// the same content if checksums are used.
+fn plan(
+    local: &Tree,
+    remote: &Tree,
+    delete: bool,
+    same_content: Option<&BTreeSet<PathBuf>>,
+) -> Plan {
+    let mut plan = Plan::default();
+
+    for (path, node) in local {
+        let remote_node = match remote.get(path) {
+            Some(remote_node) => remote_node,
+            None => {
+                plan.report.created.push(path.clone());
+                plan.transfer.push(path.clone());
+                continue;
+            }
+        };
+
+        let changed = match (&node.kind, &remote_node.kind) {
+            (
+                Kind::File { size, mtime },
+                Kind::File {
+                    size: remote_size,
+                    mtime: remote_mtime,
+                },
+            ) => {
+                node.mode != remote_node.mode
+                    || size != remote_size
+                    || match same_content {
+                        Some(same_content) => !same_content.contains(path),
+                        None => mtime != remote_mtime,
+                    }
+            }
+            (Kind::Dir { .. }, Kind::Dir { .. }) => node.mode != remote_node.mode,
+            (Kind::Symlink(target), Kind::Symlink(remote_target)) => target != remote_target,
+            _ => {
+                // The remote entry has to be removed for tar to replace it.
+                plan.remove.push(path.clone());
+                true
+            }
+        };
+
+        if changed {
+            plan.report.updated.push(path.clone());
+            plan.transfer.push(path.clone());
+        }
+    }
+
+    // Entries inside of removed remote directories disappear as well.
+    let mut removed = plan.remove.clone();
+    for path in remote.keys() {
+        if local.contains_key(path) && !removed.contains(path) {
+            continue;
+        }
+
+        if let Some(ancestor) = removed.iter().find(|removed| path.starts_with(removed)) {
+            if ancestor != path {
+                plan.report.deleted.push(path.clone());
+            }
+        } else if delete {
+            plan.report.deleted.push(path.clone());
+            plan.remove.push(path.clone());
+            removed.push(path.clone());
+        }
+    }
+
+    plan
+}
+
+/// Run `script` on the remote host, returning its stdout.
+async fn run(session: &Session, script: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let output = sh(session, script).stdin(Stdio::null()).output().await?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(remote_failure(&output))
+    }
+}
+
+/// Script running `command` in `remote` with `paths` as arguments.
+fn paths_script(remote: &Path, prelude: &[u8], command: &[u8], paths: &[PathBuf]) -> Vec<u8> {
+    let mut script = prelude.to_vec();
+    script.extend_from_slice(b"cd -- ");
+    script.extend_from_slice(escape(remote.as_os_str()).as_bytes());
+    script.extend_from_slice(b" || exit 1\n");
+    script.extend_from_slice(command);
+    for path in paths {
+        script.push(b' ');
+        script.extend_from_slice(escape(path.as_os_str()).as_bytes());
+    }
+    script
+}
+
+async fn local_sha256(path: &Path) -> Result<String, Error> {
+    let mut file = fs::File::open(path).await.map_err(Error::LocalIo)?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut hasher = Sha256::new();
+    loop {
+        let n = file.read(&mut buffer).await.map_err(Error::LocalIo)?;
+        if n == 0 {
+            break Ok(format!("{:x}", hasher.finalize()));
+        }
+        hasher.update(&buffer[..n]);
+    }
+}
+
+async fn same_content(
+    session: &Session,
+    local_root: &Path,
+    remote_root: &Path,
+    candidates: &[PathBuf],
+) -> Result<BTreeSet<PathBuf>, Error> {
+    let mut same = BTreeSet::new();
+
+    for paths in candidates.chunks(PATHS_PER_COMMAND) {
+        let mut script = paths_script(remote_root, SHA256, b"for f in", paths);
+        script.extend_from_slice(b"; do echo \"$(sha256 < \"$f\" | cut -d ' ' -f 1)\"; done\n");
+
+        let stdout = run(session, script).await?;
+        let hashes = stdout.split(|&c| c == b'\n');
+
+        for (path, remote_hash) in paths.iter().zip(hashes) {
+            // Files are considered different if the remote checksum is unknown.
+            if !remote_hash.is_empty()
+                && remote_hash == local_sha256(&local_root.join(path)).await?.as_bytes()
+            {
+                same.insert(path.clone());
+            }
+        }
+    }
+
+    Ok(same)
+}
+
+/// Write `paths` of the local tree at `root` to `writer` as a tar archive.
+///
+/// Files are archived with the size they had when the tree was walked,
+/// padded with zeros if they shrunk in the meantime.
+fn write_archive<W: Write>(
+    writer: W,
+    root: &Path,
+    entries: &[(PathBuf, Node)],
+) -> Result<(), Error> {
+    let mut builder = tar::Builder::new(writer);
+
+    for (path, node) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(node.mode);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_size(0);
+
+        let appended = match &node.kind {
+            Kind::File { size, mtime } => {
+                let file = std::fs::File::open(root.join(path)).map_err(Error::LocalIo)?;
+                let content = file.take(*size).chain(io::repeat(0)).take(*size);
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(*size);
+                header.set_mtime(*mtime);
+                builder.append_data(&mut header, path, content)
+            }
+            Kind::Dir { mtime } => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mtime(*mtime);
+                builder.append_data(&mut header, path, io::empty())
+            }
+            Kind::Symlink(target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mtime(0);
+                builder.append_link(&mut header, path, OsStr::from_bytes(target))
+            }
+            Kind::Other => continue,
+        };
+        appended.map_err(Error::ChildIo)?;
+    }
+
+    builder.finish().map_err(Error::ChildIo)
+}
+
+pub(crate) async fn sync_dir(
+    session: &Session,
+    local: &Path,
+    remote: &Path,
+    options: &SyncOptions,
+) -> Result<SyncReport, Error> {
+    let local_tree = walk_local(local, &options.exclude).await?;
+
+    let listing = run(session, list_script(remote)).await?;
+    let remote_tree = parse_listing(&listing, &options.exclude).ok_or_else(|| {
+        Error::Remote(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "unexpected output of find",
+        ))
+    })?;
+
+    let same = if options.checksum {
+        let candidates = checksum_candidates(&local_tree, &remote_tree);
+        Some(same_content(session, local, remote, &candidates).await?)
+    } else {
+        None
+    };
+
+    let mut plan = plan(&local_tree, &remote_tree, options.delete, same.as_ref());
+
+    for paths in plan.remove.chunks(PATHS_PER_COMMAND) {
+        run(session, paths_script(remote, b"", b"rm -rf --", paths)).await?;
+    }
+
+    if !plan.transfer.is_empty() {
+        let mut child = sh(session, paths_script(remote, b"", b"exec tar -xpf -", &[]))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .await?;
+
+        // `tar::Builder` writes synchronously, so the archive is written to
+        // the blocking stdin on a blocking thread. stdin is moved into the
+        // closure, so that it gets closed even on error.
+        let stdin = child.stdin().take().expect("stdin is piped");
+        let stdin = std::fs::File::from(stdin.into_owned_fd().map_err(Error::ChildIo)?);
+        let root = local.to_owned();
+        let entries: Vec<_> = plan
+            .transfer
+            .iter()
+            .map(|path| (path.clone(), local_tree[path].clone()))
+            .collect();
+        let writing = task::spawn_blocking(move || write_archive(stdin, &root, &entries));
+
+        // stderr is read while the archive is written, since tar would stop
+        // extracting once the pipe is full of warnings.
+        let output = child.wait_with_output().await;
+        let written = writing.await.expect("writing the archive panicked");
+        let output = output?;
+        if !output.status.success() {
+            return Err(remote_failure(&output));
+        }
+        written?;
+    }
+
+    let report = &mut plan.report;
+    report.created.sort();
+    report.updated.sort();
+    report.deleted.sort();
+
+    Ok(plan.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::Command;
+
+    fn file(size: u64, mtime: u64) -> Node {
+        Node {
+            kind: Kind::File { size, mtime },
+            mode: 0o644,
+        }
+    }
+
+    fn dir() -> Node {
+        Node {
+            kind: Kind::Dir { mtime: 1 },
+            mode: 0o755,
+        }
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn globbing() {
+        assert!(glob(b"*.o", b"main.o"));
+        assert!(!glob(b"*.o", b"main.c"));
+        assert!(glob(b"a?c", b"abc"));
+        assert!(!glob(b"a*c", b"a/c"));
+        assert!(glob(b"*", b""));
+
+        let exclude = ["target".to_owned(), "src/*.bak".to_owned()];
+        assert!(is_excluded(&exclude, Path::new("target")));
+        assert!(is_excluded(&exclude, Path::new("sub/target/debug")));
+        assert!(is_excluded(&exclude, Path::new("src/lib.rs.bak")));
+        assert!(!is_excluded(&exclude, Path::new("sub/src/lib.rs.bak")));
+        assert!(!is_excluded(&exclude, Path::new("src/lib.rs")));
+    }
+
+    #[test]
+    fn listing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root dir");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/file"), b"content").unwrap();
+        std::os::unix::fs::symlink("sub/file", root.join("link")).unwrap();
+        std::fs::write(root.join("excluded"), b"").unwrap();
+
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(OsStr::from_bytes(&list_script(&root)))
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+
+        let tree = parse_listing(&output.stdout, &["excluded".to_owned()]).unwrap();
+        let local = block_on(walk_local(&root, &["excluded".to_owned()])).unwrap();
+        assert_eq!(tree, local);
+        assert_eq!(
+            tree.keys().cloned().collect::<Vec<_>>(),
+            paths(&["link", "sub", "sub/file"])
+        );
+        assert_eq!(
+            tree[Path::new("link")].kind,
+            Kind::Symlink(b"sub/file".to_vec())
+        );
+
+        // The remote directory is created if it does not exist.
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(OsStr::from_bytes(&list_script(&tmp.path().join("new"))))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        assert!(tmp.path().join("new").is_dir());
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn planning() {
+        let local: Tree = [
+            ("new", file(1, 1)),
+            ("same", file(1, 1)),
+            ("touched", file(1, 2)),
+            ("became_file", file(1, 1)),
+            ("dir", dir()),
+            ("dir/new", file(1, 1)),
+        ]
+        .into_iter()
+        .map(|(path, node)| (PathBuf::from(path), node))
+        .collect();
+        let remote: Tree = [
+            ("same", file(1, 1)),
+            ("touched", file(1, 1)),
+            ("became_file", dir()),
+            ("became_file/old", file(1, 1)),
+            ("dir", dir()),
+            ("gone", dir()),
+            ("gone/file", file(1, 1)),
+        ]
+        .into_iter()
+        .map(|(path, node)| (PathBuf::from(path), node))
+        .collect();
+
+        let result = plan(&local, &remote, false, None);
+        assert_eq!(result.report.created, paths(&["dir/new", "new"]));
+        assert_eq!(result.report.updated, paths(&["became_file", "touched"]));
+        assert_eq!(result.report.deleted, paths(&["became_file/old"]));
+        assert_eq!(result.remove, paths(&["became_file"]));
+        assert_eq!(
+            result.transfer,
+            paths(&["became_file", "dir/new", "new", "touched"])
+        );
+
+        let result = plan(&local, &remote, true, None);
+        assert_eq!(
+            result.report.deleted,
+            paths(&["became_file/old", "gone", "gone/file"])
+        );
+        assert_eq!(result.remove, paths(&["became_file", "gone"]));
+
+        // With checksums, the modification time is ignored.
+        let same = BTreeSet::from([PathBuf::from("same"), PathBuf::from("touched")]);
+        assert_eq!(
+            checksum_candidates(&local, &remote),
+            paths(&["same", "touched"])
+        );
+        let result = plan(&local, &remote, false, Some(&same));
+        assert_eq!(result.report.updated, paths(&["became_file"]));
+    }
+
+    #[test]
+    fn archive() {
+        let long_dir = "d".repeat(120);
+        let long_file = format!("{long_dir}/{}", "f".repeat(120));
+        let content = b"hello tar\n";
+
+        let local = tempfile::tempdir().unwrap();
+        std::fs::create_dir(local.path().join(&long_dir)).unwrap();
+        std::fs::write(local.path().join(&long_file), content).unwrap();
+        std::fs::write(local.path().join("shrunk"), b"").unwrap();
+
+        let entries = [
+            (long_dir.as_str(), dir()),
+            (
+                long_file.as_str(),
+                file(content.len() as u64, 1_000_000_000),
+            ),
+            ("shrunk", file(3, 1)),
+            (
+                "link",
+                Node {
+                    kind: Kind::Symlink(long_file.clone().into_bytes()),
+                    mode: 0o777,
+                },
+            ),
+        ]
+        .map(|(path, node)| (PathBuf::from(path), node));
+
+        let mut archive = Vec::new();
+        write_archive(&mut archive, local.path(), &entries).unwrap();
+
+        let remote = tempfile::tempdir().unwrap();
+        let mut child = Command::new("tar")
+            .arg("-xpf")
+            .arg("-")
+            .current_dir(remote.path())
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(&archive).unwrap();
+        assert!(child.wait().unwrap().success());
+
+        let file = remote.path().join(&long_file);
+        assert_eq!(std::fs::read(&file).unwrap(), content);
+        assert_eq!(std::fs::read(remote.path().join("link")).unwrap(), content);
+        assert_eq!(std::fs::read(remote.path().join("shrunk")).unwrap(), [0; 3]);
+
+        let metadata = std::fs::metadata(&file).unwrap();
+        assert_eq!(metadata.mtime(), 1_000_000_000);
+        assert_eq!(metadata.mode() & 0o7777, 0o644);
+    }
+}
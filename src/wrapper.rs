@@ -0,0 +1,452 @@
+//! Shell code wrapped around remote commands to emulate features that
+//! neither ssh nor its multiplex protocol provide.
+//!
+//! The wrapper is only ever added when one of these features is requested,
+//! since it requires a POSIX-compatible login shell on the remote host.
+
+use super::escape::escape;
+use super::{Error, Signal};
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A file on the remote host that the wrapper writes the pid of the remote
+/// shell into.
+///
+/// The remote shell is started by sshd in a new session, so its pid is also
+/// the id of the process group that contains every process spawned by the
+/// remote command.
+#[derive(Debug, Clone)]
+pub(crate) struct PidFile(Box<str>);
+
+impl PidFile {
+    pub(crate) fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or_default();
+
+        // The name only consists of alphanumeric characters, so it does
+        // not need to be escaped.
+        Self(
+            format!(
+                "\"${{TMPDIR:-/tmp}}\"/openssh-rs-{:x}-{:x}-{:x}.pid",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed),
+                nanos,
+            )
+            .into_boxed_str(),
+        )
+    }
+
+    /// Shell script that delivers `signal` to the process group of the
+    /// remote command.
+    pub(crate) fn signal_script(&self, signal: Signal) -> Vec<u8> {
+        let path = &self.0;
+        let name = signal.name();
+
+        let mut script = format!(
+            "pid=$(cat {path} 2>/dev/null) || {{ echo 'remote process not found' >&2; exit 1; }}; \
+             kill -s {name} -- \"-$pid\""
+        );
+        if signal == Signal::Kill {
+            // The wrapper cannot clean up after itself on SIGKILL.
+            script.push_str(&format!(" && rm -f {path}"));
+        }
+
+        script.into_bytes()
+    }
+}
+
+/// Environment of a remote command, mirroring the semantics of
+/// [`std::process::Command::env`] and friends.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Env {
+    /// `None` means that the variable is removed.
+    vars: BTreeMap<OsString, Option<OsString>>,
+    clear: bool,
+    fallback: bool,
+}
+
+impl Env {
+    pub(crate) fn set(&mut self, key: &OsStr, val: &OsStr) {
+        self.vars.insert(key.to_owned(), Some(val.to_owned()));
+    }
+
+    pub(crate) fn remove(&mut self, key: &OsStr) {
+        if self.clear {
+            self.vars.remove(key);
+        } else {
+            self.vars.insert(key.to_owned(), None);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.clear = true;
+        self.vars.clear();
+    }
+
+    pub(crate) fn set_fallback(&mut self, fallback: bool) {
+        self.fallback = fallback;
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        !self.clear && self.vars.is_empty()
+    }
+
+    /// Return the variables to be set if the environment can be transmitted
+    /// by ssh, i.e. if it only sets variables and the fallback is disabled.
+    #[cfg_attr(not(feature = "process-mux"), allow(dead_code))]
+    pub(crate) fn to_send(&self) -> Option<impl Iterator<Item = (&OsStr, &OsStr)>> {
+        // ssh options cannot span multiple lines.
+        let sendable = |val: &Option<OsString>| {
+            val.as_ref()
+                .map_or(false, |val| !val.as_bytes().contains(&b'\n'))
+        };
+        if self.clear || self.fallback || !self.vars.values().all(sendable) {
+            return None;
+        }
+
+        Some(
+            self.vars
+                .iter()
+                .filter_map(|(key, val)| Some((key.as_os_str(), val.as_deref()?))),
+        )
+    }
+
+    fn set_vars(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.vars.iter().filter_map(|(key, val)| {
+            let mut assignment = key.clone();
+            assignment.push("=");
+            assignment.push(val.as_deref()?);
+            Some(escape(&assignment).as_bytes().to_vec())
+        })
+    }
+
+    /// Shell code that applies the environment when executed at the start of
+    /// the remote shell script.
+    fn exports(&self) -> Vec<u8> {
+        let mut exports = Vec::new();
+
+        for (key, val) in &self.vars {
+            if val.is_none() {
+                exports.extend_from_slice(b"unset ");
+                exports.extend_from_slice(escape(key).as_bytes());
+                exports.extend_from_slice(b"; ");
+            }
+        }
+        for assignment in self.set_vars() {
+            exports.extend_from_slice(b"export ");
+            exports.extend_from_slice(&assignment);
+            exports.extend_from_slice(b"; ");
+        }
+
+        exports
+    }
+}
+
+/// Exit status of the wrapper if the working directory cannot be entered.
+pub(crate) const CWD_NOT_FOUND: i32 = 253;
+
+/// Exit status of the wrapper if the pid file cannot be written.
+pub(crate) const PID_FILE_NOT_WRITTEN: i32 = 252;
+
+/// Wrapper around a single invocation of a remote command.
+#[derive(Debug, Default)]
+pub(crate) struct Wrapper<'a> {
+    pub(crate) pid_file: Option<&'a PidFile>,
+    /// Environment to be applied by the wrapper, rather than by ssh.
+    pub(crate) env: Option<&'a Env>,
+    pub(crate) cwd: Option<&'a Path>,
+}
+
+impl Wrapper<'_> {
+    fn is_empty(&self) -> bool {
+        self.pid_file.is_none() && self.env.map_or(true, Env::is_empty) && self.cwd.is_none()
+    }
+
+    /// Wrap `cmd`, which is the command line to be passed to the remote shell.
+    pub(crate) fn wrap<'c>(&self, cmd: &'c [u8], subsystem: bool) -> Result<Cow<'c, [u8]>, Error> {
+        if self.is_empty() {
+            return Ok(Cow::Borrowed(cmd));
+        }
+
+        if subsystem {
+            return Err(Error::Remote(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "subsystems cannot be wrapped in a shell script",
+            )));
+        }
+
+        let mut wrapped = Vec::new();
+
+        let env = self.env.filter(|env| !env.is_empty());
+        if let Some(env) = env.filter(|env| !env.clear) {
+            wrapped.extend_from_slice(&env.exports());
+        }
+
+        if let Some(cwd) = self.cwd {
+            // Unset `CDPATH`, so that `cd` neither searches other directories
+            // nor prints the new directory to stdout.
+            wrapped.extend_from_slice(b"CDPATH= cd -- ");
+            wrapped.extend_from_slice(escape(cwd.as_os_str()).as_bytes());
+            wrapped.extend_from_slice(format!(" || exit {CWD_NOT_FOUND}; ").as_bytes());
+        }
+
+        if let Some(PidFile(path)) = self.pid_file {
+            // noclobber makes sure that an existing file is never overwritten.
+            // Without the pid file the process could not be signaled, so the
+            // command is not run at all.
+            //
+            // The traps remove the pid file once the shell exits, including
+            // when the whole process group gets signaled.
+            wrapped.extend_from_slice(
+                format!(
+                    "{{ set -C; printf '%s\\n' \"$$\" > {path}; }} 2>/dev/null \
+                     || exit {PID_FILE_NOT_WRITTEN}; set +C; \
+                     trap 'rm -f {path}' EXIT; \
+                     trap 'exit 129' HUP; trap 'exit 130' INT; trap 'exit 143' TERM; "
+                )
+                .as_bytes(),
+            );
+        }
+
+        // Run the command in a subshell so that it cannot interfere with the
+        // wrapper, the newline terminates any trailing comment.
+        wrapped.push(b'(');
+        wrapped.extend_from_slice(cmd);
+        wrapped.extend_from_slice(b"\n)");
+
+        if let Some(env) = env.filter(|env| env.clear) {
+            // The environment can only be cleared by starting a new shell, which
+            // replaces the login shell, so it still is the process group leader.
+            let mut outer = b"exec env -i ".to_vec();
+            for assignment in env.set_vars() {
+                outer.extend_from_slice(&assignment);
+                outer.push(b' ');
+            }
+            outer.extend_from_slice(b"sh -c ");
+            outer.extend_from_slice(escape(OsStr::from_bytes(&wrapped)).as_bytes());
+            wrapped = outer;
+        }
+
+        Ok(Cow::Owned(wrapped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::path::Path;
+    use std::process::{Command, ExitStatus};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    /// Run `script` like sshd does, in a new session.
+    fn sh(tmpdir: &Path, script: &[u8]) -> Command {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(OsStr::from_bytes(script))
+            .env("TMPDIR", tmpdir);
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+        command
+    }
+
+    fn pid_files(tmpdir: &Path) -> usize {
+        std::fs::read_dir(tmpdir).unwrap().count()
+    }
+
+    fn spawn_tracked(tmpdir: &Path, pid_file: &PidFile) -> std::process::Child {
+        let wrapper = Wrapper {
+            pid_file: Some(pid_file),
+            ..Default::default()
+        };
+        let wrapped = wrapper.wrap(b"sleep 30 | cat # comment", false).unwrap();
+        let child = sh(tmpdir, &wrapped).spawn().unwrap();
+
+        while std::fs::read_dir(tmpdir)
+            .unwrap()
+            .all(|entry| entry.unwrap().metadata().unwrap().len() == 0)
+        {
+            sleep(Duration::from_millis(10));
+        }
+        child
+    }
+
+    fn signal(tmpdir: &Path, pid_file: &PidFile, signal: Signal) -> ExitStatus {
+        sh(tmpdir, &pid_file.signal_script(signal))
+            .status()
+            .unwrap()
+    }
+
+    #[test]
+    fn unwrapped() {
+        let wrapper = Wrapper::default();
+        assert_eq!(wrapper.wrap(b"echo", true).unwrap(), &b"echo"[..]);
+
+        let env = Env::default();
+        let wrapper = Wrapper {
+            env: Some(&env),
+            ..Default::default()
+        };
+        assert_eq!(wrapper.wrap(b"echo", true).unwrap(), &b"echo"[..]);
+
+        let pid_file = PidFile::new();
+        let wrapper = Wrapper {
+            pid_file: Some(&pid_file),
+            ..Default::default()
+        };
+        assert!(wrapper.wrap(b"sftp", true).is_err());
+    }
+
+    fn run_with_env(env: &Env, cmd: &[u8]) -> String {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let wrapper = Wrapper {
+            env: Some(env),
+            ..Default::default()
+        };
+        let wrapped = wrapper.wrap(cmd, false).unwrap();
+
+        let output = sh(tmpdir.path(), &wrapped)
+            .env("INHERITED", "1")
+            .env("REMOVED", "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn env() {
+        let mut env = Env::default();
+        env.set(OsStr::new("A"), OsStr::new("it's a \"value\"; $HOME"));
+        env.set(OsStr::new("B"), OsStr::new("b"));
+        env.remove(OsStr::new("REMOVED"));
+        assert!(env.to_send().is_none());
+
+        let cmd = b"echo \"$A\" \"$B\" \"${INHERITED-unset}\" \"${REMOVED-unset}\"";
+        assert_eq!(
+            run_with_env(&env, cmd),
+            "it's a \"value\"; $HOME b 1 unset\n"
+        );
+
+        env.clear();
+        env.set(OsStr::new("A"), OsStr::new("a"));
+        assert_eq!(run_with_env(&env, cmd), "a  unset unset\n");
+
+        let mut env = Env::default();
+        env.set(OsStr::new("A"), OsStr::new("a"));
+        assert_eq!(
+            env.to_send().unwrap().collect::<Vec<_>>(),
+            [(OsStr::new("A"), OsStr::new("a"))]
+        );
+        env.set_fallback(true);
+        assert!(env.to_send().is_none());
+    }
+
+    #[test]
+    fn cwd() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let dir = tmpdir.path().join("it's a dir");
+        std::fs::create_dir(&dir).unwrap();
+
+        let run = |cwd: &Path| {
+            let wrapper = Wrapper {
+                cwd: Some(cwd),
+                ..Default::default()
+            };
+            let wrapped = wrapper.wrap(b"pwd", false).unwrap();
+            sh(tmpdir.path(), &wrapped)
+                .env("CDPATH", tmpdir.path())
+                .output()
+                .unwrap()
+        };
+
+        let output = run(&dir);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, [dir.as_os_str().as_bytes(), b"\n"].concat());
+
+        let output = run(&tmpdir.path().join("missing"));
+        assert_eq!(output.status.code(), Some(CWD_NOT_FOUND));
+        assert!(output.stdout.is_empty());
+    }
+
+    #[test]
+    fn exit_status() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let pid_file = PidFile::new();
+        let wrapper = Wrapper {
+            pid_file: Some(&pid_file),
+            ..Default::default()
+        };
+
+        let wrapped = wrapper.wrap(b"echo foo; exit 3", false).unwrap();
+        let output = sh(tmpdir.path(), &wrapped).output().unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"foo\n");
+        assert_eq!(pid_files(tmpdir.path()), 0);
+    }
+
+    #[test]
+    fn pid_file_exists() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let pid_file = PidFile::new();
+        let name = pid_file.0.rsplit('/').next().unwrap();
+        std::fs::write(tmpdir.path().join(name), "1\n").unwrap();
+
+        let wrapper = Wrapper {
+            pid_file: Some(&pid_file),
+            ..Default::default()
+        };
+        let wrapped = wrapper.wrap(b"echo foo", false).unwrap();
+        let output = sh(tmpdir.path(), &wrapped).output().unwrap();
+        assert_eq!(output.status.code(), Some(PID_FILE_NOT_WRITTEN));
+        assert!(output.stdout.is_empty());
+
+        // The file belongs to someone else, so it is left alone.
+        assert_eq!(pid_files(tmpdir.path()), 1);
+    }
+
+    #[test]
+    fn terminate() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let pid_file = PidFile::new();
+        let mut child = spawn_tracked(tmpdir.path(), &pid_file);
+
+        assert!(signal(tmpdir.path(), &pid_file, Signal::Terminate).success());
+        assert_eq!(child.wait().unwrap().code(), Some(143));
+        assert_eq!(pid_files(tmpdir.path()), 0);
+
+        // The process is gone.
+        assert!(!signal(tmpdir.path(), &pid_file, Signal::Terminate).success());
+    }
+
+    #[test]
+    fn kill() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let pid_file = PidFile::new();
+        let mut child = spawn_tracked(tmpdir.path(), &pid_file);
+
+        assert!(signal(tmpdir.path(), &pid_file, Signal::Kill).success());
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+        assert_eq!(pid_files(tmpdir.path()), 0);
+    }
+}
@@ -18,6 +18,8 @@ docker run \
     -d \
     -p 127.0.0.1:2222:2222 \
     -e 'USER_NAME=test-user' \
+    -e 'PASSWORD_ACCESS=true' \
+    -e 'USER_PASSWORD=test-password' \
     -e DOCKER_MODS \
     -e PUBLIC_KEY \
     linuxserver/openssh-server:amd64-latest
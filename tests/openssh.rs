@@ -4,15 +4,16 @@ use std::{
     env,
     io::{self, Write},
     net::IpAddr,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process,
+    sync::Arc,
     time::Duration,
 };
 use tempfile::tempdir;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{UnixListener, UnixStream},
-    time::sleep,
+    time::{sleep, timeout},
 };
 
 use openssh::*;
@@ -116,6 +117,50 @@ async fn it_connects() {
     }
 }
 
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept);
+
+    let mut pools = Vec::new();
+    #[cfg(feature = "process-mux")]
+    pools.push(SessionPool::new(builder.clone()));
+    #[cfg(feature = "native-mux")]
+    pools.push(SessionPool::new_mux(builder.clone()));
+
+    for mut pool in pools {
+        pool.idle_timeout(Duration::ZERO);
+
+        let session = pool.get(&addr()).await.unwrap();
+        let output = session
+            .clone()
+            .arc_command("echo")
+            .arg("pooled")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"pooled\n");
+
+        // Sessions are shared and not evicted while they are in use.
+        let same = pool.get(&addr()).await.unwrap();
+        assert!(Arc::ptr_eq(&session, &same));
+        drop(same);
+        assert_eq!(pool.evict_idle().await, 0);
+
+        drop(session);
+        assert_eq!(pool.evict_idle().await, 1);
+
+        let session = pool.get(&addr()).await.unwrap();
+        session.check().await.unwrap();
+        drop(session);
+
+        pool.close().await.unwrap();
+    }
+}
+
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
 async fn control_dir() {
@@ -250,6 +295,59 @@ async fn config_file() {
     std::fs::remove_dir_all(&dirname).unwrap();
 }
 
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn askpass_password_auth() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let dirname = tempdir().unwrap();
+    let config = dirname.path().join("config");
+
+    // Disable public key authentication, so that ssh has to ask for the password.
+    std::fs::write(
+        &config,
+        "PubkeyAuthentication no\nPreferredAuthentications password,keyboard-interactive\n",
+    )
+    .unwrap();
+
+    let prompts = Arc::new(AtomicUsize::new(0));
+
+    let mut builder = SessionBuilder::default();
+    builder.config_file(&config).askpass({
+        let prompts = prompts.clone();
+        move |prompt| {
+            prompts.fetch_add(1, Ordering::Relaxed);
+            async move {
+                assert_eq!(prompt.kind(), PromptKind::Secret);
+                assert!(prompt.message().contains("assword"), "{:?}", prompt);
+                Some(SecretString::from("test-password"))
+            }
+        }
+    });
+
+    for session in session_builder_connect(builder.clone(), &addr()).await {
+        session.check().await.unwrap();
+
+        let whoami = session.command("whoami").output().await.unwrap();
+        assert_eq!(whoami.stdout, b"test-user\n");
+
+        session.close().await.unwrap();
+    }
+    assert!(prompts.load(Ordering::Relaxed) > 0);
+
+    // A cancelled prompt must fail the authentication.
+    builder.askpass(|_prompt| async { None });
+    for err in session_builder_connects_err(&addr(), builder).await {
+        match err {
+            Error::ConnectFailed(ConnectError::AuthFailed { methods_offered }) => {
+                assert!(methods_offered.iter().any(|method| &**method == "password"));
+            }
+            e => unreachable!("{:?}", e),
+        }
+    }
+}
+
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
 async fn terminate_on_drop() {
@@ -292,6 +390,230 @@ async fn stdout() {
     }
 }
 
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn command_timeout() {
+    for session in connects().await {
+        let output = session
+            .command("echo")
+            .arg("foo")
+            .timeout(Duration::from_secs(10))
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"foo\n");
+
+        let err = session
+            .raw_command("echo foo; echo bar >&2; sleep 10")
+            .timeout(Duration::from_secs(1))
+            .output()
+            .await
+            .unwrap_err();
+        match err {
+            Error::Timeout { stdout, stderr } => {
+                assert_eq!(stdout, b"foo\n");
+                assert_eq!(stderr, b"bar\n");
+            }
+            e => unreachable!("{:?}", e),
+        }
+
+        let child = session.command("sleep").arg("10").spawn().await.unwrap();
+        match child.wait_timeout(Duration::from_secs(1)).await {
+            Err(Error::Timeout { .. }) => (),
+            res => unreachable!("{:?}", res),
+        }
+
+        // The session is still usable after a timeout.
+        let output = session.command("true").output().await.unwrap();
+        assert!(output.status.success());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn signal() {
+    for session in connects().await {
+        let child = session.command("sleep").arg("100").spawn().await.unwrap();
+        assert!(matches!(child.kill().await, Err(Error::SignalUnsupported)));
+        child.disconnect().await.unwrap();
+
+        let child = session
+            .command("sleep")
+            .arg("100")
+            .track_pid(true)
+            .spawn()
+            .await
+            .unwrap();
+        // Give the wrapper a chance to record the pid.
+        sleep(Duration::from_millis(500)).await;
+        child.signal(Signal::Terminate).await.unwrap();
+        assert_eq!(child.wait().await.unwrap().code(), Some(143));
+
+        let child = session
+            .command("sleep")
+            .arg("100")
+            .track_pid(true)
+            .spawn()
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(500)).await;
+        child.kill().await.unwrap();
+        assert!(matches!(
+            child.wait().await,
+            Err(Error::RemoteProcessTerminated)
+        ));
+
+        // Tracked processes are killed on timeout.
+        let output = session
+            .command("sleep")
+            .arg("100")
+            .track_pid(true)
+            .timeout(Duration::from_secs(1))
+            .output()
+            .await;
+        assert!(matches!(output, Err(Error::Timeout { .. })));
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn env() {
+    for session in connects().await {
+        // The test server does not accept any env vars, so use the fallback.
+        let output = session
+            .command("sh")
+            .arg("-c")
+            .arg(r#"echo "$A" "${HOME:+home}" "${B-unset}""#)
+            .env("A", "it's \"a\" $value")
+            .env("B", "b")
+            .env_remove("B")
+            .env_fallback(true)
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "it's \"a\" $value home unset\n"
+        );
+
+        let output = session
+            .command("/usr/bin/env")
+            .env_clear()
+            .env("A", "a")
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"A=a\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn current_dir() {
+    for session in connects().await {
+        let output = session
+            .command("pwd")
+            .current_dir("/tmp")
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"/tmp\n");
+
+        let err = session
+            .command("pwd")
+            .current_dir("/no such dir")
+            .status()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::CwdNotFound(dir) if dir == Path::new("/no such dir")),
+            "{:?}",
+            err
+        );
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn pty() {
+    for session in connects().await {
+        let config = PtyConfig {
+            cols: 120,
+            rows: 40,
+            ..Default::default()
+        };
+
+        let output = session
+            .raw_command("tty && stty size && echo $TERM")
+            .pty(config.clone())
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<_> = stdout.lines().collect();
+        assert!(lines[0].starts_with("/dev/"), "{:?}", lines);
+        assert_eq!(lines[1], "40 120");
+        assert_eq!(lines[2], "xterm-256color");
+
+        // Without a pty, resizing fails.
+        let child = session.command("true").spawn().await.unwrap();
+        assert!(matches!(child.resize(80, 24).await, Err(Error::NoPty)));
+        child.wait().await.unwrap();
+
+        let mut child = session
+            .raw_command("read line && stty size")
+            .pty(config.clone())
+            .spawn()
+            .await
+            .unwrap();
+        child.resize(100, 30).await.unwrap();
+        // Give ssh a chance to forward the new size.
+        sleep(Duration::from_millis(500)).await;
+        child
+            .stdin()
+            .as_mut()
+            .unwrap()
+            .write_all(b"\n")
+            .await
+            .unwrap();
+        let output = child.wait_with_output().await.unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.ends_with("30 100\r\n"), "{:?}", stdout);
+
+        // ^C interrupts the foreground process.
+        let child = session
+            .command("sleep")
+            .arg("100")
+            .pty(config)
+            .spawn()
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(500)).await;
+        child.signal(Signal::Interrupt).await.unwrap();
+        let status = child.wait().await;
+        assert!(
+            matches!(&status, Err(Error::RemoteProcessTerminated))
+                || matches!(&status, Ok(status) if status.code() == Some(130)),
+            "{:?}",
+            status
+        );
+
+        session.close().await.unwrap();
+    }
+}
+
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
 async fn over_session_ok() {
@@ -344,35 +666,38 @@ async fn over_session_ok_require_escaping_arguments() {
     }
 }
 
-/// Test that `over_ssh` errors if the source command has env vars specified.
+/// Test that `over_ssh` carries env vars of the source command over.
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
-async fn over_session_err_because_env_var() {
+async fn over_session_env_var() {
     for session in connects().await {
-        let command_with_env = std::process::Command::new("printenv")
+        let output = std::process::Command::new("printenv")
             .arg("MY_ENV_VAR")
             .env("MY_ENV_VAR", "foo")
-            .over_ssh(&session);
-        assert!(matches!(
-            command_with_env,
-            Err(openssh::Error::CommandHasEnv)
-        ));
+            .over_ssh(&session)
+            .expect("over_ssh to work")
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"foo\n");
     }
 }
 
-/// Test that `over_ssh` errors if the source command has a `current_dir` specified.
+/// Test that `over_ssh` carries the `current_dir` of the source command over.
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
-async fn over_session_err_because_cwd() {
+async fn over_session_cwd() {
     for session in connects().await {
-        let command_with_current_dir = std::process::Command::new("echo")
-            .arg("foo")
+        let output = std::process::Command::new("pwd")
             .current_dir("/tmp")
-            .over_ssh(&session);
-        assert!(matches!(
-            command_with_current_dir,
-            Err(openssh::Error::CommandHasCwd)
-        ));
+            .over_ssh(&session)
+            .expect("over_ssh to work")
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"/tmp\n");
     }
 }
 
@@ -540,7 +865,10 @@ async fn connect_timeout() {
         assert!(duration > Duration::from_secs(1));
         assert!(duration < Duration::from_secs(2));
         eprintln!("{:?}", failed);
-        assert!(matches!(failed, Error::Connect(ref e) if e.kind() == io::ErrorKind::TimedOut));
+        assert!(matches!(
+            failed,
+            Error::ConnectFailed(ConnectError::Timeout)
+        ));
     }
 
     // Test native-mux_impl
@@ -555,7 +883,10 @@ async fn connect_timeout() {
         assert!(duration > Duration::from_secs(1));
         assert!(duration < Duration::from_secs(2));
         eprintln!("{:?}", failed);
-        assert!(matches!(failed, Error::Connect(ref e) if e.kind() == io::ErrorKind::TimedOut));
+        assert!(matches!(
+            failed,
+            Error::ConnectFailed(ConnectError::Timeout)
+        ));
     }
 }
 
@@ -678,51 +1009,258 @@ async fn process_exit_on_signal() {
 
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
-async fn broken_connection() {
-    for session in connects().await {
-        let sleeping = session.command("sleep").arg("1000").spawn().await.unwrap();
+async fn fleet() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept);
 
-        // get ID of remote ssh process
-        let ppid = session
-            .command("echo")
-            .raw_arg("$PPID")
-            .output()
+    let bad = addr().replace("test-user", "bad-user");
+    let destinations = [addr(), addr(), bad.clone()];
+
+    let mut fleets = Vec::new();
+    #[cfg(feature = "process-mux")]
+    fleets.push(Fleet::new(builder.clone(), destinations.clone()));
+    #[cfg(feature = "native-mux")]
+    fleets.push(Fleet::new_mux(builder.clone(), destinations.clone()));
+
+    for mut fleet in fleets {
+        fleet.max_concurrency(2).timeout(Duration::from_secs(30));
+
+        let report = fleet
+            .run(|session| {
+                let mut command = session.arc_command("echo");
+                command.arg("hello");
+                command
+            })
+            .collect()
+            .await;
+
+        assert!(!report.is_success());
+        assert_eq!(report.outputs.len(), 2);
+        for (host, output) in &report.outputs {
+            assert_eq!(host, &addr());
+            assert_eq!(output.stdout, b"hello\n");
+        }
+
+        assert_eq!(report.failures.len(), 1);
+        let (host, err) = &report.failures[0];
+        assert_eq!(host, &bad);
+        assert!(matches!(err, Error::ConnectFailed(_)), "{:?}", err);
+
+        // A timeout is reported per host.
+        fleet.timeout(Duration::from_millis(1));
+        let mut run = fleet.run(|session| session.arc_command("true"));
+        let mut hosts = 0;
+        while let Some((_, result)) = run.next().await {
+            assert!(
+                matches!(result, Err(Error::ConnectFailed(ConnectError::Timeout))),
+                "{:?}",
+                result
+            );
+            hosts += 1;
+        }
+        assert_eq!(hosts, 3);
+
+        // The output of a command that timed out is kept.
+        fleet.timeout(Duration::from_secs(5));
+        let report = fleet
+            .run(|session| {
+                let mut command = session.arc_command("echo");
+                command.arg("started").raw_arg("; sleep 10");
+                command
+            })
+            .collect()
+            .await;
+        assert_eq!(report.failures.len(), 3);
+        for (host, err) in &report.failures {
+            if host != &bad {
+                assert!(
+                    matches!(err, Error::Timeout { stdout, .. } if stdout == b"started\n"),
+                    "{:?}",
+                    err
+                );
+            }
+        }
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn max_sessions() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .max_sessions(2)
+        .max_sessions_timeout(Duration::from_millis(500));
+
+    for session in session_builder_connect(builder, &addr()).await {
+        let first = session.command("sleep").arg("100").spawn().await.unwrap();
+        let _second = session.command("sleep").arg("100").spawn().await.unwrap();
+
+        let err = session.command("true").status().await.unwrap_err();
+        assert!(matches!(err, Error::TooManySessions), "{:?}", err);
+
+        // Disconnecting from a child frees its channel.
+        first.disconnect().await.unwrap();
+        session.command("true").status().await.unwrap();
+    }
+
+    // Signals are delivered even when the signalled process holds the only channel.
+    let mut builder = SessionBuilder::default();
+    builder.max_sessions(1);
+
+    for session in session_builder_connect(builder, &addr()).await {
+        let child = session
+            .command("sleep")
+            .arg("100")
+            .track_pid(true)
+            .spawn()
             .await
             .unwrap();
-        eprintln!("ppid: {:?}", ppid);
-        let ppid: u32 = String::from_utf8(ppid.stdout)
+        // Give the wrapper a chance to record the pid.
+        sleep(Duration::from_millis(500)).await;
+        timeout(Duration::from_secs(10), child.kill())
+            .await
             .unwrap()
-            .trim()
-            .parse()
             .unwrap();
-
-        // and kill it -- this kills the master connection
-        let killed = session
-            .command("kill")
-            .arg("-9")
-            .arg(&format!("{}", ppid))
-            .output()
-            .await
-            .unwrap_err();
-        eprintln!("{:?}", killed);
-        assert!(matches!(killed, Error::RemoteProcessTerminated));
-
-        // this fails because the master connection is gone
-        let failed = session
-            .command("echo")
-            .arg("foo")
-            .output()
-            .await
-            .unwrap_err();
-        eprintln!("{:?}", failed);
         assert!(matches!(
-            failed,
-            Error::RemoteProcessTerminated | Error::Disconnected
+            child.wait().await,
+            Err(Error::RemoteProcessTerminated)
         ));
 
-        // so does this
-        let failed = session
-            .command("echo")
+        let mut command = session.command("sleep");
+        command
+            .arg("100")
+            .track_pid(true)
+            .timeout(Duration::from_secs(1));
+        let output = timeout(Duration::from_secs(10), command.output())
+            .await
+            .unwrap();
+        assert!(matches!(output, Err(Error::Timeout { .. })));
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn reconnecting_session() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept);
+
+    let mut policy = ReconnectPolicy::default();
+    policy.initial_backoff(Duration::from_millis(100));
+
+    let mut sessions = Vec::new();
+    #[cfg(feature = "process-mux")]
+    sessions.push(
+        ReconnectingSession::connect(builder.clone(), addr(), policy.clone())
+            .await
+            .unwrap(),
+    );
+    #[cfg(feature = "native-mux")]
+    sessions.push(
+        ReconnectingSession::connect_mux(builder.clone(), addr(), policy.clone())
+            .await
+            .unwrap(),
+    );
+
+    for mut session in sessions {
+        let reconnects = Arc::new(AtomicU32::new(0));
+        let counter = reconnects.clone();
+        session.on_event(move |event| {
+            if let ReconnectEvent::Reconnected { .. } = event {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+
+        let ppid = session
+            .command("echo")
+            .await
+            .unwrap()
+            .raw_arg("$PPID")
+            .output()
+            .await
+            .unwrap();
+        let ppid = String::from_utf8(ppid.stdout).unwrap();
+
+        // kill the remote end of the master connection
+        session
+            .command("kill")
+            .await
+            .unwrap()
+            .arg("-9")
+            .arg(ppid.trim())
+            .output()
+            .await
+            .unwrap_err();
+        assert_eq!(reconnects.load(Ordering::SeqCst), 0);
+
+        // the next command transparently launches a new master
+        let output = session
+            .command("echo")
+            .await
+            .unwrap()
+            .arg("foo")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"foo\n");
+        assert_eq!(reconnects.load(Ordering::SeqCst), 1);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn broken_connection() {
+    for session in connects().await {
+        let sleeping = session.command("sleep").arg("1000").spawn().await.unwrap();
+
+        // get ID of remote ssh process
+        let ppid = session
+            .command("echo")
+            .raw_arg("$PPID")
+            .output()
+            .await
+            .unwrap();
+        eprintln!("ppid: {:?}", ppid);
+        let ppid: u32 = String::from_utf8(ppid.stdout)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+
+        // and kill it -- this kills the master connection
+        let killed = session
+            .command("kill")
+            .arg("-9")
+            .arg(&format!("{}", ppid))
+            .output()
+            .await
+            .unwrap_err();
+        eprintln!("{:?}", killed);
+        assert!(matches!(killed, Error::RemoteProcessTerminated));
+
+        // this fails because the master connection is gone
+        let failed = session
+            .command("echo")
+            .arg("foo")
+            .output()
+            .await
+            .unwrap_err();
+        eprintln!("{:?}", failed);
+        assert!(matches!(
+            failed,
+            Error::RemoteProcessTerminated | Error::Disconnected
+        ));
+
+        // so does this
+        let failed = session
+            .command("echo")
             .arg("foo")
             .status()
             .await
@@ -752,9 +1290,14 @@ async fn broken_connection() {
 async fn cannot_resolve() {
     for err in connects_err("bad-host").await {
         match err {
-            Error::Connect(e) => {
+            Error::ConnectFailed(e) => {
                 eprintln!("{:?}", e);
-                assert_eq!(e.kind(), io::ErrorKind::Other);
+                assert_eq!(
+                    e,
+                    ConnectError::DnsFailure {
+                        host: "bad-host".into()
+                    }
+                );
             }
             e => unreachable!("{:?}", e),
         }
@@ -769,9 +1312,9 @@ async fn no_route() {
 
     for err in session_builder_connects_err("192.0.2.1", builder).await {
         match err {
-            Error::Connect(e) => {
+            Error::ConnectFailed(e) => {
                 eprintln!("{:?}", e);
-                assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+                assert_eq!(e, ConnectError::Timeout);
             }
             e => unreachable!("{:?}", e),
         }
@@ -782,9 +1325,9 @@ async fn no_route() {
 async fn connection_refused() {
     for err in connects_err("ssh://127.0.0.1:9").await {
         match err {
-            Error::Connect(e) => {
+            Error::ConnectFailed(e) => {
                 eprintln!("{:?}", e);
-                assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+                assert_eq!(e, ConnectError::ConnectionRefused);
             }
             e => unreachable!("{:?}", e),
         }
@@ -802,15 +1345,95 @@ async fn auth_failed() {
 
     for err in connects_err(&addr).await {
         match err {
-            Error::Connect(e) => {
+            Error::ConnectFailed(e) => {
                 eprintln!("{:?}", e);
-                assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+                assert!(matches!(e, ConnectError::AuthFailed { .. }), "{:?}", e);
+            }
+            e => unreachable!("{:?}", e),
+        }
+    }
+}
+
+#[tokio::test]
+async fn unsupported_algorithm() {
+    let mut builder = SessionBuilder::default();
+    builder.ciphers(AlgorithmList::Set(vec![
+        "aes128-ctr".to_owned(),
+        "aes128-crt".to_owned(),
+    ]));
+
+    // The typo is caught before connecting.
+    for err in session_builder_connects_err("ssh://test-user@192.0.2.1", builder).await {
+        match err {
+            Error::UnsupportedAlgorithm { option, algorithm } => {
+                assert_eq!(option, "Ciphers");
+                assert_eq!(algorithm, "aes128-crt");
+            }
+            e => unreachable!("{:?}", e),
+        }
+    }
+}
+
+#[tokio::test]
+async fn invalid_option() {
+    let check = |keyword: &'static str, value: &'static str| async move {
+        let mut builder = SessionBuilder::default();
+        builder
+            .option("ServerAliveCountMax", "3")
+            .option(keyword, value);
+        session_builder_connects_err("ssh://test-user@192.0.2.1", builder).await
+    };
+
+    for err in check("controlpath", "/tmp/ctl").await {
+        match err {
+            Error::InvalidOption { keyword, .. } => assert_eq!(keyword, "controlpath"),
+            e => unreachable!("{:?}", e),
+        }
+    }
+
+    for err in check("NoSuchOption", "yes").await {
+        match err {
+            Error::InvalidOption { keyword, reason } => {
+                assert_eq!(keyword, "NoSuchOption");
+                assert!(reason.contains("Bad configuration option"), "{}", reason);
             }
             e => unreachable!("{:?}", e),
         }
     }
 }
 
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn options() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .options([("ServerAliveCountMax", "3"), ("TCPKeepAlive", "yes")])
+        .known_hosts_check(KnownHosts::Accept);
+
+    for session in session_builder_connect(builder, &addr()).await {
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn algorithms() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .ciphers(AlgorithmList::Prepend(vec!["aes256-ctr".to_owned()]))
+        .macs(AlgorithmList::Remove(vec!["*-sha1*".to_owned()]))
+        .kex_algorithms(AlgorithmList::Append(vec![
+            "diffie-hellman-group14-sha256".to_owned()
+        ]))
+        .known_hosts_check(KnownHosts::Accept);
+
+    for session in session_builder_connect(builder, &addr()).await {
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
 async fn remote_socket_forward() {
@@ -929,6 +1552,318 @@ async fn local_socket_forward() {
     }
 }
 
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn dynamic_forward() {
+    use tokio::net::TcpStream;
+
+    let sessions = connects().await;
+    for (session, (port, socks_port)) in sessions.iter().zip([(1435, 1085), (1434, 1084)]) {
+        eprintln!("Creating remote process");
+        let cmd = format!("echo -n hello | nc -l -p {} >/dev/stderr", port);
+        let child = session
+            .raw_command(cmd)
+            .stderr(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+
+        sleep(Duration::from_secs(1)).await;
+
+        eprintln!("Requesting dynamic forward");
+        // The connect socket is ignored for dynamic forwardings.
+        session
+            .request_port_forward(
+                ForwardType::Dynamic,
+                (loopback(), socks_port),
+                (loopback(), 0),
+            )
+            .await
+            .unwrap();
+
+        eprintln!("Connecting through the SOCKS5 proxy");
+        let mut stream = TcpStream::connect((loopback(), socks_port)).await.unwrap();
+
+        // No authentication.
+        stream.write_all(&[5, 1, 0]).await.unwrap();
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [5, 0]);
+
+        // CONNECT to 127.0.0.1:port on the remote host.
+        let mut request = vec![5, 1, 0, 1, 127, 0, 0, 1];
+        request.extend_from_slice(&u16::to_be_bytes(port));
+        stream.write_all(&request).await.unwrap();
+        let mut reply = [0; 10];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[..2], [5, 0]);
+
+        let mut buffer = Vec::new();
+        stream.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(buffer, b"hello");
+        drop(stream);
+
+        eprintln!("Closing dynamic forward");
+        session
+            .close_port_forward(
+                ForwardType::Dynamic,
+                (loopback(), socks_port),
+                (loopback(), 0),
+            )
+            .await
+            .unwrap();
+
+        let e = TcpStream::connect((loopback(), socks_port))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+
+        let output = child.wait_with_output().await.unwrap();
+        assert!(output.status.success());
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn ephemeral_port_forward() {
+    use tokio::net::TcpStream;
+
+    for session in connects().await {
+        let dir = tempdir().unwrap();
+        let unix_socket = dir.path().join("unix_socket_listener");
+        let listener = UnixListener::bind(&unix_socket).unwrap();
+
+        eprintln!("Requesting remote port forward on port 0");
+        let bound = session
+            .request_port_forward(ForwardType::Remote, (loopback(), 0), &*unix_socket)
+            .await
+            .unwrap();
+        let remote_port = bound.port().unwrap();
+        assert_ne!(remote_port, 0);
+
+        let cmd = format!("echo -n hello | nc localhost {} >/dev/stderr", remote_port);
+        let child = session
+            .raw_command(cmd)
+            .stderr(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+
+        let (mut output, _addr) = listener.accept().await.unwrap();
+        let mut buffer = [0_u8; 5];
+        output.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+        drop(output);
+        assert!(child.wait_with_output().await.unwrap().status.success());
+
+        // Remote forwardings on port 0 are closed as they were requested.
+        session
+            .close_port_forward(ForwardType::Remote, (loopback(), 0), &*unix_socket)
+            .await
+            .unwrap();
+
+        eprintln!("Requesting local port forward on port 0");
+        let cmd = "echo -n hello | nc -l -p 1438 >/dev/stderr";
+        let child = session
+            .raw_command(cmd)
+            .stderr(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+
+        sleep(Duration::from_secs(1)).await;
+
+        let bound = session
+            .request_port_forward(ForwardType::Local, (loopback(), 0), (loopback(), 1438))
+            .await
+            .unwrap();
+        let local_port = bound.port().unwrap();
+        assert_ne!(local_port, 0);
+
+        let mut stream = TcpStream::connect((loopback(), local_port)).await.unwrap();
+        let mut buffer = Vec::new();
+        stream.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(buffer, b"hello");
+        drop(stream);
+        assert!(child.wait_with_output().await.unwrap().status.success());
+
+        // Local forwardings on port 0 are closed with the port that was picked.
+        session
+            .close_port_forward(ForwardType::Local, bound, (loopback(), 1438))
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn list_forwards_and_channels() {
+    for session in connects().await {
+        assert!(session.list_forwards().is_empty());
+        assert!(session.list_channels().is_empty());
+
+        let dir = tempdir().unwrap();
+        let unix_socket = dir.path().join("unix_socket_forwarded");
+        let bound = session
+            .request_port_forward(ForwardType::Remote, (loopback(), 1441), &*unix_socket)
+            .await
+            .unwrap();
+
+        let forwards = session.list_forwards();
+        assert_eq!(forwards.len(), 1);
+        assert_eq!(forwards[0].forward_type, ForwardType::Remote);
+        assert_eq!(forwards[0].listen_socket, bound);
+        assert_eq!(
+            forwards[0].connect_socket,
+            Some(Socket::from(unix_socket.clone()))
+        );
+
+        let child = session.command("sleep").arg("1").spawn().await.unwrap();
+        let channels = session.list_channels();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].command, "sleep 1");
+        assert!(!channels[0].subsystem);
+
+        child.wait().await.unwrap();
+        assert!(session.list_channels().is_empty());
+
+        session
+            .close_port_forward(ForwardType::Remote, (loopback(), 1441), &*unix_socket)
+            .await
+            .unwrap();
+        assert!(session.list_forwards().is_empty());
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn direct_stream() {
+    for session in connects().await {
+        let child = session
+            .raw_command("nc -l -p 1439")
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+
+        sleep(Duration::from_secs(1)).await;
+
+        eprintln!("Connecting to remote port");
+        let mut stream = session.connect_tcp("localhost", 1439).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        stream.shutdown().await.unwrap();
+        drop(stream);
+
+        let output = child.wait_with_output().await.unwrap();
+        assert_eq!(output.stdout, b"hello");
+
+        eprintln!("Connecting to closed remote port");
+        let mut stream = session.connect_tcp("localhost", 1439).await.unwrap();
+        let e = stream.read(&mut [0; 8]).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+#[cfg(feature = "http")]
+async fn http_connector() {
+    use http_body_util::{BodyExt, Empty};
+    use hyper::body::Bytes;
+    use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+
+    for session in connects().await {
+        let session = Arc::new(session);
+
+        let response =
+            "HTTP/1.1 200 OK\\r\\nContent-Length: 5\\r\\nConnection: close\\r\\n\\r\\nhello";
+        let cmd = format!("printf '{}' | nc -l -p 1440 >/dev/null", response);
+        let child = session.raw_command(cmd).spawn().await.unwrap();
+
+        sleep(Duration::from_secs(1)).await;
+
+        let client = Client::builder(TokioExecutor::new())
+            .build::<_, Empty<Bytes>>(HttpConnector::new(session.clone()));
+        let response = client
+            .get("http://localhost:1440/".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"hello");
+
+        child.wait().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn port_forward_guard() {
+    let sessions = connects().await;
+    for (session, port) in sessions.into_iter().zip([1437, 1436]) {
+        let session = Arc::new(session);
+
+        eprintln!("Creating remote process");
+        let cmd = format!("echo -n hello | nc -l -p {} >/dev/stderr", port);
+        let child = session
+            .raw_command(cmd)
+            .stderr(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+
+        sleep(Duration::from_secs(1)).await;
+
+        eprintln!("Requesting port forward");
+        let dir = tempdir().unwrap();
+        let unix_socket = dir.path().join("unix_socket_forwarded");
+
+        let forward = session
+            .clone()
+            .arc_port_forward(ForwardType::Local, &*unix_socket, (loopback(), port))
+            .await
+            .unwrap();
+        assert_eq!(forward.forward_type(), ForwardType::Local);
+        assert_eq!(forward.listen_socket(), &Socket::from(&*unix_socket));
+
+        eprintln!("Reading from forwarded socket in a task owning the forward");
+        let socket = unix_socket.clone();
+        let buffer = tokio::spawn(async move {
+            let mut output = UnixStream::connect(&socket).await.unwrap();
+            let mut buffer = Vec::new();
+            output.read_to_end(&mut buffer).await.unwrap();
+
+            // Dropping the guard closes the forward.
+            drop(forward);
+            buffer
+        })
+        .await
+        .unwrap();
+        assert_eq!(buffer, b"hello");
+
+        // The guard closes the forward in the background.
+        sleep(Duration::from_secs(1)).await;
+
+        eprintln!("Trying to connect again");
+        let e = UnixStream::connect(&unix_socket).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+
+        let output = child.wait_with_output().await.unwrap();
+        assert!(output.status.success());
+
+        eprintln!("Closing the forward explicitly");
+        let forward = session
+            .port_forward(ForwardType::Local, &*unix_socket, (loopback(), port))
+            .await
+            .unwrap();
+        forward.close().await.unwrap();
+
+        let e = UnixStream::connect(&unix_socket).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
+
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
 #[cfg(feature = "process-mux")]
@@ -1056,6 +1991,187 @@ async fn test_sftp_subsystem() {
     }
 }
 
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn copy_to_and_from() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let local = dir.path().join("local");
+    let content: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    std::fs::write(&local, &content).unwrap();
+    std::fs::set_permissions(&local, std::fs::Permissions::from_mode(0o750)).unwrap();
+
+    for session in connects().await {
+        let remote = "/tmp/openssh-rust-test-copy";
+
+        let n = session.copy_to(&local, remote).await.unwrap();
+        assert_eq!(n, content.len() as u64);
+
+        let output = session
+            .shell(format!("ls -l {remote} | cut -c 1-10; wc -c < {remote}"))
+            .output()
+            .await
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut lines = stdout.lines();
+        assert_eq!(lines.next(), Some("-rwxr-x---"));
+        assert_eq!(lines.next().unwrap().trim(), content.len().to_string());
+
+        let downloaded = dir.path().join("downloaded");
+        let n = session.copy_from(remote, &downloaded).await.unwrap();
+        assert_eq!(n, content.len() as u64);
+        assert_eq!(std::fs::read(&downloaded).unwrap(), content);
+        let mode = std::fs::metadata(&downloaded).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o750);
+
+        let err = session
+            .copy_from("/tmp/openssh-rust-test-no-such-file", &downloaded)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Remote(_)), "{:?}", err);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn sync_dir() {
+    let dir = tempdir().unwrap();
+    let local = dir.path();
+    std::fs::create_dir_all(local.join("sub/target")).unwrap();
+    std::fs::write(local.join("file"), b"file").unwrap();
+    std::fs::write(local.join("sub/file"), b"sub/file").unwrap();
+    std::fs::write(local.join("sub/target/ignored"), b"").unwrap();
+    std::os::unix::fs::symlink("sub/file", local.join("link")).unwrap();
+
+    for session in connects().await {
+        let remote = "/tmp/openssh-rust-test-sync";
+        session
+            .command("rm")
+            .args(["-rf", remote])
+            .status()
+            .await
+            .unwrap();
+
+        let options = SyncOptions {
+            delete: true,
+            exclude: vec!["target".to_owned()],
+            ..Default::default()
+        };
+
+        let report = session
+            .sync_dir(local, remote, options.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            report.created,
+            ["file", "link", "sub", "sub/file"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+        assert!(report.updated.is_empty());
+        assert!(report.deleted.is_empty());
+
+        let output = session
+            .shell(format!("cd {remote} && cat sub/file && readlink link"))
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"sub/filesub/file\n");
+
+        // Nothing changed, so nothing is transferred.
+        let report = session
+            .sync_dir(local, remote, options.clone())
+            .await
+            .unwrap();
+        assert_eq!(report, SyncReport::default());
+
+        session
+            .shell(format!("echo stale > {remote}/stale"))
+            .status()
+            .await
+            .unwrap();
+        std::fs::write(local.join("file"), b"changed").unwrap();
+
+        let report = session
+            .sync_dir(local, remote, options.clone())
+            .await
+            .unwrap();
+        assert_eq!(report.updated, [PathBuf::from("file")]);
+        assert_eq!(report.deleted, [PathBuf::from("stale")]);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[cfg(feature = "sftp")]
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn sftp() {
+    let dir = tempdir().unwrap();
+    let local = dir.path().join("local");
+    let content: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    std::fs::write(&local, &content).unwrap();
+
+    for session in connects().await {
+        let sftp = session.sftp().await.unwrap();
+
+        let remote_dir = "/tmp/openssh-rust-test-sftp";
+        session
+            .command("rm")
+            .args(["-rf", remote_dir])
+            .status()
+            .await
+            .unwrap();
+        session
+            .command("mkdir")
+            .arg(remote_dir)
+            .status()
+            .await
+            .unwrap();
+        let remote = format!("{remote_dir}/file");
+
+        let mut last = None;
+        let n = sftp
+            .upload_with_progress(&local, &remote, |progress| last = Some(progress))
+            .await
+            .unwrap();
+        assert_eq!(n, content.len() as u64);
+        let last = last.unwrap();
+        assert_eq!(last.transferred, n);
+        assert_eq!(last.total, Some(n));
+
+        assert_eq!(sftp.metadata(&remote).await.unwrap().len(), Some(n));
+        assert_eq!(sftp.read_to_vec(&remote).await.unwrap(), content);
+
+        let downloaded = dir.path().join("downloaded");
+        assert_eq!(sftp.download(&remote, &downloaded).await.unwrap(), n);
+        assert_eq!(std::fs::read(&downloaded).unwrap(), content);
+
+        let renamed = format!("{remote_dir}/renamed");
+        sftp.write_all(&remote, b"small").await.unwrap();
+        sftp.rename(&remote, &renamed).await.unwrap();
+        assert_eq!(sftp.read_to_vec(&renamed).await.unwrap(), b"small");
+
+        let entries = sftp.read_dir(remote_dir).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].filename(), Path::new("renamed"));
+
+        sftp.remove(&renamed).await.unwrap();
+        sftp.remove(remote_dir).await.unwrap();
+        assert!(matches!(
+            sftp.metadata(remote_dir).await,
+            Err(Error::Sftp(_))
+        ));
+
+        sftp.close().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
 async fn test_read_large_file_bug() {
@@ -1157,3 +2273,177 @@ async fn test_session_to_command() {
         assert!(status.success());
     }
 }
+
+#[tokio::test]
+async fn effective_config() {
+    let dir = tempdir().unwrap();
+    let config_file = dir.path().join("config");
+    std::fs::write(
+        &config_file,
+        "Host alias\n  HostName 192.0.2.7\n  ProxyJump jump.example.com\n",
+    )
+    .unwrap();
+    // ssh ignores identity files that do not exist.
+    let keyfile = dir.path().join("id_test");
+    std::fs::write(&keyfile, "").unwrap();
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .config_file(&config_file)
+        .keyfile(&keyfile)
+        .option("ServerAliveCountMax", "7");
+
+    let config = builder
+        .effective_config("ssh://test-user@alias:2200")
+        .await
+        .unwrap();
+
+    assert_eq!(config.user.as_deref(), Some("test-user"));
+    assert_eq!(config.host_name.as_deref(), Some("192.0.2.7"));
+    assert_eq!(config.port, Some(2200));
+    assert_eq!(config.proxy_jump.as_deref(), Some("jump.example.com"));
+    assert_eq!(config.identity_files, [keyfile]);
+    assert_eq!(config.get("ServerAliveCountMax"), Some("7"));
+    assert_eq!(config.get("BatchMode"), Some("yes"));
+
+    builder.option("NoSuchOption", "yes");
+    match builder.effective_config("alias").await.unwrap_err() {
+        Error::InvalidOption { keyword, .. } => assert_eq!(keyword, "NoSuchOption"),
+        e => unreachable!("{:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn to_config_file() {
+    let dir = tempdir().unwrap();
+    let keyfile = dir.path().join("id test");
+    std::fs::write(&keyfile, "").unwrap();
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Strict)
+        .port(2200)
+        .keyfile(&keyfile)
+        .jump_hosts(["jump1.example.com", "jump2.example.com"])
+        .compression(true)
+        .option("ServerAliveCountMax", "7");
+
+    let destination = "ssh://test-user@192.0.2.1";
+    let path = dir.path().join("config");
+    std::fs::write(&path, builder.to_config_file(destination).to_string()).unwrap();
+
+    // ssh reads the generated file the same way as the options of the builder.
+    let expected = builder.effective_config(destination).await.unwrap();
+    // StrictHostKeyChecking is always passed on the command line.
+    let config = SessionBuilder::default()
+        .known_hosts_check(KnownHosts::Strict)
+        .config_file(&path)
+        .effective_config("192.0.2.1")
+        .await
+        .unwrap();
+    for keyword in [
+        "User",
+        "Port",
+        "StrictHostKeyChecking",
+        "IdentitiesOnly",
+        "IdentityFile",
+        "ProxyJump",
+        "Compression",
+        "ServerAliveCountMax",
+    ] {
+        assert_eq!(config.get(keyword), expected.get(keyword), "{}", keyword);
+    }
+
+    let resolved = ssh_config::ConfigFile::load(&path)
+        .unwrap()
+        .resolve("192.0.2.1");
+    assert_eq!(resolved.user.as_deref(), Some("test-user"));
+    assert_eq!(resolved.port, Some(2200));
+    assert_eq!(resolved.identity_files, [keyfile]);
+    assert_eq!(
+        resolved.proxy_jump.as_deref(),
+        Some("jump1.example.com,jump2.example.com")
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn pinned_host_keys() {
+    let config = SessionBuilder::default()
+        .effective_config(&addr())
+        .await
+        .unwrap();
+    let scanned = process::Command::new("ssh-keyscan")
+        .arg("-p")
+        .arg(config.port.unwrap().to_string())
+        .arg(config.host_name.unwrap())
+        .output()
+        .unwrap();
+    assert!(scanned.status.success());
+    let scanned = String::from_utf8(scanned.stdout).unwrap();
+
+    // Lines of the form `host algorithm key`.
+    let keys: Vec<HostKey> = scanned
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| HostKey::PublicKey(line.split_once(' ').unwrap().1.to_owned()))
+        .collect();
+    assert!(!keys.is_empty());
+
+    let dir = tempdir().unwrap();
+    let scanned_file = dir.path().join("scanned");
+    std::fs::write(&scanned_file, &scanned).unwrap();
+    let fingerprints = process::Command::new("ssh-keygen")
+        .arg("-l")
+        .arg("-f")
+        .arg(&scanned_file)
+        .output()
+        .unwrap();
+    assert!(fingerprints.status.success());
+    // Lines of the form `bits fingerprint host (type)`.
+    let fingerprints: Vec<HostKey> = String::from_utf8(fingerprints.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| HostKey::Fingerprint(line.split(' ').nth(1).unwrap().to_owned()))
+        .collect();
+
+    for pinned in [keys, fingerprints] {
+        let mut builder = SessionBuilder::default();
+        builder.known_hosts_check(KnownHosts::Pinned(pinned));
+        for session in session_builder_connect(builder, &addr()).await {
+            session.check().await.unwrap();
+            session.close().await.unwrap();
+        }
+    }
+
+    let mut builder = SessionBuilder::default();
+    builder.known_hosts_check(KnownHosts::Pinned(vec![HostKey::PublicKey(
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHNpXN6/WYkv8TKcQkcnptPRr9tR7zNSLIvLtFARlS4u".into(),
+    )]));
+
+    #[cfg(feature = "process-mux")]
+    {
+        let err = builder.connect(&addr()).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::ConnectFailed(ConnectError::HostKeyMismatch { .. })
+            ),
+            "{:?}",
+            err
+        );
+    }
+
+    #[cfg(feature = "native-mux")]
+    {
+        let err = builder.connect_mux(&addr()).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::ConnectFailed(ConnectError::HostKeyMismatch { .. })
+            ),
+            "{:?}",
+            err
+        );
+    }
+}